@@ -0,0 +1,256 @@
+use eframe::egui::{Key, KeyboardShortcut, Modifiers};
+
+/// Команда редактора. Через неё проходят пункты меню и горячие клавиши.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    NewFile,
+    OpenFile,
+    Save,
+    SaveAs,
+    ReopenWithEncoding,
+    SaveWithEncoding,
+    ConvertLineEndings,
+    Print,
+    CloseTab,
+    NextTab,
+    PrevTab,
+    Quit,
+    Undo,
+    Redo,
+    AddNextOccurrence,
+    DuplicateLines,
+    DeleteLines,
+    MoveLinesUp,
+    MoveLinesDown,
+    JoinLines,
+    SortLines,
+    SortLinesDescending,
+    SortLinesNumeric,
+    SortLinesCaseInsensitive,
+    RemoveDuplicateLines,
+    ReverseLines,
+    TrimTrailingWhitespace,
+    SetIndentation,
+    IndentToSpaces,
+    IndentToTabs,
+    Find,
+    FindNext,
+    FindPrevious,
+    FindInFolder,
+    ToggleUndoTree,
+    ToggleLog,
+    CommandPalette,
+    GoToLine,
+    GoToSymbol,
+    SetFontSize,
+    SetLanguage,
+}
+
+const CTRL: Modifiers = Modifiers::COMMAND;
+const CTRL_SHIFT: Modifiers = Modifiers::COMMAND.plus(Modifiers::SHIFT);
+
+impl Action {
+    pub const ALL: &[Action] = &[
+        Action::NewFile,
+        Action::OpenFile,
+        Action::Save,
+        Action::SaveAs,
+        Action::ReopenWithEncoding,
+        Action::SaveWithEncoding,
+        Action::ConvertLineEndings,
+        Action::Print,
+        Action::CloseTab,
+        Action::NextTab,
+        Action::PrevTab,
+        Action::Quit,
+        Action::Undo,
+        Action::Redo,
+        Action::AddNextOccurrence,
+        Action::DuplicateLines,
+        Action::DeleteLines,
+        Action::MoveLinesUp,
+        Action::MoveLinesDown,
+        Action::JoinLines,
+        Action::SortLines,
+        Action::SortLinesDescending,
+        Action::SortLinesNumeric,
+        Action::SortLinesCaseInsensitive,
+        Action::RemoveDuplicateLines,
+        Action::ReverseLines,
+        Action::TrimTrailingWhitespace,
+        Action::SetIndentation,
+        Action::IndentToSpaces,
+        Action::IndentToTabs,
+        Action::Find,
+        Action::FindNext,
+        Action::FindPrevious,
+        Action::FindInFolder,
+        Action::ToggleUndoTree,
+        Action::ToggleLog,
+        Action::CommandPalette,
+        Action::GoToLine,
+        Action::GoToSymbol,
+        Action::SetFontSize,
+        Action::SetLanguage,
+    ];
+
+    /// Имя команды в файле раскладки.
+    pub fn id(self) -> &'static str {
+        match self {
+            Action::NewFile => "file.new",
+            Action::OpenFile => "file.open",
+            Action::Save => "file.save",
+            Action::SaveAs => "file.save_as",
+            Action::ReopenWithEncoding => "file.reopen_with_encoding",
+            Action::SaveWithEncoding => "file.save_with_encoding",
+            Action::ConvertLineEndings => "file.line_endings",
+            Action::Print => "file.print",
+            Action::CloseTab => "tab.close",
+            Action::NextTab => "tab.next",
+            Action::PrevTab => "tab.prev",
+            Action::Quit => "app.quit",
+            Action::Undo => "edit.undo",
+            Action::Redo => "edit.redo",
+            Action::AddNextOccurrence => "edit.add_next_occurrence",
+            Action::DuplicateLines => "edit.duplicate_lines",
+            Action::DeleteLines => "edit.delete_lines",
+            Action::MoveLinesUp => "edit.move_lines_up",
+            Action::MoveLinesDown => "edit.move_lines_down",
+            Action::JoinLines => "edit.join_lines",
+            Action::SortLines => "edit.sort_lines",
+            Action::SortLinesDescending => "edit.sort_lines_descending",
+            Action::SortLinesNumeric => "edit.sort_lines_numeric",
+            Action::SortLinesCaseInsensitive => "edit.sort_lines_case_insensitive",
+            Action::RemoveDuplicateLines => "edit.remove_duplicate_lines",
+            Action::ReverseLines => "edit.reverse_lines",
+            Action::TrimTrailingWhitespace => "edit.trim_trailing_whitespace",
+            Action::SetIndentation => "edit.indentation",
+            Action::IndentToSpaces => "edit.indent_to_spaces",
+            Action::IndentToTabs => "edit.indent_to_tabs",
+            Action::Find => "search.find",
+            Action::FindNext => "search.next",
+            Action::FindPrevious => "search.previous",
+            Action::FindInFolder => "search.in_folder",
+            Action::ToggleUndoTree => "view.undo_tree",
+            Action::ToggleLog => "view.log",
+            Action::CommandPalette => "view.command_palette",
+            Action::GoToLine => "go.line",
+            Action::GoToSymbol => "go.symbol",
+            Action::SetFontSize => "view.font_size",
+            Action::SetLanguage => "view.language",
+        }
+    }
+
+    /// Подпись в меню.
+    pub fn label(self) -> &'static str {
+        match self {
+            Action::NewFile => "Новый",
+            Action::OpenFile => "Открыть...",
+            Action::Save => "Сохранить",
+            Action::SaveAs => "Сохранить как...",
+            Action::ReopenWithEncoding => "Открыть заново в кодировке...",
+            Action::SaveWithEncoding => "Сохранить в кодировке...",
+            Action::ConvertLineEndings => "Окончания строк...",
+            Action::Print => "Печать...",
+            Action::CloseTab => "Закрыть вкладку",
+            Action::NextTab => "Следующая вкладка",
+            Action::PrevTab => "Предыдущая вкладка",
+            Action::Quit => "Выход",
+            Action::Undo => "Отменить (Undo)",
+            Action::Redo => "Повторить (Redo)",
+            Action::AddNextOccurrence => "Добавить следующее вхождение",
+            Action::DuplicateLines => "Дублировать строку",
+            Action::DeleteLines => "Удалить строку",
+            Action::MoveLinesUp => "Переместить строку вверх",
+            Action::MoveLinesDown => "Переместить строку вниз",
+            Action::JoinLines => "Объединить строки",
+            Action::SortLines => "Сортировать строки по возрастанию",
+            Action::SortLinesDescending => "Сортировать строки по убыванию",
+            Action::SortLinesNumeric => "Сортировать строки по числам",
+            Action::SortLinesCaseInsensitive => "Сортировать строки без учёта регистра",
+            Action::RemoveDuplicateLines => "Удалить повторяющиеся строки",
+            Action::ReverseLines => "Обратить порядок строк",
+            Action::TrimTrailingWhitespace => "Удалить пробелы в конце строк",
+            Action::SetIndentation => "Отступ...",
+            Action::IndentToSpaces => "Преобразовать отступы в пробелы",
+            Action::IndentToTabs => "Преобразовать отступы в табуляцию",
+            Action::Find => "Найти / Заменить...",
+            Action::FindNext => "Найти далее",
+            Action::FindPrevious => "Найти ранее",
+            Action::FindInFolder => "Найти в папке...",
+            Action::ToggleUndoTree => "Дерево отмены",
+            Action::ToggleLog => "Журнал сообщений",
+            Action::CommandPalette => "Палитра команд...",
+            Action::GoToLine => "Перейти к строке...",
+            Action::GoToSymbol => "Перейти к символу...",
+            Action::SetFontSize => "Размер шрифта...",
+            Action::SetLanguage => "Подсветка синтаксиса...",
+        }
+    }
+
+    /// Имя в палитре команд — подпись меню без многоточия.
+    pub fn name(self) -> &'static str {
+        self.label().trim_end_matches("...")
+    }
+
+    /// Что команда принимает аргументом (после двоеточия в палитре).
+    pub fn argument_hint(self) -> Option<&'static str> {
+        match self {
+            Action::GoToLine => Some("строка[:столбец]"),
+            Action::SetFontSize => Some("10–30"),
+            Action::SetLanguage => Some("язык"),
+            Action::SetIndentation => Some("tab или число пробелов"),
+            Action::ReopenWithEncoding | Action::SaveWithEncoding => Some("кодировка"),
+            Action::ConvertLineEndings => Some("LF, CRLF или CR"),
+            _ => None,
+        }
+    }
+
+    /// Сочетания по умолчанию; первое показывается в меню.
+    pub fn default_shortcuts(self) -> Vec<KeyboardShortcut> {
+        let shortcut = KeyboardShortcut::new;
+        match self {
+            Action::NewFile => vec![shortcut(CTRL, Key::N)],
+            Action::OpenFile => vec![shortcut(CTRL, Key::O)],
+            Action::Save => vec![shortcut(CTRL, Key::S)],
+            Action::SaveAs => vec![shortcut(CTRL_SHIFT, Key::S)],
+            Action::Print => vec![shortcut(CTRL, Key::P)],
+            Action::CloseTab => vec![shortcut(CTRL, Key::W)],
+            Action::NextTab => vec![shortcut(CTRL, Key::Tab)],
+            Action::PrevTab => vec![shortcut(CTRL_SHIFT, Key::Tab)],
+            Action::Quit => vec![shortcut(CTRL, Key::Q)],
+            Action::Undo => vec![shortcut(CTRL, Key::Z)],
+            Action::Redo => vec![shortcut(CTRL_SHIFT, Key::Z), shortcut(CTRL, Key::Y)],
+            Action::AddNextOccurrence => vec![shortcut(CTRL, Key::D)],
+            Action::DuplicateLines => vec![shortcut(CTRL_SHIFT, Key::D)],
+            Action::DeleteLines => vec![shortcut(CTRL_SHIFT, Key::K)],
+            Action::MoveLinesUp => vec![shortcut(Modifiers::ALT, Key::ArrowUp)],
+            Action::MoveLinesDown => vec![shortcut(Modifiers::ALT, Key::ArrowDown)],
+            Action::JoinLines => vec![shortcut(CTRL, Key::J)],
+            Action::Find => vec![shortcut(CTRL, Key::F)],
+            Action::FindNext => vec![shortcut(Modifiers::NONE, Key::F3)],
+            Action::FindPrevious => vec![shortcut(Modifiers::SHIFT, Key::F3)],
+            Action::FindInFolder => vec![shortcut(CTRL_SHIFT, Key::F)],
+            Action::CommandPalette => vec![shortcut(CTRL_SHIFT, Key::P)],
+            Action::GoToLine => vec![shortcut(CTRL, Key::G)],
+            Action::GoToSymbol => vec![shortcut(CTRL_SHIFT, Key::O)],
+            Action::ToggleUndoTree
+            | Action::ToggleLog
+            | Action::SetFontSize
+            | Action::SetLanguage
+            | Action::SetIndentation
+            | Action::IndentToSpaces
+            | Action::IndentToTabs
+            | Action::ReopenWithEncoding
+            | Action::SaveWithEncoding
+            | Action::ConvertLineEndings
+            | Action::SortLines
+            | Action::SortLinesDescending
+            | Action::SortLinesNumeric
+            | Action::SortLinesCaseInsensitive
+            | Action::RemoveDuplicateLines
+            | Action::ReverseLines
+            | Action::TrimTrailingWhitespace => Vec::new(),
+        }
+    }
+}
@@ -1,9 +1,129 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
 use eframe::egui;
 use eframe::egui::Color32;
 
-use crate::document::Document;
+use crate::actions::Action;
+use crate::document::{self, DiskVersion, Document, Selection};
+use crate::encoding::TextEncoding;
+use crate::file_io::BackupMode;
+use crate::folder_search::FolderSearch;
+use crate::gutter::{self, LineNumbers};
+use crate::history;
+use crate::history_store;
+use crate::indent::Indent;
+use crate::keymap::Keymap;
+use crate::line_commands::{LineCommand, SortOrder};
+use crate::line_ending::LineEnding;
+use crate::multi_cursor;
+use crate::notifications::{FollowUp, Notifications, time_ago};
+use crate::palette::Palette;
+use crate::search::{self, Query, SearchOptions, SearchScope};
+use crate::session::{self, Session};
+use crate::swap;
+use crate::symbols::{self, SymbolPicker};
+use crate::syntax::{self, Language};
+use crate::text_buffer::DocumentBuffer;
+
+/// Как часто сверяем открытые файлы с диском.
+const DISK_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Пока документ правят, совпадения поиска пересчитываются только после
+/// такой паузы: поиск идёт по всему тексту, на каждую клавишу это дорого.
+const RECOUNT_DELAY: Duration = Duration::from_millis(300);
+
+/// Сколько строк сверх видимых отдаём виджету в больших файлах.
+const VIEW_MARGIN_LINES: usize = 50;
+
+/// Ключ кэша раскладки текста: пока он не изменился, galley переиспользуется.
+#[derive(Clone, PartialEq)]
+struct GalleyKey {
+    doc_id: usize,
+    revision: u64,
+    view: Range<usize>,
+    wrap_width: u32,
+    font_size: u32,
+    text_color: Color32,
+    /// Подсветка поиска: поколение совпадений и текущее совпадение.
+    search: Option<(u64, Option<usize>)>,
+    language: Language,
+    dark_mode: bool,
+}
+
+/// Файл изменили на диске, пока в редакторе были несохранённые правки.
+struct ExternalChange {
+    doc_id: usize,
+    disk: DiskVersion,
+}
+
+/// Закрытие, которое ждёт ответа про несохранённые правки.
+enum PendingClose {
+    /// Вкладка с документом `doc_id`.
+    Tab(usize),
+    /// Выход: несохранённые документы и отметка «сохранить» у каждого.
+    Quit(Vec<(usize, bool)>),
+}
+
+/// Совпадения поиска в документе, в позициях символов.
+struct Matches {
+    doc_id: usize,
+    revision: u64,
+    pattern: String,
+    options: SearchOptions,
+    /// Поиск в выделении: где именно.
+    scope: Option<Range<usize>>,
+    ranges: Vec<Range<usize>>,
+    /// Меняется при каждом пересчёте — для кэша раскладки.
+    generation: u64,
+    /// Последняя замеченная правка документа (ревизия и когда): пересчёт
+    /// ждёт, пока правки не затихнут.
+    edited: Option<(u64, Instant)>,
+}
+
+/// Собранный образец поиска и то, из чего он собран.
+struct CompiledQuery {
+    pattern: String,
+    options: SearchOptions,
+    query: Result<Query, String>,
+}
+
+/// Замены, которые сделает "Заменить всё", — пока документы не изменились.
+struct ReplacePreview {
+    revisions: Vec<(usize, u64)>,
+    /// Заголовок документа и замены в нём.
+    groups: Vec<(String, Vec<search::Change>)>,
+    total: usize,
+}
+
+/// Строка в панели результатов поиска.
+struct Hit {
+    range: Range<usize>,
+    /// Номер строки, с 1.
+    line: usize,
+    preview: String,
+    /// Байты найденного в `preview`.
+    highlight: Range<usize>,
+}
+
+/// Результаты поиска в одной вкладке.
+struct ResultGroup {
+    doc_id: usize,
+    title: String,
+    revision: u64,
+    hits: Vec<Hit>,
+}
+
+/// Построчное сравнение двух текстов для окна "Сравнение".
+struct DiffView {
+    title: String,
+    lines: Vec<(similar::ChangeTag, String)>,
+}
 
 pub struct TextEditorApp {
     docs: Vec<Document>,
@@ -13,39 +133,219 @@ pub struct TextEditorApp {
     // Поиск / замена
     pub(crate) find_text: String,
     pub(crate) replace_text: String,
-    pub(crate) last_find_count: Option<usize>,
     pub(crate) last_replace_count: Option<usize>,
+    search_options: SearchOptions,
+    search_scope: SearchScope,
+    /// Образец пересобирается, только когда его поменяли.
+    compiled_query: Option<CompiledQuery>,
+    search_results: Option<Vec<ResultGroup>>,
+    replace_preview: Option<ReplacePreview>,
+    // Поиск в папке
+    show_folder_search: bool,
+    folder_root: Option<PathBuf>,
+    folder_search: Option<FolderSearch>,
+    matches: Option<Matches>,
+    /// Прокрутить редактор к найденному, не забирая фокус у поля поиска.
+    reveal_match: bool,
 
     // Окно поиска
     show_search_window: bool,
 
+    // Дерево отмены
+    show_undo_tree: bool,
+    diff_view: Option<DiffView>,
+
     // Внешний вид
     pub(crate) font_size: f32,
     pub(crate) text_color: Color32,
 
-    // Автосохранение
+    // Автосохранение в файлы подкачки
     autosave_interval: Duration,
     last_autosave: Instant,
+    /// Ревизия, записанная в файл подкачки, по номерам документов.
+    swapped: HashMap<usize, u64>,
+    /// Файлы подкачки прошлых запусков, которые ждут решения пользователя.
+    recovery: Vec<swap::Leftover>,
+
+    // Резервные копии при сохранении
+    backup_mode: BackupMode,
+    line_numbers: LineNumbers,
+
+    /// Сообщения об ошибках и событиях.
+    notifications: Notifications,
+
+    // Изменения файлов другими программами
+    last_disk_check: Instant,
+    external_changes: Vec<ExternalChange>,
+
+    // Закрытие вкладок и выход
+    pending_close: Option<PendingClose>,
+    /// Пользователь ответил на вопрос о несохранённом — окно можно закрыть.
+    quit_confirmed: bool,
+
+    // История отмены
+    undo_budget_mb: u32,
+
+    // Горячие клавиши и палитра команд
+    keymap: Keymap,
+    palette: Palette,
+    symbol_picker: SymbolPicker,
+
+    // Редактор
+    galley_cache: Option<(GalleyKey, Arc<egui::Galley>)>,
+    span_cache: syntax::SpanCache,
+    editor_viewport: (usize, egui::Rect),
+    /// Прокрутка редактора по документам — для сессии.
+    scroll_offsets: HashMap<usize, egui::Vec2>,
+    /// Прокрутка из прошлой сессии, которую ещё не применили.
+    pending_scroll: HashMap<usize, egui::Vec2>,
+    /// Прокрутить редактор к курсору и отдать ему фокус на следующем кадре.
+    reveal_cursor: bool,
+    /// Начало прямоугольного выделения (Alt+перетаскивание): строка и x.
+    block_anchor: Option<(usize, f32)>,
 }
 
 impl TextEditorApp {
-    pub fn new(_cc: &eframe::CreationContext<'_>) -> Self {
-        let mut docs = Vec::new();
-        docs.push(Document::new_untitled(1));
+    pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
+        let docs = vec![Document::new_untitled(1)];
 
-        Self {
+        let mut app = Self {
             docs,
             active_doc: 0,
             next_doc_id: 2,
             find_text: String::new(),
             replace_text: String::new(),
-            last_find_count: None,
             last_replace_count: None,
+            search_options: SearchOptions::default(),
+            search_scope: SearchScope::default(),
+            compiled_query: None,
+            search_results: None,
+            replace_preview: None,
+            show_folder_search: false,
+            folder_root: None,
+            folder_search: None,
+            matches: None,
+            reveal_match: false,
             show_search_window: false,
+            show_undo_tree: false,
+            diff_view: None,
             font_size: 16.0,
             text_color: Color32::from_rgb(230, 230, 230),
             autosave_interval: Duration::from_secs(60),
             last_autosave: Instant::now(),
+            swapped: HashMap::new(),
+            recovery: swap::leftovers(),
+            backup_mode: BackupMode::None,
+            line_numbers: LineNumbers::default(),
+            notifications: Notifications::default(),
+            last_disk_check: Instant::now(),
+            external_changes: Vec::new(),
+            pending_close: None,
+            quit_confirmed: false,
+            undo_budget_mb: (history::DEFAULT_BUDGET_BYTES / (1024 * 1024)) as u32,
+            keymap: Keymap::load(),
+            palette: Palette::default(),
+            symbol_picker: SymbolPicker::default(),
+            galley_cache: None,
+            span_cache: syntax::SpanCache::default(),
+            editor_viewport: (0, egui::Rect::NOTHING),
+            scroll_offsets: HashMap::new(),
+            pending_scroll: HashMap::new(),
+            reveal_cursor: false,
+            block_anchor: None,
+        };
+        if let Some(session) = session::load(cc.storage) {
+            app.restore_session(session);
+        }
+        app
+    }
+
+    /// Вкладки и настройки прошлого запуска. Файлы, которые не открылись,
+    /// пропускаем.
+    fn restore_session(&mut self, session: Session) {
+        self.font_size = session.font_size.clamp(10.0, 30.0);
+        self.text_color = session.text_color;
+        self.autosave_interval = Duration::from_secs(session.autosave_secs.clamp(10, 600));
+        self.undo_budget_mb = session.undo_budget_mb.clamp(1, 1024);
+        self.backup_mode = session.backup_mode;
+        self.line_numbers = session.line_numbers;
+        self.find_text = session.find_text;
+        self.replace_text = session.replace_text;
+        self.search_options = session.search_options;
+        self.search_scope = session.search_scope;
+
+        self.docs.clear();
+        self.next_doc_id = 1;
+        let mut active = 0;
+        for (i, tab) in session.tabs.into_iter().enumerate() {
+            let doc = match tab.path {
+                Some(path) => {
+                    match Document::from_file(self.next_doc_id, path.clone(), tab.encoding) {
+                        Ok(doc) => doc,
+                        Err(err) => {
+                            self.notifications.warning(format!(
+                                "Не удалось открыть {} из прошлой сессии: {err}",
+                                path.display()
+                            ));
+                            continue;
+                        }
+                    }
+                }
+                None => Document::untitled_with_text(
+                    self.next_doc_id,
+                    tab.text.as_deref().unwrap_or_default(),
+                ),
+            };
+            self.open_document(doc);
+            let doc = self.current_doc_mut();
+            doc.set_selection(tab.selection);
+            if let Some(language) = tab.language {
+                doc.set_language(language);
+            }
+            let id = doc.id;
+            self.pending_scroll.insert(id, tab.scroll);
+            if i <= session.active_tab {
+                active = self.docs.len() - 1;
+            }
+        }
+
+        if self.docs.is_empty() {
+            self.docs.push(Document::new_untitled(1));
+            self.next_doc_id = 2;
+        }
+        self.active_doc = active;
+    }
+
+    /// Текущее состояние для сохранения сессии.
+    fn session(&self) -> Session {
+        Session {
+            tabs: self
+                .docs
+                .iter()
+                .map(|doc| session::Tab {
+                    path: doc.path.clone(),
+                    text: doc.path.is_none().then(|| doc.text().to_string()),
+                    encoding: doc.path.is_some().then(|| doc.encoding()),
+                    language: doc.language_override(),
+                    selection: doc.selection(),
+                    scroll: self
+                        .scroll_offsets
+                        .get(&doc.id)
+                        .copied()
+                        .unwrap_or_default(),
+                })
+                .collect(),
+            active_tab: self.active_doc,
+            font_size: self.font_size,
+            text_color: self.text_color,
+            autosave_secs: self.autosave_interval.as_secs(),
+            undo_budget_mb: self.undo_budget_mb,
+            backup_mode: self.backup_mode,
+            line_numbers: self.line_numbers,
+            find_text: self.find_text.clone(),
+            replace_text: self.replace_text.clone(),
+            search_options: self.search_options,
+            search_scope: self.search_scope,
         }
     }
 
@@ -57,129 +357,770 @@ impl TextEditorApp {
         &mut self.docs[self.active_doc]
     }
 
-    /// Автосохранение всех документов.
-    ///
-    /// - Если у документа есть путь (`path`), сохраняем в этот файл.
-    /// - Если путь отсутствует (Безымянный), делаем autosave_*.txt рядом с бинарником.
+    fn undo_budget_bytes(&self) -> usize {
+        self.undo_budget_mb as usize * 1024 * 1024
+    }
+
+    /// Добавляет документ новой вкладкой и делает её активной.
+    fn open_document(&mut self, mut doc: Document) {
+        doc.set_undo_budget(self.undo_budget_bytes());
+        if let Err(err) = history_store::restore(&mut doc) {
+            self.notifications.warning(format!(
+                "Не удалось загрузить историю «{}»: {err}",
+                doc.title
+            ));
+        }
+        self.docs.push(doc);
+        self.active_doc = self.docs.len() - 1;
+        self.next_doc_id += 1;
+    }
+
+    /// Открывает файл во вкладке, а если он уже открыт — переключается на неё.
+    fn open_path(&mut self, path: PathBuf) -> bool {
+        // Один и тот же файл может прийти под разными путями: `./`, ссылки.
+        let canonical = |path: &Path| path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        let wanted = canonical(&path);
+        if let Some(i) = self
+            .docs
+            .iter()
+            .position(|doc| doc.path.as_deref().map(canonical).as_ref() == Some(&wanted))
+        {
+            self.active_doc = i;
+            return true;
+        }
+        match Document::from_file(self.next_doc_id, path.clone(), None) {
+            Ok(doc) => {
+                self.open_document(doc);
+                true
+            }
+            Err(err) => {
+                self.notifications
+                    .error(format!("Не удалось открыть {}: {err}", path.display()));
+                false
+            }
+        }
+    }
+
+    /// Автосохранение: текст изменённых документов пишем в файлы подкачки.
+    /// Сами файлы документов сохраняет только пользователь.
     fn handle_autosave(&mut self) {
-        if self.last_autosave.elapsed() >= self.autosave_interval {
-            for doc in &mut self.docs {
-                if !doc.dirty {
-                    continue;
+        if self.last_autosave.elapsed() < self.autosave_interval {
+            return;
+        }
+
+        for doc in &self.docs {
+            if doc.dirty {
+                write_swap(doc, &mut self.swapped, &mut self.notifications);
+            } else if self.swapped.remove(&doc.id).is_some() {
+                remove_swap(doc.id, &mut self.notifications);
+            }
+        }
+
+        self.last_autosave = Instant::now();
+    }
+
+    /// Сверяет открытые файлы с диском. Документы без правок просто
+    /// перечитываем, для остальных спрашиваем пользователя.
+    fn check_external_changes(&mut self) {
+        if self.last_disk_check.elapsed() < DISK_CHECK_INTERVAL {
+            return;
+        }
+        self.last_disk_check = Instant::now();
+
+        for doc in &mut self.docs {
+            if self.external_changes.iter().any(|c| c.doc_id == doc.id) {
+                continue;
+            }
+            // Файл удалён или недоступен — ничего не делаем, при сохранении он появится снова.
+            let Ok(Some(disk)) = doc.check_disk() else {
+                continue;
+            };
+            if doc.dirty {
+                self.external_changes.push(ExternalChange {
+                    doc_id: doc.id,
+                    disk,
+                });
+            } else {
+                doc.reload(&disk);
+                store_history(doc, &mut self.notifications);
+                self.notifications
+                    .info(format!("«{}» изменён на диске и перечитан", doc.title));
+            }
+        }
+    }
+
+    /// Вопрос, что делать с файлом, изменённым на диске.
+    fn external_change_window(&mut self, ctx: &egui::Context) {
+        enum Choice {
+            Reload,
+            KeepMine,
+            Merge,
+        }
+        let mut chosen = None;
+        let mut diff_with = None;
+
+        egui::Window::new("Файл изменён на диске")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                for (i, change) in self.external_changes.iter().enumerate() {
+                    let Some(doc) = self.docs.iter().find(|d| d.id == change.doc_id) else {
+                        continue;
+                    };
+                    ui.label(format!(
+                        "«{}» изменили другой программой, а в редакторе есть несохранённые правки.",
+                        doc.title
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Загрузить с диска").clicked() {
+                            chosen = Some((i, Choice::Reload));
+                        }
+                        if ui.button("Оставить мои").clicked() {
+                            chosen = Some((i, Choice::KeepMine));
+                        }
+                        if ui.button("Объединить").clicked() {
+                            chosen = Some((i, Choice::Merge));
+                        }
+                        if ui.button("Сравнить").clicked() {
+                            diff_with = Some(i);
+                        }
+                    });
+                    ui.separator();
                 }
+            });
+
+        if let Some(i) = diff_with {
+            let change = &self.external_changes[i];
+            if let Some(doc) = self.docs.iter().find(|d| d.id == change.doc_id) {
+                self.diff_view = Some(DiffView {
+                    title: format!("{}: мои правки → на диске", doc.title),
+                    lines: diff_lines(&doc.text().to_string(), &change.disk.text),
+                });
+            }
+        }
+
+        let Some((i, choice)) = chosen else {
+            return;
+        };
 
-                if doc.path.is_some() {
-                    // Обычный сохранённый файл — пишем прямо в него
-                    if let Err(err) = doc.save() {
-                        eprintln!("Ошибка автосохранения {:?}: {err}", doc.title);
+        let change = self.external_changes.remove(i);
+        let Some(idx) = self.docs.iter().position(|d| d.id == change.doc_id) else {
+            return;
+        };
+        let doc = &mut self.docs[idx];
+        match choice {
+            Choice::Reload => doc.reload(&change.disk),
+            Choice::KeepMine => doc.accept_disk(&change.disk),
+            Choice::Merge => {
+                let conflicts = doc.merge_with_disk(&change.disk);
+                if conflicts > 0 {
+                    self.notifications.warning(format!(
+                        "«{}»: конфликтов при слиянии — {conflicts}, \
+                         они отмечены <<<<<<< и >>>>>>>",
+                        doc.title
+                    ));
+                    // Показываем первый конфликт.
+                    let text = doc.text().to_string();
+                    if let Some(byte) = text.find("<<<<<<< ") {
+                        let pos = text[..byte].chars().count();
+                        doc.set_selection(Selection {
+                            anchor: pos,
+                            head: pos,
+                        });
+                        self.active_doc = idx;
+                        self.reveal_cursor = true;
                     }
-                } else {
-                    // Безымянный документ — сохраняем во временный autosave-файл
-                    if let Ok(mut dir) = std::env::current_dir() {
-                        let filename = format!("autosave_{}.txt", doc.id);
-                        dir.push(filename);
-                        if let Err(err) = std::fs::write(&dir, &doc.text) {
-                            eprintln!("Ошибка автосохранения в {:?}: {err}", dir);
-                        } else {
-                            // Для автосохранения безымянного файла dirty НЕ сбрасываем,
-                            // чтобы было видно, что он ещё не сохранён "по-настоящему".
-                            println!("Автосохранение безымянного документа в {:?}", dir);
+                }
+            }
+        }
+    }
+
+    /// Документ сохранён или закрыт — его файл подкачки больше не нужен.
+    fn drop_swap(&mut self, doc_id: usize) {
+        if self.swapped.remove(&doc_id).is_some() {
+            remove_swap(doc_id, &mut self.notifications);
+        }
+    }
+
+    /// Открывает документ из файла подкачки: файл с диска плюс несохранённые
+    /// правки поверх, чтобы их можно было отменить.
+    fn recover(&mut self, leftover: swap::Leftover) {
+        let swap = leftover.swap;
+        let existing = swap
+            .path
+            .as_ref()
+            .and_then(|path| self.docs.iter().position(|d| d.path.as_ref() == Some(path)));
+        let opened = match (existing, &swap.path) {
+            (Some(idx), _) => {
+                self.active_doc = idx;
+                true
+            }
+            (None, Some(path)) => match Document::from_file(self.next_doc_id, path.clone(), None) {
+                Ok(doc) => {
+                    self.open_document(doc);
+                    true
+                }
+                Err(err) => {
+                    self.notifications.warning(format!(
+                        "Не удалось открыть {}: {err}. Правки восстановлены в новый документ",
+                        path.display()
+                    ));
+                    false
+                }
+            },
+            (None, None) => false,
+        };
+
+        if opened {
+            self.current_doc_mut().set_text(&swap.text);
+        } else {
+            // Безымянный документ или файла на диске уже нет.
+            self.open_document(Document::untitled_with_text(self.next_doc_id, &swap.text));
+        }
+        if let Err(err) = swap::discard(&leftover.file) {
+            self.notifications.warning(format!(
+                "Не удалось удалить {}: {err}",
+                leftover.file.display()
+            ));
+        }
+    }
+
+    /// Окно восстановления несохранённых правок после сбоя.
+    fn recovery_window(&mut self, ctx: &egui::Context) {
+        enum Choice {
+            Recover,
+            Diff,
+            Discard,
+        }
+        let mut chosen = None;
+        let mut later = false;
+
+        egui::Window::new("Восстановление")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.label("Редактор был закрыт некорректно. Остались несохранённые правки:");
+                ui.separator();
+                for (i, leftover) in self.recovery.iter().enumerate() {
+                    let swap = &leftover.swap;
+                    ui.horizontal(|ui| {
+                        let name = match &swap.path {
+                            Some(path) => path.display().to_string(),
+                            None => swap.title.clone(),
+                        };
+                        ui.label(format!("{name} · {}", time_ago(swap.saved_at)));
+                        if ui.button("Восстановить").clicked() {
+                            chosen = Some((i, Choice::Recover));
                         }
-                    }
+                        if ui.button("Сравнить").clicked() {
+                            chosen = Some((i, Choice::Diff));
+                        }
+                        if ui.button("Удалить").clicked() {
+                            chosen = Some((i, Choice::Discard));
+                        }
+                    });
+                }
+                ui.separator();
+                later = ui.button("Решить позже").clicked();
+            });
+
+        match chosen {
+            Some((i, Choice::Recover)) => {
+                let leftover = self.recovery.remove(i);
+                self.recover(leftover);
+            }
+            Some((i, Choice::Diff)) => {
+                let swap = &self.recovery[i].swap;
+                let on_disk = match &swap.path {
+                    Some(path) => match document::read_text(path) {
+                        Ok(text) => text,
+                        Err(err) => {
+                            self.notifications
+                                .error(format!("Не удалось прочитать {}: {err}", path.display()));
+                            return;
+                        }
+                    },
+                    None => String::new(),
+                };
+                self.diff_view = Some(DiffView {
+                    title: format!("{}: файл на диске → несохранённые правки", swap.title),
+                    lines: diff_lines(&on_disk, &swap.text),
+                });
+            }
+            Some((i, Choice::Discard)) => {
+                let leftover = self.recovery.remove(i);
+                if let Err(err) = swap::discard(&leftover.file) {
+                    self.notifications.warning(format!(
+                        "Не удалось удалить {}: {err}",
+                        leftover.file.display()
+                    ));
                 }
             }
+            None => {}
+        }
+        if later {
+            // Файлы остаются на месте и будут предложены при следующем запуске.
+            self.recovery.clear();
+        }
+    }
+
+    /// Сохраняет текущий документ (в `path`, если он задан).
+    fn save_current(&mut self, path: Option<PathBuf>) {
+        let backup = self.backup_mode;
+        let doc = self.current_doc_mut();
+        let saved = match path {
+            Some(path) => doc.save_as(path, backup),
+            None => doc.save(backup),
+        };
+        self.finish_save(saved);
+    }
 
-            self.last_autosave = Instant::now();
+    /// Сохраняет документ `idx`, делая его текущим; безымянному спрашивает
+    /// путь. Возвращает, остались ли в нём несохранённые правки.
+    fn save_doc(&mut self, idx: usize) -> bool {
+        self.active_doc = idx;
+        if self.current_doc().path.is_some() {
+            self.save_current(None);
+        } else if let Some(path) = rfd::FileDialog::new().save_file() {
+            self.save_current(Some(path));
         }
+        !self.current_doc().dirty
     }
 
-    /// Меню "Файл"
-    fn file_menu(&mut self, ui: &mut egui::Ui, ctx: &egui::Context) {
-        use rfd::FileDialog;
+    /// После удачного сохранения текущего документа пишем историю отмены и
+    /// убираем файл подкачки, после неудачного — сообщаем об ошибке.
+    fn finish_save(&mut self, saved: io::Result<()>) {
+        let doc = &self.docs[self.active_doc];
+        let id = doc.id;
+        match saved {
+            Ok(()) => {
+                store_history(doc, &mut self.notifications);
+                self.drop_swap(id);
+                // Сохранили поверх — вопрос о версии на диске снят.
+                self.external_changes.retain(|change| change.doc_id != id);
+            }
+            Err(err) => self.notifications.write_error(
+                format!("Не удалось сохранить «{}»", doc.title),
+                &err,
+                id,
+            ),
+        }
+    }
+
+    /// Сохраняет текущий документ в другой кодировке. Безымянному
+    /// сначала нужен путь.
+    fn save_with_encoding(&mut self, encoding: TextEncoding) {
+        let mut path = None;
+        if self.current_doc().path.is_none() {
+            path = rfd::FileDialog::new().save_file();
+            if path.is_none() {
+                return;
+            }
+        }
+        let backup = self.backup_mode;
+        let saved = self
+            .current_doc_mut()
+            .save_with_encoding(encoding, path, backup);
+        self.finish_save(saved);
+    }
+
+    /// Выполняет команду — из меню, по горячей клавише или откуда угодно ещё.
+    /// Команды с аргументом без него открывают палитру, чтобы аргумент ввели.
+    fn run_action(&mut self, ctx: &egui::Context, action: Action) {
+        self.palette.record_use(action);
+        match action {
+            Action::NewFile => self.open_document(Document::new_untitled(self.next_doc_id)),
+            Action::OpenFile => {
+                if let Some(path) = rfd::FileDialog::new().pick_file() {
+                    self.open_path(path);
+                }
+            }
+            Action::Save => {
+                self.save_doc(self.active_doc);
+            }
+            Action::SaveAs => {
+                if let Some(path) = rfd::FileDialog::new().save_file() {
+                    self.save_current(Some(path));
+                }
+            }
+            Action::Print => {
+                // TODO: реальная печать (через системную команду или PDF)
+                self.notifications.info("Печать пока не реализована");
+            }
+            Action::CloseTab => self.close_tab(self.active_doc),
+            Action::NextTab => self.active_doc = (self.active_doc + 1) % self.docs.len(),
+            Action::PrevTab => {
+                self.active_doc = (self.active_doc + self.docs.len() - 1) % self.docs.len();
+            }
+            Action::Quit => self.request_quit(ctx),
+            Action::Undo => self.current_doc_mut().undo(),
+            Action::Redo => self.current_doc_mut().redo(),
+            Action::DuplicateLines => self.line_command(LineCommand::Duplicate),
+            Action::DeleteLines => self.line_command(LineCommand::Delete),
+            Action::MoveLinesUp => self.line_command(LineCommand::MoveUp),
+            Action::MoveLinesDown => self.line_command(LineCommand::MoveDown),
+            Action::JoinLines => self.line_command(LineCommand::Join),
+            Action::SortLines => self.line_command(LineCommand::Sort(SortOrder::Ascending)),
+            Action::SortLinesDescending => {
+                self.line_command(LineCommand::Sort(SortOrder::Descending))
+            }
+            Action::SortLinesNumeric => self.line_command(LineCommand::Sort(SortOrder::Numeric)),
+            Action::SortLinesCaseInsensitive => {
+                self.line_command(LineCommand::Sort(SortOrder::CaseInsensitive))
+            }
+            Action::RemoveDuplicateLines => self.line_command(LineCommand::RemoveDuplicates),
+            Action::ReverseLines => self.line_command(LineCommand::Reverse),
+            Action::TrimTrailingWhitespace => {
+                self.line_command(LineCommand::TrimTrailingWhitespace)
+            }
+            Action::IndentToSpaces => self.current_doc_mut().convert_indentation(false),
+            Action::IndentToTabs => self.current_doc_mut().convert_indentation(true),
+            Action::AddNextOccurrence => {
+                self.current_doc_mut().add_next_occurrence();
+                self.reveal_cursor = true;
+            }
+            Action::Find => {
+                self.take_selection_scope();
+                self.show_search_window = true;
+            }
+            Action::FindNext => self.find_next(false),
+            Action::FindPrevious => self.find_next(true),
+            Action::FindInFolder => {
+                if self.folder_root.is_none() {
+                    self.folder_root = rfd::FileDialog::new().pick_folder();
+                }
+                self.show_folder_search = self.folder_root.is_some();
+            }
+            Action::ToggleUndoTree => self.show_undo_tree = !self.show_undo_tree,
+            Action::ToggleLog => self.notifications.show_log = !self.notifications.show_log,
+            Action::CommandPalette => self.palette.open(""),
+            Action::GoToSymbol => {
+                let doc = self.current_doc();
+                let symbols = symbols::symbols(doc.language(), doc.text());
+                self.symbol_picker.open(symbols);
+            }
+            Action::GoToLine
+            | Action::SetFontSize
+            | Action::SetLanguage
+            | Action::SetIndentation
+            | Action::ReopenWithEncoding
+            | Action::SaveWithEncoding
+            | Action::ConvertLineEndings => {
+                self.palette.open(&format!("{}: ", action.name()));
+            }
+        }
+    }
+
+    /// Выполняет команду с аргументом из палитры.
+    fn run_action_with(
+        &mut self,
+        ctx: &egui::Context,
+        action: Action,
+        argument: &str,
+    ) -> Result<(), String> {
+        match action {
+            Action::GoToLine => {
+                let invalid = || format!("«{argument}» — не номер строки");
+                let (line, column) = match argument.split_once(':') {
+                    Some((line, column)) => (line, Some(column)),
+                    None => (argument, None),
+                };
+                let line = line.trim().parse().map_err(|_| invalid())?;
+                let column = column
+                    .map(|column| column.trim().parse().map_err(|_| invalid()))
+                    .transpose()?;
+                self.go_to_line(line, column)
+            }
+            Action::SetFontSize => {
+                let size: f32 = argument
+                    .replace(',', ".")
+                    .parse()
+                    .map_err(|_| format!("«{argument}» — не число"))?;
+                if !(10.0..=30.0).contains(&size) {
+                    return Err("Размер шрифта должен быть от 10 до 30".to_string());
+                }
+                self.palette.record_use(action);
+                self.font_size = size;
+                Ok(())
+            }
+            Action::ReopenWithEncoding | Action::SaveWithEncoding => {
+                let encoding = TextEncoding::from_name(argument)
+                    .ok_or_else(|| format!("Неизвестная кодировка «{argument}»"))?;
+                self.palette.record_use(action);
+                if action == Action::ReopenWithEncoding {
+                    self.current_doc_mut()
+                        .reopen_with_encoding(encoding)
+                        .map_err(|err| format!("Не удалось открыть заново: {err}"))
+                } else {
+                    self.save_with_encoding(encoding);
+                    Ok(())
+                }
+            }
+            Action::ConvertLineEndings => {
+                let ending = LineEnding::from_name(argument)
+                    .ok_or_else(|| format!("«{argument}» — не LF, CRLF или CR"))?;
+                self.palette.record_use(action);
+                self.current_doc_mut().set_line_ending(ending);
+                Ok(())
+            }
+            Action::SetLanguage => {
+                let language = Language::from_name(argument)
+                    .ok_or_else(|| format!("Нет подсветки для «{argument}»"))?;
+                self.palette.record_use(action);
+                self.current_doc_mut().set_language(language);
+                Ok(())
+            }
+            Action::SetIndentation => {
+                let indent = Indent::from_name(argument).ok_or_else(|| {
+                    format!("«{argument}» — не tab и не число пробелов от 1 до 8")
+                })?;
+                self.palette.record_use(action);
+                self.current_doc_mut().set_indent(indent);
+                Ok(())
+            }
+            _ => {
+                self.run_action(ctx, action);
+                Ok(())
+            }
+        }
+    }
+
+    /// Ставит курсор на строку `line` и столбец `column` (с единицы; без
+    /// столбца — в начало строки) и прокручивает к нему. Столбец за концом
+    /// строки — конец строки.
+    fn go_to_line(&mut self, line: usize, column: Option<usize>) -> Result<(), String> {
+        let lines = self.current_doc().text().len_lines();
+        if line == 0 || line > lines {
+            return Err(format!("Номер строки должен быть от 1 до {lines}"));
+        }
+        if column == Some(0) {
+            return Err("Столбцы считаются с 1".to_string());
+        }
+        self.palette.record_use(Action::GoToLine);
+        self.go_to(line - 1, column.map_or(0, |column| column - 1));
+        Ok(())
+    }
+
+    /// Курсор на строку и столбец (с нуля) текущего документа. Строки
+    /// могли удалить, пока был открыт список символов, — тогда на последнюю.
+    fn go_to(&mut self, line: usize, column: usize) {
+        let doc = self.current_doc_mut();
+        let text = doc.text();
+        let line = line.min(text.len_lines() - 1);
+        let start = text.line_to_char(line);
+        let len = text.line(line).chars().take_while(|&c| c != '\n').count();
+        let pos = start + column.min(len);
+        doc.set_selection(Selection {
+            anchor: pos,
+            head: pos,
+        });
+        self.reveal_cursor = true;
+    }
+
+    /// Команда над строками текущего документа.
+    fn line_command(&mut self, command: LineCommand) {
+        self.current_doc_mut().line_command(command);
+        self.reveal_cursor = true;
+    }
+
+    /// Палитра команд и запуск выбранной в ней команды.
+    fn command_palette(&mut self, ctx: &egui::Context) {
+        let Some(invocation) = self.palette.show(ctx, &self.keymap) else {
+            return;
+        };
+        let result = match &invocation.argument {
+            Some(argument) => self.run_action_with(ctx, invocation.action, argument),
+            None => {
+                self.run_action(ctx, invocation.action);
+                Ok(())
+            }
+        };
+        if let Err(err) = result {
+            self.palette.show_error(err);
+        }
+    }
+
+    /// Пункт меню для команды: подпись и сочетание клавиш из раскладки.
+    fn action_button(&mut self, ui: &mut egui::Ui, action: Action) {
+        let mut button = egui::Button::new(action.label());
+        if let Some(shortcut) = self.keymap.shortcut(action) {
+            button = button.shortcut_text(ui.ctx().format_shortcut(&shortcut));
+        }
+        if ui.add(button).clicked() {
+            ui.close();
+            self.run_action(ui.ctx(), action);
+        }
+    }
 
+    /// Меню "Файл"
+    fn file_menu(&mut self, ui: &mut egui::Ui) {
         ui.menu_button("Файл", |ui| {
-            if ui.button("Новый").clicked() {
-                self.docs.push(Document::new_untitled(self.next_doc_id));
-                self.active_doc = self.docs.len() - 1;
-                self.next_doc_id += 1;
-                ui.close_menu(); // deprecated, но работает
-            }
-
-            if ui.button("Открыть...").clicked() {
-                if let Some(path) = FileDialog::new().pick_file() {
-                    if let Ok(doc) = Document::from_file(self.next_doc_id, path) {
-                        self.docs.push(doc);
-                        self.active_doc = self.docs.len() - 1;
-                        self.next_doc_id += 1;
+            self.action_button(ui, Action::NewFile);
+            self.action_button(ui, Action::OpenFile);
+            self.action_button(ui, Action::Save);
+            self.action_button(ui, Action::SaveAs);
+            self.encoding_menu(ui, Action::ReopenWithEncoding);
+            self.encoding_menu(ui, Action::SaveWithEncoding);
+            self.line_ending_menu(ui);
+            self.action_button(ui, Action::Print);
+
+            ui.separator();
+
+            self.action_button(ui, Action::CloseTab);
+            self.action_button(ui, Action::Quit);
+        });
+    }
+
+    /// Подменю со списком кодировок для команды `action`.
+    fn encoding_menu(&mut self, ui: &mut egui::Ui, action: Action) {
+        ui.menu_button(action.name(), |ui| {
+            for encoding in TextEncoding::ALL {
+                let current = self.current_doc().encoding() == encoding;
+                if ui.selectable_label(current, encoding.label()).clicked() {
+                    if let Err(err) = self.run_action_with(ui.ctx(), action, encoding.label()) {
+                        self.notifications.error(err);
                     }
+                    ui.close();
                 }
-                ui.close_menu();
             }
+        });
+    }
 
-            if ui.button("Сохранить").clicked() {
-                let doc = self.current_doc_mut();
-                if doc.path.is_some() {
-                    let _ = doc.save();
-                } else if let Some(path) = FileDialog::new().save_file() {
-                    let _ = doc.save_as(path);
+    /// Подменю перевода окончаний строк.
+    fn line_ending_menu(&mut self, ui: &mut egui::Ui) {
+        let action = Action::ConvertLineEndings;
+        ui.menu_button(action.name(), |ui| {
+            for ending in LineEnding::ALL {
+                let current = self.current_doc().line_ending() == ending;
+                if ui.selectable_label(current, ending.label()).clicked() {
+                    if let Err(err) = self.run_action_with(ui.ctx(), action, ending.label()) {
+                        self.notifications.error(err);
+                    }
+                    ui.close();
                 }
-                ui.close_menu();
             }
+        });
+    }
 
-            if ui.button("Сохранить как...").clicked() {
-                if let Some(path) = FileDialog::new().save_file() {
-                    let _ = self.current_doc_mut().save_as(path);
+    /// Подменю выбора языка подсветки.
+    fn language_menu(&mut self, ui: &mut egui::Ui) {
+        let action = Action::SetLanguage;
+        ui.menu_button(action.name(), |ui| {
+            for language in Language::ALL {
+                let current = self.current_doc().language() == language;
+                if ui.selectable_label(current, language.label()).clicked() {
+                    if let Err(err) = self.run_action_with(ui.ctx(), action, language.label()) {
+                        self.notifications.error(err);
+                    }
+                    ui.close();
                 }
-                ui.close_menu();
             }
+        });
+    }
 
-            if ui.button("Печать...").clicked() {
-                // TODO: реальная печать (через системную команду или PDF)
-                println!("Печать пока не реализована");
-                ui.close_menu();
+    /// Подменю отступа документа.
+    fn indent_menu(&mut self, ui: &mut egui::Ui) {
+        let action = Action::SetIndentation;
+        ui.menu_button(action.name(), |ui| {
+            for indent in Indent::CHOICES {
+                let current = self.current_doc().indent() == indent;
+                if ui.selectable_label(current, indent.label()).clicked() {
+                    if let Err(err) = self.run_action_with(ui.ctx(), action, &indent.label()) {
+                        self.notifications.error(err);
+                    }
+                    ui.close();
+                }
             }
-
             ui.separator();
+            self.action_button(ui, Action::IndentToSpaces);
+            self.action_button(ui, Action::IndentToTabs);
+        });
+    }
 
-            if ui.button("Выход").clicked() {
-                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
-                ui.close_menu();
-            }
+    /// Подменю команд над строками.
+    fn lines_menu(&mut self, ui: &mut egui::Ui) {
+        ui.menu_button("Строки", |ui| {
+            self.action_button(ui, Action::DuplicateLines);
+            self.action_button(ui, Action::DeleteLines);
+            self.action_button(ui, Action::MoveLinesUp);
+            self.action_button(ui, Action::MoveLinesDown);
+            self.action_button(ui, Action::JoinLines);
+            ui.separator();
+            self.action_button(ui, Action::SortLines);
+            self.action_button(ui, Action::SortLinesDescending);
+            self.action_button(ui, Action::SortLinesNumeric);
+            self.action_button(ui, Action::SortLinesCaseInsensitive);
+            self.action_button(ui, Action::RemoveDuplicateLines);
+            self.action_button(ui, Action::ReverseLines);
+            ui.separator();
+            self.action_button(ui, Action::TrimTrailingWhitespace);
         });
     }
 
     /// Меню "Правка"
     fn edit_menu(&mut self, ui: &mut egui::Ui) {
         ui.menu_button("Правка", |ui| {
-            if ui.button("Отменить (Undo)").clicked() {
-                self.current_doc_mut().undo();
-                ui.close_menu();
-            }
-            if ui.button("Повторить (Redo)").clicked() {
-                self.current_doc_mut().redo();
-                ui.close_menu();
-            }
+            self.action_button(ui, Action::Undo);
+            self.action_button(ui, Action::Redo);
+            ui.separator();
+            self.action_button(ui, Action::AddNextOccurrence);
+            self.lines_menu(ui);
+            ui.separator();
+            self.indent_menu(ui);
         });
     }
 
     /// Меню "Поиск" — только открывает окно поиска/замены
     fn search_menu(&mut self, ui: &mut egui::Ui) {
         ui.menu_button("Поиск", |ui| {
-            if ui.button("Найти / Заменить...").clicked() {
-                self.show_search_window = true;
-                ui.close_menu();
-            }
+            self.action_button(ui, Action::Find);
+            self.action_button(ui, Action::FindNext);
+            self.action_button(ui, Action::FindPrevious);
+            self.action_button(ui, Action::FindInFolder);
+            self.action_button(ui, Action::GoToLine);
+            self.action_button(ui, Action::GoToSymbol);
         });
     }
 
     /// Меню "Вид" — размер шрифта, цвет текста, интервал автосохранения
     fn view_menu(&mut self, ui: &mut egui::Ui) {
         ui.menu_button("Вид", |ui| {
+            self.action_button(ui, Action::CommandPalette);
+            self.language_menu(ui);
+            ui.separator();
+
+            let mut show_undo_tree = self.show_undo_tree;
+            if ui
+                .checkbox(&mut show_undo_tree, Action::ToggleUndoTree.label())
+                .clicked()
+            {
+                self.run_action(ui.ctx(), Action::ToggleUndoTree);
+            }
+
+            let mut show_log = self.notifications.show_log;
+            if ui
+                .checkbox(&mut show_log, Action::ToggleLog.label())
+                .clicked()
+            {
+                self.run_action(ui.ctx(), Action::ToggleLog);
+            }
+
             ui.horizontal(|ui| {
                 ui.label("Размер шрифта:");
                 ui.add(egui::Slider::new(&mut self.font_size, 10.0..=30.0));
             });
 
+            ui.horizontal(|ui| {
+                ui.label("Номера строк:");
+                egui::ComboBox::from_id_salt("line_numbers")
+                    .selected_text(self.line_numbers.label())
+                    .show_ui(ui, |ui| {
+                        for mode in LineNumbers::ALL {
+                            ui.selectable_value(&mut self.line_numbers, mode, mode.label());
+                        }
+                    });
+            });
+
             ui.horizontal(|ui| {
                 ui.label("Цвет текста:");
                 // Встроенный color picker, который нормально работает внутри меню.
@@ -190,23 +1131,51 @@ impl TextEditorApp {
                 );
             });
 
+            self.autosave_setting(ui);
+
             ui.horizontal(|ui| {
-                ui.label("Интервал автосохранения (сек):");
-                let mut secs = self.autosave_interval.as_secs() as u32;
+                ui.label("Память истории отмены (МБ):");
                 if ui
-                    .add(egui::DragValue::new(&mut secs).range(10..=600))
+                    .add(egui::DragValue::new(&mut self.undo_budget_mb).range(1..=1024))
                     .changed()
                 {
-                    self.autosave_interval = Duration::from_secs(secs as u64);
+                    let bytes = self.undo_budget_bytes();
+                    for doc in &mut self.docs {
+                        doc.set_undo_budget(bytes);
+                    }
                 }
             });
+
+            ui.horizontal(|ui| {
+                ui.label("Резервная копия при сохранении:");
+                egui::ComboBox::from_id_salt("backup_mode")
+                    .selected_text(self.backup_mode.label())
+                    .show_ui(ui, |ui| {
+                        for mode in BackupMode::ALL {
+                            ui.selectable_value(&mut self.backup_mode, mode, mode.label());
+                        }
+                    });
+            });
+        });
+    }
+
+    /// Интервал автосохранения — в меню "Вид" и в строке состояния.
+    fn autosave_setting(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Интервал автосохранения (сек):");
+            let mut secs = self.autosave_interval.as_secs() as u32;
+            if ui
+                .add(egui::DragValue::new(&mut secs).range(10..=600))
+                .changed()
+            {
+                self.autosave_interval = Duration::from_secs(secs as u64);
+            }
         });
     }
 
     /// Вкладки/многодокументный интерфейс
     fn tabs_bar(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
-            let len = self.docs.len();
             let active = self.active_doc;
 
             let mut to_close: Option<usize> = None;
@@ -223,7 +1192,7 @@ impl TextEditorApp {
                     new_active = Some(i);
                 }
 
-                if ui.small_button("×").clicked() && len > 1 {
+                if ui.small_button("×").clicked() {
                     to_close = Some(i);
                 }
             }
@@ -233,130 +1202,1673 @@ impl TextEditorApp {
             }
 
             if let Some(idx) = to_close {
-                self.docs.remove(idx);
-                if self.active_doc >= self.docs.len() {
-                    self.active_doc = self.docs.len() - 1;
-                }
+                self.close_tab(idx);
             }
         });
     }
 
-    /// Основное текстовое поле
-    fn editor_area(&mut self, ui: &mut egui::Ui) {
-        // Сначала снимаем настройки в локальные переменные (чтобы не ругался borrow checker)
-        let font_size = self.font_size;
-        let text_color = self.text_color;
-
-        let doc = self.current_doc_mut();
-        let mut text = doc.text.clone();
+    /// Закрывает вкладку, а если в ней несохранённые правки — сначала
+    /// спрашивает. Последнюю вкладку не закрываем.
+    fn close_tab(&mut self, idx: usize) {
+        if self.docs.len() < 2 {
+            return;
+        }
+        if self.docs[idx].dirty {
+            self.pending_close = Some(PendingClose::Tab(self.docs[idx].id));
+        } else {
+            self.remove_tab(idx);
+        }
+    }
 
-        let response = egui::TextEdit::multiline(&mut text)
-            .desired_rows(30)
-            // Настройка шрифта прямо на виджете:
-            .font(egui::FontId::monospace(font_size))
-            // Настройка цвета текста прямо на виджете:
-            .text_color(text_color)
-            .lock_focus(true)
-            .desired_width(f32::INFINITY)
-            .show(ui);
+    fn remove_tab(&mut self, idx: usize) {
+        store_history(&self.docs[idx], &mut self.notifications);
+        let doc = self.docs.remove(idx);
+        self.drop_swap(doc.id);
+        self.external_changes
+            .retain(|change| change.doc_id != doc.id);
+        if self.active_doc >= self.docs.len() {
+            self.active_doc = self.docs.len() - 1;
+        }
+    }
 
-        if response.response.changed() {
-            doc.set_text(text);
+    /// Выход из редактора. Если есть несохранённые документы, сначала
+    /// показываем их списком.
+    fn request_quit(&mut self, ctx: &egui::Context) {
+        let unsaved: Vec<(usize, bool)> = self
+            .docs
+            .iter()
+            .filter(|doc| doc.dirty)
+            // Безымянные документы переживут выход в сессии.
+            .map(|doc| (doc.id, doc.path.is_some()))
+            .collect();
+        if unsaved.is_empty() {
+            self.quit_confirmed = true;
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        } else {
+            self.pending_close = Some(PendingClose::Quit(unsaved));
         }
     }
-}
 
-impl eframe::App for TextEditorApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Верхнее меню
-        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
-            egui::menu::bar(ui, |ui| {
-                self.file_menu(ui, ctx);
-                self.edit_menu(ui);
-                self.search_menu(ui);
-                self.view_menu(ui);
-            });
-        });
+    /// Вопрос «Сохранить / Не сохранять / Отмена» перед закрытием вкладки
+    /// или выходом.
+    fn close_dialog(&mut self, ctx: &egui::Context) {
+        enum Choice {
+            Save,
+            Discard,
+            Cancel,
+        }
+        let Some(pending) = &mut self.pending_close else {
+            return;
+        };
+        let mut choice = None;
 
-        // Центральная область: вкладки и редактор
-        egui::CentralPanel::default().show(ctx, |ui| {
-            self.tabs_bar(ui);
+        let modal = egui::Modal::new(egui::Id::new("close_dialog")).show(ctx, |ui| {
+            ui.set_max_width(420.0);
+            let save_label = match pending {
+                PendingClose::Tab(doc_id) => {
+                    let title = self
+                        .docs
+                        .iter()
+                        .find(|doc| doc.id == *doc_id)
+                        .map_or("", |doc| doc.title.as_str());
+                    ui.heading(format!("Сохранить изменения в «{title}»?"));
+                    ui.label("Если не сохранить, правки будут потеряны.");
+                    "Сохранить"
+                }
+                PendingClose::Quit(unsaved) => {
+                    ui.heading("Есть несохранённые документы");
+                    ui.label("Отмеченные будут сохранены перед выходом:");
+                    ui.separator();
+                    for (doc_id, save) in unsaved.iter_mut() {
+                        let Some(doc) = self.docs.iter().find(|doc| doc.id == *doc_id) else {
+                            continue;
+                        };
+                        let name = match &doc.path {
+                            Some(path) => path.display().to_string(),
+                            None => format!("{} (останется в сессии)", doc.title),
+                        };
+                        ui.checkbox(save, name);
+                    }
+                    "Сохранить отмеченные"
+                }
+            };
             ui.separator();
-            self.editor_area(ui);
+            ui.horizontal(|ui| {
+                if ui.button(save_label).clicked() {
+                    choice = Some(Choice::Save);
+                }
+                if ui.button("Не сохранять").clicked() {
+                    choice = Some(Choice::Discard);
+                }
+                if ui.button("Отмена").clicked() {
+                    choice = Some(Choice::Cancel);
+                }
+            });
         });
+        if modal.should_close() && choice.is_none() {
+            choice = Some(Choice::Cancel);
+        }
 
-        // Окно поиска / замены (отдельное, не меню)
-        if self.show_search_window {
-            egui::Window::new("Поиск и замена")
-                .collapsible(false)
-                .resizable(false)
-                .show(ctx, |ui| {
-                    // --- Найти ---
-                    ui.horizontal(|ui| {
-                        ui.label("Найти:");
-                        ui.text_edit_singleline(&mut self.find_text);
-                    });
+        let Some(choice) = choice else {
+            return;
+        };
+        let Some(pending) = self.pending_close.take() else {
+            return;
+        };
+        match (pending, choice) {
+            (_, Choice::Cancel) => {}
+            (PendingClose::Tab(doc_id), choice) => {
+                let Some(idx) = self.docs.iter().position(|doc| doc.id == doc_id) else {
+                    return;
+                };
+                // Не удалось сохранить или отказались от выбора пути — вкладка остаётся.
+                if matches!(choice, Choice::Discard) || self.save_doc(idx) {
+                    self.remove_tab(idx);
+                }
+            }
+            (PendingClose::Quit(unsaved), choice) => {
+                if matches!(choice, Choice::Save) {
+                    for (doc_id, _) in unsaved.iter().filter(|(_, save)| *save) {
+                        let Some(idx) = self.docs.iter().position(|doc| doc.id == *doc_id) else {
+                            continue;
+                        };
+                        if !self.save_doc(idx) {
+                            return;
+                        }
+                    }
+                }
+                // От правок, которые не стали сохранять, отказались явно.
+                let save_chosen = matches!(choice, Choice::Save);
+                for (doc_id, save) in unsaved {
+                    if !(save_chosen && save) {
+                        self.drop_swap(doc_id);
+                    }
+                }
+                self.quit_confirmed = true;
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+        }
+    }
 
-                    ui.horizontal(|ui| {
-                        if ui.button("Найти").clicked() {
-                            let needle = self.find_text.clone();
-
-                            if needle.is_empty() {
-                                self.last_find_count = Some(0);
-                            } else {
-                                // Берём копию текста в отдельном блоке, чтобы ограничить заимствование
-                                let text = {
-                                    let doc = self.current_doc();
-                                    doc.text.clone()
-                                };
-                                let count = text.matches(&needle).count();
-                                self.last_find_count = Some(count);
-                            }
+    /// Выделение, в котором ищем, если оно взято в текущем документе.
+    fn selection_scope(&self) -> Option<Range<usize>> {
+        self.current_doc().search_scope()
+    }
+
+    /// Запоминает текущее выделение как область поиска. Найденное
+    /// совпадение областью не считается.
+    fn take_selection_scope(&mut self) {
+        let range = self.current_doc().selection().range();
+        if !range.is_empty() && self.current_match().is_none() {
+            self.current_doc_mut().set_search_scope(range);
+        }
+    }
+
+    /// Документы (по индексу) и диапазоны символов, в которых ищем и заменяем.
+    fn scope_targets(&self) -> Vec<(usize, Range<usize>)> {
+        let whole = |i: usize| (i, 0..self.docs[i].text().len_chars());
+        match self.search_scope {
+            SearchScope::Document => vec![whole(self.active_doc)],
+            SearchScope::Selection => self
+                .selection_scope()
+                .map(|range| (self.active_doc, range))
+                .into_iter()
+                .collect(),
+            SearchScope::AllDocuments => (0..self.docs.len()).map(whole).collect(),
+        }
+    }
+
+    fn doc_revisions(&self) -> Vec<(usize, u64)> {
+        self.docs
+            .iter()
+            .map(|doc| (doc.id, doc.revision()))
+            .collect()
+    }
+
+    /// Собирает все совпадения области поиска для панели результатов.
+    fn find_all(&mut self, query: &Query) {
+        let groups = self
+            .scope_targets()
+            .into_iter()
+            .filter_map(|(i, range)| {
+                let doc = &self.docs[i];
+                let text = doc.text();
+                let hits: Vec<Hit> = doc
+                    .find_matches(query, range)
+                    .into_iter()
+                    .map(|found| {
+                        let line = text.char_to_line(found.start);
+                        let line_start = text.line_to_char(line);
+                        let line_text = text.line(line);
+                        // Очень длинные строки целиком не копируем.
+                        let upto = line_text.len_chars().min(found.end - line_start + 200);
+                        let (preview, highlight) = search::preview_line(
+                            &line_text.slice(..upto).to_string(),
+                            found.start - line_start..found.end - line_start,
+                        );
+                        Hit {
+                            range: found,
+                            line: line + 1,
+                            preview,
+                            highlight,
                         }
+                    })
+                    .collect();
+                (!hits.is_empty()).then(|| ResultGroup {
+                    doc_id: doc.id,
+                    title: doc.title.clone(),
+                    revision: doc.revision(),
+                    hits,
+                })
+            })
+            .collect();
+        self.search_results = Some(groups);
+    }
 
-                        if let Some(count) = self.last_find_count {
-                            ui.label(format!("Найдено вхождений: {count}"));
+    /// Панель результатов поиска, сгруппированных по вкладкам.
+    fn search_results_panel(&mut self, ctx: &egui::Context) {
+        let Some(groups) = &self.search_results else {
+            return;
+        };
+        let mut jump = None;
+        let mut refresh = false;
+        let mut close = false;
+
+        egui::TopBottomPanel::bottom("search_results")
+            .resizable(true)
+            .default_height(200.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let total: usize = groups.iter().map(|group| group.hits.len()).sum();
+                    ui.strong(format!("Найдено: {total}, вкладок: {}", groups.len()));
+                    let stale = groups.iter().any(|group| {
+                        self.docs
+                            .iter()
+                            .find(|doc| doc.id == group.doc_id)
+                            .is_none_or(|doc| doc.revision() != group.revision)
+                    });
+                    if stale {
+                        ui.weak("(документы изменились)");
+                    }
+                    refresh = ui.small_button("Обновить").clicked();
+                    close = ui.small_button("×").clicked();
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical()
+                    .auto_shrink(false)
+                    .show(ui, |ui| {
+                        for group in groups {
+                            egui::CollapsingHeader::new(format!(
+                                "{} — {}",
+                                group.title,
+                                group.hits.len()
+                            ))
+                            .id_salt(("search_results", group.doc_id))
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                for hit in group.hits.iter().take(search::PREVIEW_LIMIT) {
+                                    let job =
+                                        hit_job(ui, hit.line, &hit.preview, hit.highlight.clone());
+                                    if ui.selectable_label(false, job).clicked() {
+                                        jump = Some((group.doc_id, hit.range.clone()));
+                                    }
+                                }
+                                if let Some(more) =
+                                    group.hits.len().checked_sub(search::PREVIEW_LIMIT)
+                                    && more > 0
+                                {
+                                    ui.weak(format!("... и ещё {more}"));
+                                }
+                            });
                         }
                     });
+            });
 
-                    ui.separator();
+        if close {
+            self.search_results = None;
+        }
+        if refresh && let Ok(query) = self.query() {
+            self.find_all(&query);
+        }
+        if let Some((doc_id, range)) = jump
+            && let Some(i) = self.docs.iter().position(|doc| doc.id == doc_id)
+        {
+            self.active_doc = i;
+            let doc = self.current_doc_mut();
+            let len = doc.text().len_chars();
+            doc.set_selection(Selection {
+                anchor: range.start.min(len),
+                head: range.end.min(len),
+            });
+            self.reveal_cursor = true;
+        }
+    }
 
-                    // --- Заменить ---
-                    ui.horizontal(|ui| {
-                        ui.label("Заменить на:");
-                        ui.text_edit_singleline(&mut self.replace_text);
+    /// Окно "Найти в папке": папка, образец и запуск поиска.
+    fn folder_search_window(&mut self, ctx: &egui::Context) {
+        let query = self.query();
+        let running = self
+            .folder_search
+            .as_ref()
+            .is_some_and(|search| !search.done);
+        let mut pick_folder = false;
+        let mut start = false;
+        let mut stop = false;
+        let mut open = true;
+
+        egui::Window::new("Найти в папке")
+            .open(&mut open)
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Папка:");
+                    if let Some(root) = &self.folder_root {
+                        ui.monospace(root.display().to_string());
+                    }
+                    pick_folder = ui.button("Выбрать...").clicked();
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Найти:");
+                    let response = ui.text_edit_singleline(&mut self.find_text);
+                    start = response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter));
+                });
+                ui.horizontal(|ui| {
+                    let options = &mut self.search_options;
+                    ui.checkbox(&mut options.regex, "Регулярное выражение");
+                    ui.checkbox(&mut options.ignore_case, "Без учёта регистра");
+                    ui.checkbox(&mut options.whole_word, "Слово целиком");
+                });
+                if let Err(err) = &query
+                    && !self.find_text.is_empty()
+                {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        egui::RichText::new(err).monospace(),
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    if running {
+                        stop = ui.button("Остановить").clicked();
+                        ui.spinner();
+                    } else {
+                        start |= ui.button("Искать").clicked();
+                    }
+                    if let Some(search) = &self.folder_search {
+                        ui.label(format!(
+                            "Файлов просмотрено: {}, найдено строк: {}",
+                            search.searched,
+                            search.total_hits()
+                        ));
+                    }
+                });
+            });
+
+        if !open {
+            self.show_folder_search = false;
+        }
+        if pick_folder && let Some(root) = rfd::FileDialog::new().pick_folder() {
+            self.folder_root = Some(root);
+        }
+        if stop && let Some(search) = &self.folder_search {
+            search.stop();
+        }
+        if start && let (Ok(query), Some(root)) = (query, self.folder_root.clone()) {
+            // Прежний поиск останавливается, когда его результаты выбрасываются.
+            self.folder_search = Some(FolderSearch::start(
+                root,
+                self.find_text.clone(),
+                query,
+                ctx.clone(),
+            ));
+        }
+    }
+
+    /// Панель результатов поиска в папке; пополняется, пока идёт поиск.
+    fn folder_results_panel(&mut self, ctx: &egui::Context) {
+        let Some(search) = &mut self.folder_search else {
+            return;
+        };
+        search.poll();
+        let mut jump = None;
+        let mut close = false;
+
+        egui::TopBottomPanel::bottom("folder_results")
+            .resizable(true)
+            .default_height(220.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong(format!(
+                        "«{}» в {}: строк {}, файлов {}",
+                        search.pattern,
+                        search.root.display(),
+                        search.total_hits(),
+                        search.files.len()
+                    ));
+                    if !search.done {
+                        ui.spinner();
+                    } else if search.truncated {
+                        ui.weak(format!(
+                            "(показаны первые {} строк)",
+                            crate::folder_search::HIT_LIMIT
+                        ));
+                    }
+                    close = ui.small_button("×").clicked();
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical()
+                    .auto_shrink(false)
+                    .show(ui, |ui| {
+                        for file in &search.files {
+                            let name = file.path.strip_prefix(&search.root).unwrap_or(&file.path);
+                            egui::CollapsingHeader::new(format!(
+                                "{} — {}",
+                                name.display(),
+                                file.hits.len()
+                            ))
+                            .id_salt(("folder_results", &file.path))
+                            .default_open(true)
+                            .show(ui, |ui| {
+                                for hit in file.hits.iter().take(search::PREVIEW_LIMIT) {
+                                    let job =
+                                        hit_job(ui, hit.line, &hit.preview, hit.highlight.clone());
+                                    if ui.selectable_label(false, job).clicked() {
+                                        jump = Some((
+                                            file.path.clone(),
+                                            hit.line,
+                                            hit.columns.clone(),
+                                        ));
+                                    }
+                                }
+                            });
+                        }
                     });
+            });
 
-                    ui.horizontal(|ui| {
-                        if ui.button("Заменить всё").clicked() {
-                            let needle = self.find_text.clone();
-                            let replacement = self.replace_text.clone();
-
-                            if needle.is_empty() {
-                                self.last_replace_count = Some(0);
-                            } else {
-                                // Ограничиваем время жизни &mut за счёт отдельного блока
-                                let count = {
-                                    let doc = self.current_doc_mut();
-                                    doc.replace_all(&needle, &replacement)
-                                };
-                                self.last_replace_count = Some(count);
+        if close {
+            self.folder_search = None;
+        }
+        if let Some((path, line, columns)) = jump
+            && self.open_path(path)
+        {
+            let doc = self.current_doc_mut();
+            let text = doc.text();
+            let line = (line - 1).min(text.len_lines() - 1);
+            let start = text.line_to_char(line);
+            let len = text.line(line).len_chars();
+            doc.set_selection(Selection {
+                anchor: start + columns.start.min(len),
+                head: start + columns.end.min(len),
+            });
+            self.reveal_cursor = true;
+        }
+    }
+
+    /// Образец поиска из поля «Найти» и параметров.
+    fn query(&mut self) -> Result<Query, String> {
+        let compiled = self.compiled_query.take().filter(|compiled| {
+            compiled.pattern == self.find_text && compiled.options == self.search_options
+        });
+        let compiled = compiled.unwrap_or_else(|| CompiledQuery {
+            pattern: self.find_text.clone(),
+            options: self.search_options,
+            query: Query::new(&self.find_text, self.search_options),
+        });
+        let query = compiled.query.clone();
+        self.compiled_query = Some(compiled);
+        query
+    }
+
+    /// Пересчитывает совпадения, если поменялись образец, параметры или
+    /// текст. С `wait` правки текста учитываются только после паузы в них.
+    /// Возвращает `true`, если поменялся сам образец.
+    fn refresh_matches(&mut self, wait: bool) -> bool {
+        let scope = (self.search_scope == SearchScope::Selection)
+            .then(|| self.selection_scope().unwrap_or(0..0));
+        let query = self.query();
+        let doc = &self.docs[self.active_doc];
+        let pattern_changed = self.matches.as_ref().is_none_or(|matches| {
+            matches.pattern != self.find_text
+                || matches.options != self.search_options
+                || matches.scope != scope
+        });
+        if !pattern_changed
+            && let Some(matches) = &mut self.matches
+            && matches.doc_id == doc.id
+        {
+            if matches.revision == doc.revision() {
+                return false;
+            }
+            if wait {
+                let revision = doc.revision();
+                let edited = matches
+                    .edited
+                    .filter(|&(seen, _)| seen == revision)
+                    .unwrap_or((revision, Instant::now()));
+                matches.edited = Some(edited);
+                if edited.1.elapsed() < RECOUNT_DELAY {
+                    return false;
+                }
+            }
+        }
+
+        // Ищем в том же куске текста, что и «Заменить всё», — иначе `^`,
+        // `\b` и слово целиком на краях выделения срабатывали бы по-разному.
+        let within = scope.clone().unwrap_or(0..doc.text().len_chars());
+        let ranges = match query {
+            Ok(query) => doc.find_matches(&query, within),
+            Err(_) => Vec::new(),
+        };
+        let generation = self
+            .matches
+            .as_ref()
+            .map_or(0, |matches| matches.generation + 1);
+        self.matches = Some(Matches {
+            doc_id: doc.id,
+            revision: doc.revision(),
+            pattern: self.find_text.clone(),
+            options: self.search_options,
+            scope,
+            ranges,
+            generation,
+            edited: None,
+        });
+        pattern_changed
+    }
+
+    /// Совпадения текущего документа, если они посчитаны по его нынешнему
+    /// тексту; пока пересчёт ждёт паузы в правках, их нет.
+    fn fresh_matches(&self) -> Option<&Matches> {
+        let doc = self.current_doc();
+        self.matches
+            .as_ref()
+            .filter(|matches| matches.doc_id == doc.id && matches.revision == doc.revision())
+    }
+
+    /// Номер совпадения, которое сейчас выделено в документе.
+    fn current_match(&self) -> Option<usize> {
+        let matches = self.fresh_matches()?;
+        let range = self.current_doc().selection().range();
+        let i = matches
+            .ranges
+            .binary_search_by_key(&range.start, |r| r.start)
+            .ok()?;
+        (matches.ranges[i] == range).then_some(i)
+    }
+
+    /// Выделяет совпадение `i` и прокручивает к нему.
+    fn select_match(&mut self, i: usize) {
+        let Some(range) = self.matches.as_ref().and_then(|m| m.ranges.get(i).cloned()) else {
+            return;
+        };
+        self.current_doc_mut().set_selection(Selection {
+            anchor: range.start,
+            head: range.end,
+        });
+        self.reveal_match = true;
+    }
+
+    /// Переход к следующему (или предыдущему) совпадению от курсора, по кругу.
+    fn find_next(&mut self, backwards: bool) {
+        if self.find_text.is_empty() {
+            self.show_search_window = true;
+            return;
+        }
+        self.refresh_matches(false);
+        let Range {
+            start: from,
+            end: to,
+        } = self.current_doc().selection().range();
+        let Some(matches) = &self.matches else {
+            return;
+        };
+        let ranges = &matches.ranges;
+        if ranges.is_empty() {
+            self.notifications
+                .info(format!("Не найдено: «{}»", self.find_text));
+            return;
+        }
+        let next = if backwards {
+            ranges
+                .iter()
+                .rposition(|r| r.end <= from && *r != (from..to))
+                .unwrap_or(ranges.len() - 1)
+        } else {
+            ranges
+                .iter()
+                .position(|r| r.start >= to && *r != (from..to))
+                .unwrap_or(0)
+        };
+        self.select_match(next);
+    }
+
+    /// Заменяет выделенное совпадение и переходит к следующему.
+    fn replace_current(&mut self) {
+        let Some(i) = self.current_match() else {
+            self.find_next(false);
+            return;
+        };
+        let Ok(query) = self.query() else {
+            return;
+        };
+        let matches = self.matches.as_ref().expect("есть совпадения");
+        let found = matches.ranges[i].clone();
+        let doc = &mut self.docs[self.active_doc];
+        // Та же область, в которой совпадение нашли, — иначе края выделения
+        // для `^` и `\b` были бы другими.
+        let within = matches.scope.clone().unwrap_or(0..doc.text().len_chars());
+        let Some(end) = doc.replace_match(&query, &self.replace_text, within, found) else {
+            self.notifications
+                .warning("Совпадение изменилось — найдите его заново");
+            return;
+        };
+        doc.set_selection(Selection {
+            anchor: end,
+            head: end,
+        });
+        self.find_next(false);
+    }
+
+    /// Окно поиска и замены.
+    fn search_window(&mut self, ctx: &egui::Context) {
+        let query = self.query();
+        let current = self.current_match();
+        let mut edited = false;
+        let mut replace = false;
+        let mut replace_one = false;
+        let mut preview = false;
+        let mut find_all = false;
+        let mut take_selection = false;
+        let mut step = None;
+
+        egui::Window::new("Поиск и замена")
+            .collapsible(false)
+            .resizable(false)
+            .show(ctx, |ui| {
+                // --- Найти ---
+                ui.horizontal(|ui| {
+                    ui.label("Найти:");
+                    let response = ui.text_edit_singleline(&mut self.find_text);
+                    edited |= response.changed();
+                    if response.lost_focus() && ui.input(|i| i.key_pressed(egui::Key::Enter)) {
+                        step = Some(ui.input(|i| i.modifiers.shift));
+                        response.request_focus();
+                    }
+                });
+                ui.horizontal(|ui| {
+                    let options = &mut self.search_options;
+                    edited |= ui
+                        .checkbox(&mut options.regex, "Регулярное выражение")
+                        .changed();
+                    edited |= ui
+                        .checkbox(&mut options.ignore_case, "Без учёта регистра")
+                        .changed();
+                    edited |= ui
+                        .checkbox(&mut options.whole_word, "Слово целиком")
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    let previous = self.search_scope;
+                    egui::ComboBox::from_id_salt("search_scope")
+                        .selected_text(self.search_scope.label())
+                        .show_ui(ui, |ui| {
+                            for scope in SearchScope::ALL {
+                                ui.selectable_value(&mut self.search_scope, scope, scope.label());
                             }
+                        });
+                    if self.search_scope == SearchScope::Selection {
+                        take_selection =
+                            previous != SearchScope::Selection && self.selection_scope().is_none();
+                        take_selection |= ui
+                            .small_button("Взять выделение")
+                            .on_hover_text("Искать в том, что сейчас выделено")
+                            .clicked();
+                        if self.selection_scope().is_none() {
+                            ui.weak("Нет выделения");
                         }
+                    }
+                    find_all = ui.button("Найти все").clicked();
+                });
+                if let Err(err) = &query
+                    && !self.find_text.is_empty()
+                {
+                    ui.colored_label(
+                        ui.visuals().error_fg_color,
+                        egui::RichText::new(err).monospace(),
+                    );
+                }
+
+                ui.horizontal(|ui| {
+                    if ui.button("Предыдущее").clicked() {
+                        step = Some(true);
+                    }
+                    if ui.button("Следующее").clicked() {
+                        step = Some(false);
+                    }
 
-                        if let Some(count) = self.last_replace_count {
-                            ui.label(format!("Заменено вхождений: {count}"));
+                    let total = self.matches.as_ref().map_or(0, |m| m.ranges.len());
+                    let total = if total == search::MATCH_LIMIT {
+                        format!("{total}+")
+                    } else {
+                        total.to_string()
+                    };
+                    match current {
+                        _ if self.find_text.is_empty() || query.is_err() => {}
+                        Some(i) => {
+                            ui.label(format!("Совпадение {} из {total}", i + 1));
                         }
-                    });
+                        None => {
+                            ui.label(format!("Совпадений: {total}"));
+                        }
+                    }
+                });
 
+                ui.separator();
+
+                // --- Заменить ---
+                ui.horizontal(|ui| {
+                    ui.label("Заменить на:");
+                    edited |= ui
+                        .text_edit_singleline(&mut self.replace_text)
+                        .on_hover_text(
+                            "В режиме регулярных выражений $1 или ${имя} — найденная группа, \
+                             $$ — знак доллара",
+                        )
+                        .changed();
+                });
+
+                ui.horizontal(|ui| {
+                    replace_one = ui.button("Заменить").clicked();
+                    replace = ui.button("Заменить всё").clicked();
+                    preview = ui.button("Предпросмотр").clicked();
+
+                    if let Some(count) = self.last_replace_count {
+                        ui.label(format!("Заменено вхождений: {count}"));
+                    }
+                });
+
+                if let Some(preview) = &self.replace_preview {
                     ui.separator();
+                    let shown: usize = preview
+                        .groups
+                        .iter()
+                        .map(|(_, changes)| changes.len())
+                        .sum();
+                    if preview.total > shown {
+                        ui.label(format!("Замен: {}, показаны первые {shown}", preview.total));
+                    } else {
+                        ui.label(format!("Замен: {}", preview.total));
+                    }
+                    egui::ScrollArea::vertical()
+                        .max_height(240.0)
+                        .show(ui, |ui| {
+                            for (title, changes) in &preview.groups {
+                                if preview.groups.len() > 1 {
+                                    ui.strong(title);
+                                }
+                                for change in changes {
+                                    ui.horizontal(|ui| {
+                                        ui.weak(format!("{}:", change.line));
+                                        ui.monospace(&change.found);
+                                        ui.label("→");
+                                        ui.monospace(&change.replaced);
+                                    });
+                                }
+                            }
+                        });
+                }
+
+                ui.separator();
+
+                if ui.button("Закрыть").clicked() {
+                    self.show_search_window = false;
+                    self.replace_preview = None;
+                }
+            });
+
+        if take_selection {
+            self.take_selection_scope();
+        }
+        // Поиск по мере набора: переходим к первому совпадению от курсора.
+        let pattern_changed = self.refresh_matches(true);
+        if self.fresh_matches().is_none() {
+            ctx.request_repaint_after(RECOUNT_DELAY);
+        }
+        if pattern_changed
+            && let Some(ranges) = self.matches.as_ref().map(|m| &m.ranges)
+            && !ranges.is_empty()
+        {
+            let from = self.current_doc().selection().range().start;
+            let i = ranges.iter().position(|r| r.start >= from).unwrap_or(0);
+            self.select_match(i);
+        }
+        if let Some(backwards) = step {
+            self.find_next(backwards);
+        }
+        if replace_one {
+            self.replace_current();
+        }
+
+        // Предпросмотр устарел, если поменяли образец или документы.
+        if edited
+            || take_selection
+            || self
+                .replace_preview
+                .as_ref()
+                .is_some_and(|p| p.revisions != self.doc_revisions())
+        {
+            self.replace_preview = None;
+        }
+
+        let Ok(query) = query else {
+            if replace {
+                self.last_replace_count = Some(0);
+            }
+            return;
+        };
+        if find_all {
+            self.find_all(&query);
+        }
+        if preview {
+            let mut groups = Vec::new();
+            let mut total = 0;
+            for (i, range) in self.scope_targets() {
+                let doc = &self.docs[i];
+                let text = doc.text().slice(range.clone()).to_string();
+                let first_line = doc.text().char_to_line(range.start);
+                let mut changes = query.preview(&text, &self.replace_text);
+                for change in &mut changes {
+                    change.line += first_line;
+                }
+                total += query.count(&text);
+                if !changes.is_empty() {
+                    groups.push((doc.title.clone(), changes));
+                }
+            }
+            self.replace_preview = Some(ReplacePreview {
+                revisions: self.doc_revisions(),
+                groups,
+                total,
+            });
+        }
+        if replace {
+            // Каждый документ меняется своим шагом отмены.
+            let replacement = self.replace_text.clone();
+            let mut count = 0;
+            for (i, range) in self.scope_targets() {
+                count += self.docs[i].replace_all(&query, &replacement, range);
+            }
+            self.last_replace_count = Some(count);
+            self.replace_preview = None;
+        }
+    }
+
+    /// Ошибки в файле раскладки и конфликты сочетаний.
+    fn keymap_problems_window(&mut self, ctx: &egui::Context) {
+        let mut open = true;
+        egui::Window::new("Раскладка клавиш")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                for problem in &self.keymap.problems {
+                    ui.label(problem);
+                }
+            });
+        if !open {
+            self.keymap.problems.clear();
+        }
+    }
+
+    /// Команда, выбранная в уведомлении, — для документа, о котором оно.
+    fn run_follow_up(&mut self, ctx: &egui::Context, follow_up: FollowUp) {
+        if let Some(doc_id) = follow_up.doc_id {
+            let Some(idx) = self.docs.iter().position(|d| d.id == doc_id) else {
+                return;
+            };
+            self.active_doc = idx;
+        }
+        self.run_action(ctx, follow_up.action);
+    }
+
+    /// Строка состояния внизу окна. Слева — курсор и состояние документа,
+    /// справа — свойства файла; клик по полю открывает команду для него.
+    fn status_bar(&mut self, ctx: &egui::Context) {
+        egui::TopBottomPanel::bottom("status_bar").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                self.cursor_status(ui);
+                ui.separator();
+                self.save_status(ui);
 
-                    if ui.button("Закрыть").clicked() {
-                        self.show_search_window = false;
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    let errors = self.notifications.unread_errors();
+                    let log_label = if errors > 0 {
+                        format!("⛔ {errors}")
+                    } else {
+                        "Журнал".to_string()
+                    };
+                    if ui
+                        .selectable_label(self.notifications.show_log, log_label)
+                        .on_hover_text(Action::ToggleLog.label())
+                        .clicked()
+                    {
+                        self.run_action(ui.ctx(), Action::ToggleLog);
                     }
+                    ui.menu_button(self.current_doc().encoding().label(), |ui| {
+                        self.encoding_menu(ui, Action::ReopenWithEncoding);
+                        self.encoding_menu(ui, Action::SaveWithEncoding);
+                    });
+                    ui.menu_button(self.current_doc().line_ending().label(), |ui| {
+                        self.line_ending_menu(ui);
+                    });
+                    ui.menu_button(self.current_doc().language().label(), |ui| {
+                        self.language_menu(ui);
+                    });
+                    ui.menu_button(self.current_doc().indent().label(), |ui| {
+                        self.indent_menu(ui);
+                    });
+                    if self.current_doc().mixed_line_endings() {
+                        ui.colored_label(ui.visuals().warn_fg_color, "⚠ Разные окончания строк")
+                            .on_hover_text(
+                                "В файле встречаются разные окончания строк. \
+                                 При сохранении все они станут такими, как выбрано справа.",
+                            );
+                    }
+                });
+            });
+        });
+    }
+
+    /// Позиция курсора, выделение и число строк.
+    fn cursor_status(&mut self, ui: &mut egui::Ui) {
+        let doc = self.current_doc();
+        let text = doc.text();
+        let selection = doc.selection();
+        let line = text.char_to_line(selection.head);
+        let column = selection.head - text.line_to_char(line);
+        let total = text.len_lines();
+        let cursors = doc.extra_cursors().len() + 1;
+        let range = selection.range();
+        let (first, last) = doc.selected_lines();
+        let selected_lines = last + 1 - first;
+
+        if ui
+            .button(format!("Стр {}, стлб {}", line + 1, column + 1))
+            .on_hover_text(Action::GoToLine.label())
+            .clicked()
+        {
+            self.run_action(ui.ctx(), Action::GoToLine);
+        }
+        if !range.is_empty() {
+            ui.label(format!(
+                "Выделено: {} симв., {selected_lines} стр.",
+                range.len()
+            ));
+        }
+        if cursors > 1 {
+            ui.label(format!("Курсоров: {cursors}"));
+        }
+        ui.weak(format!("Строк: {total}"));
+    }
+
+    /// Сохранён ли документ и когда автосохранение запишет правки.
+    fn save_status(&mut self, ui: &mut egui::Ui) {
+        let doc = self.current_doc();
+        let (dirty, id, revision) = (doc.dirty, doc.id, doc.revision());
+        if !dirty {
+            ui.weak("Сохранён");
+            return;
+        }
+        if ui
+            .button("● Изменён")
+            .on_hover_text(Action::Save.label())
+            .clicked()
+        {
+            self.run_action(ui.ctx(), Action::Save);
+        }
+        if self.swapped.get(&id) != Some(&revision) {
+            let left = self
+                .autosave_interval
+                .saturating_sub(self.last_autosave.elapsed());
+            ui.menu_button(
+                format!("Автосохранение через {} с", left.as_secs() + 1),
+                |ui| {
+                    self.autosave_setting(ui);
+                },
+            )
+            .response
+            .on_hover_text("Несохранённые правки попадут в файл подкачки");
+        }
+    }
+
+    /// Боковая панель дерева отмены: все состояния документа, включая
+    /// отменённые ветки. Клик — переход к состоянию.
+    fn undo_tree_panel(&mut self, ctx: &egui::Context) {
+        let mut jump_to = None;
+        let mut diff_with = None;
+
+        egui::SidePanel::right("undo_tree")
+            .resizable(true)
+            .default_width(280.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.heading("Дерево отмены");
+                    if ui.small_button("×").clicked() {
+                        self.show_undo_tree = false;
+                    }
+                });
+                ui.separator();
+
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for row in self.current_doc().history().rows() {
+                        ui.horizontal(|ui| {
+                            ui.add_space(row.depth as f32 * 14.0);
+                            let marker = if row.current { "●" } else { "○" };
+                            let label =
+                                format!("{marker} {} · {}", time_ago(row.time), row.summary);
+                            if ui.selectable_label(row.current, label).clicked() {
+                                jump_to = Some(row.id);
+                            }
+                            if !row.current && ui.small_button("Сравнить").clicked() {
+                                diff_with = Some(row.id);
+                            }
+                        });
+                    }
+                });
+            });
+
+        if let Some(id) = jump_to {
+            self.current_doc_mut().jump_to(id);
+        }
+
+        if let Some(id) = diff_with {
+            let doc = self.current_doc();
+            if let Some(old) = doc.text_at(id) {
+                self.diff_view = Some(DiffView {
+                    title: format!("{}: состояние {id} → текущее", doc.title),
+                    lines: diff_lines(&old.to_string(), &doc.text().to_string()),
+                });
+            }
+        }
+    }
+
+    /// Окно с разницей между двумя текстами.
+    fn diff_window(&mut self, ctx: &egui::Context) {
+        let Some(diff) = &self.diff_view else {
+            return;
+        };
+        let mut open = true;
+
+        egui::Window::new("Сравнение")
+            .open(&mut open)
+            .default_size([600.0, 400.0])
+            .show(ctx, |ui| {
+                ui.label(&diff.title);
+                ui.separator();
+                egui::ScrollArea::both().show(ui, |ui| {
+                    for (tag, line) in &diff.lines {
+                        let (sign, color) = match tag {
+                            similar::ChangeTag::Delete => ("-", Color32::from_rgb(230, 110, 110)),
+                            similar::ChangeTag::Insert => ("+", Color32::from_rgb(110, 200, 110)),
+                            similar::ChangeTag::Equal => (" ", ui.visuals().weak_text_color()),
+                        };
+                        ui.label(
+                            egui::RichText::new(format!("{sign} {}", line.trim_end_matches('\n')))
+                                .monospace()
+                                .color(color),
+                        );
+                    }
+                });
+            });
+
+        if !open {
+            self.diff_view = None;
+        }
+    }
+
+    /// Основное текстовое поле
+    fn editor_area(&mut self, ui: &mut egui::Ui) {
+        // Сначала снимаем настройки в локальные переменные (чтобы не ругался borrow checker)
+        let font_id = egui::FontId::monospace(self.font_size);
+        let text_color = self.text_color;
+        let row_height = ui.fonts_mut(|f| f.row_height(&font_id));
+        let dark_mode = ui.visuals().dark_mode;
+        let line_numbers = self.line_numbers;
+        // Совпадения поиска подсвечиваем, пока открыто окно поиска.
+        let current_match = self.current_match();
+        let matches = self.matches.as_ref().filter(|matches| {
+            let doc = &self.docs[self.active_doc];
+            self.show_search_window
+                && matches.doc_id == doc.id
+                && matches.revision == doc.revision()
+        });
+
+        let doc = &mut self.docs[self.active_doc];
+        let galley_cache = &mut self.galley_cache;
+        let span_cache = &mut self.span_cache;
+        let last_viewport = &mut self.editor_viewport;
+        let scroll_offsets = &mut self.scroll_offsets;
+        let reveal = std::mem::take(&mut self.reveal_cursor);
+        let reveal_match = std::mem::take(&mut self.reveal_match);
+        let block_anchor = &mut self.block_anchor;
+
+        let doc_id = doc.id;
+        let edit_id = egui::Id::new(("editor", doc_id));
+        let large = doc.is_large();
+        // С несколькими курсорами ввод применяем сами, а не виджет.
+        // Enter и Tab — всегда сами: виджет не знает об отступах документа.
+        let mut typed = false;
+        if ui.memory(|m| m.has_focus(edit_id)) {
+            if !doc.extra_cursors().is_empty() {
+                multi_cursor::handle_input(ui.ctx(), doc);
+            }
+            // egui сравнивает модификаторы "логически": Tab сработал бы и на Shift+Tab.
+            let (outdent, tab, enter) = ui.input_mut(|i| {
+                (
+                    i.consume_key(egui::Modifiers::SHIFT, egui::Key::Tab),
+                    i.consume_key(egui::Modifiers::NONE, egui::Key::Tab),
+                    i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                )
+            });
+            if outdent || tab {
+                doc.tab(outdent);
+            }
+            if enter {
+                doc.newline();
+            }
+            typed = outdent || tab || enter;
+        }
+
+        let mut scroll_area = egui::ScrollArea::both()
+            .id_salt(("editor_scroll", doc_id))
+            .auto_shrink(false);
+        if let Some(offset) = self.pending_scroll.remove(&doc_id) {
+            scroll_area = scroll_area.scroll_offset(offset);
+        }
+
+        // Большой файл виджет видит только вокруг экрана. Если курсор ушёл
+        // за экран, а пользователь печатает, сначала прокручиваем к курсору.
+        if large && last_viewport.0 == doc_id {
+            let viewport = last_viewport.1;
+            let cursor_y = doc.text().char_to_line(doc.selection().head) as f32 * row_height;
+            let cursor_visible =
+                cursor_y >= viewport.min.y && cursor_y + row_height <= viewport.max.y;
+            let typing = ui.memory(|m| m.has_focus(edit_id))
+                && ui.input(|i| {
+                    i.events.iter().any(|e| {
+                        matches!(
+                            e,
+                            egui::Event::Text(_)
+                                | egui::Event::Paste(_)
+                                | egui::Event::Key { pressed: true, .. }
+                        )
+                    })
                 });
+            if (typing || typed || reveal || reveal_match) && !cursor_visible {
+                scroll_area = scroll_area
+                    .vertical_scroll_offset((cursor_y - viewport.height() / 2.0).max(0.0));
+            }
+        }
+
+        scroll_area.show_viewport(ui, |ui, viewport| {
+            *last_viewport = (doc_id, viewport);
+            scroll_offsets.insert(doc_id, viewport.min.to_vec2());
+            // Видимая часть в координатах экрана.
+            let visible = viewport.translate(ui.max_rect().min.to_vec2());
+
+            let total_lines = doc.text().len_lines();
+            let gutter_width = gutter::width(ui, &font_id, line_numbers, total_lines);
+            let (first_line, last_line) = if large {
+                let last = ((viewport.max.y / row_height).ceil() as usize + VIEW_MARGIN_LINES)
+                    .min(total_lines);
+                let first = ((viewport.min.y / row_height) as usize)
+                    .saturating_sub(VIEW_MARGIN_LINES)
+                    .min(last);
+                (first, last)
+            } else {
+                (0, total_lines)
+            };
+            let view = doc.text().line_to_char(first_line)..doc.text().line_to_char(last_line);
+            doc.prepare_view(view.clone());
+            // Подсветку считаем только для окна, начиная с состояния лексера
+            // на его первой строке: строки выше уже разобраны и закэшированы.
+            let language = doc.language();
+            let syntax_state = doc.syntax_state(first_line);
+
+            // Курсор хранит документ; виджету отдаём его в координатах окна.
+            let selection = doc.selection();
+            let in_view = |pos: usize| view.start <= pos && pos <= view.end;
+            let cursor_in_view = in_view(selection.anchor) && in_view(selection.head);
+            let wanted = cursor_in_view.then(|| {
+                egui::text::CCursorRange::two(
+                    egui::text::CCursor::new(selection.anchor - view.start),
+                    egui::text::CCursor::new(selection.head - view.start),
+                )
+            });
+            let mut state = egui::TextEdit::load_state(ui.ctx(), edit_id).unwrap_or_default();
+            let current = state
+                .cursor
+                .char_range()
+                .map(|r| (r.secondary.index, r.primary.index));
+            if current != wanted.map(|r| (r.secondary.index, r.primary.index)) {
+                state.cursor.set_char_range(wanted);
+            }
+            // Своей отмены у виджета быть не должно: даже если Ctrl+Z
+            // переназначен, TextEdit не откатит текст в обход документа.
+            state.clear_undoer();
+            state.store(ui.ctx(), edit_id);
+
+            let revision = Cell::new(doc.revision());
+            let mut layouter = |ui: &egui::Ui, buf: &dyn egui::TextBuffer, wrap_width: f32| {
+                let wrap_width = if large { f32::INFINITY } else { wrap_width };
+                let key = GalleyKey {
+                    doc_id,
+                    revision: revision.get(),
+                    view: view.clone(),
+                    wrap_width: wrap_width.to_bits(),
+                    font_size: font_id.size.to_bits(),
+                    text_color,
+                    search: matches.map(|matches| (matches.generation, current_match)),
+                    language,
+                    dark_mode,
+                };
+                if let Some((cached_key, galley)) = galley_cache.as_ref()
+                    && *cached_key == key
+                {
+                    return galley.clone();
+                }
+
+                let mut highlights = Vec::new();
+                if let Some(matches) = matches {
+                    let first = matches.ranges.partition_point(|r| r.end <= view.start);
+                    let visible = matches.ranges[first..]
+                        .iter()
+                        .take_while(|r| r.start < view.end);
+                    for (i, range) in visible.enumerate() {
+                        let color = if current_match == Some(first + i) {
+                            ui.visuals().selection.bg_fill
+                        } else {
+                            ui.visuals().warn_fg_color.gamma_multiply(0.35)
+                        };
+                        let start = range.start.max(view.start) - view.start;
+                        let end = range.end.min(view.end) - view.start;
+                        highlights.push((start..end, color));
+                    }
+                }
+                let colors: Vec<_> = span_cache
+                    .highlight(language, buf.as_str(), syntax_state)
+                    .into_iter()
+                    .map(|(range, kind)| (range, kind.color(dark_mode)))
+                    .collect();
+                let job = layout_job(
+                    buf.as_str(),
+                    &font_id,
+                    text_color,
+                    wrap_width,
+                    &colors,
+                    &highlights,
+                );
+                let galley = ui.fonts_mut(|f| f.layout_job(job));
+                *galley_cache = Some((key, galley.clone()));
+                galley
+            };
+
+            // Подсветку строки с курсором рисуем под текстом, но узнаем, где
+            // она, только из раскладки — оставляем для неё место заранее.
+            let current_line_bg = ui.painter().add(egui::Shape::Noop);
+            let extra_selections_bg = ui.painter().add(egui::Shape::Noop);
+            let (alt, pressed) = ui.input(|i| (i.modifiers.alt, i.pointer.primary_pressed()));
+            let mut buffer = DocumentBuffer::new(doc, &revision);
+            let text_edit = egui::TextEdit::multiline(&mut buffer)
+                .id(edit_id)
+                // Настройка шрифта прямо на виджете:
+                .font(font_id.clone())
+                // Настройка цвета текста прямо на виджете:
+                .text_color(text_color)
+                .lock_focus(true)
+                .desired_width(f32::INFINITY)
+                .frame(false)
+                .layouter(&mut layouter);
+
+            let output = if large {
+                // Окно строк рисуем там, где оно оказалось бы в полном тексте.
+                ui.set_min_height(total_lines as f32 * row_height);
+                let rect = egui::Rect::from_min_size(
+                    ui.max_rect().min + egui::vec2(gutter_width, first_line as f32 * row_height),
+                    egui::vec2(
+                        viewport.width() - gutter_width,
+                        (last_line - first_line).max(1) as f32 * row_height,
+                    ),
+                );
+                ui.scope_builder(egui::UiBuilder::new().max_rect(rect), |ui| {
+                    text_edit.margin(egui::Margin::ZERO).show(ui)
+                })
+                .inner
+            } else {
+                ui.horizontal_top(|ui| {
+                    ui.spacing_mut().item_spacing.x = 0.0;
+                    ui.add_space(gutter_width);
+                    text_edit
+                        .min_size(viewport.size() - egui::vec2(gutter_width, 0.0))
+                        .show(ui)
+                })
+                .inner
+            };
+
+            if (reveal || reveal_match || typed) && cursor_in_view {
+                let cursor = egui::text::CCursor::new(selection.head - view.start);
+                let rect = output.galley.pos_from_cursor(cursor);
+                // После Enter и Tab прокручиваем ровно настолько, чтобы курсор был виден.
+                ui.scroll_to_rect(
+                    rect.translate(output.galley_pos.to_vec2()),
+                    (reveal || reveal_match).then_some(egui::Align::Center),
+                );
+                if reveal {
+                    output.response.request_focus();
+                }
+            }
+
+            // Курсор за пределами окна не трогаем, пока пользователь не кликнул.
+            let interacted =
+                output.response.changed() || output.response.clicked() || output.response.dragged();
+            if let Some(range) = output.cursor_range
+                && (cursor_in_view || interacted)
+            {
+                let start = doc.view_range().start;
+                doc.set_selection(Selection {
+                    anchor: start + range.secondary.index,
+                    head: start + range.primary.index,
+                });
+            }
+
+            let lines = gutter::line_rects(&output.galley, first_line);
+            let origin = output.galley_pos;
+
+            // Alt+клик добавляет курсор, Alt+перетаскивание выделяет столбец,
+            // простой клик оставляет один курсор.
+            let pointer = output.response.interact_pointer_pos();
+            if pressed && output.response.is_pointer_button_down_on() {
+                if alt && let Some(pos) = pointer {
+                    *block_anchor =
+                        gutter::line_at(&lines, origin, pos.y).map(|line| (line, pos.x - origin.x));
+                    let mut extra = doc.extra_cursors().to_vec();
+                    extra.push(selection);
+                    doc.set_cursors(doc.selection(), extra);
+                } else {
+                    *block_anchor = None;
+                    doc.clear_cursors();
+                }
+            }
+            if let Some(from) = *block_anchor
+                && output.response.dragged()
+                && let Some(pos) = pointer
+                && let Some(line) = gutter::line_at(&lines, origin, pos.y)
+                && let Some((primary, extra)) = multi_cursor::block_selection(
+                    &output.galley,
+                    &lines,
+                    view.start,
+                    from,
+                    (line, pos.x - origin.x),
+                )
+            {
+                doc.set_cursors(primary, extra);
+            }
+            if !output.response.is_pointer_button_down_on() {
+                *block_anchor = None;
+            }
+            let extra_cursors = doc.extra_cursors();
+            if !extra_cursors.is_empty() {
+                let shapes = multi_cursor::selection_shapes(
+                    ui,
+                    &output.galley,
+                    origin,
+                    &view,
+                    extra_cursors,
+                );
+                ui.painter()
+                    .set(extra_selections_bg, egui::Shape::Vec(shapes));
+                multi_cursor::paint_cursors(ui, &output.galley, origin, &view, extra_cursors);
+            }
+
+            let cursor_line = doc.text().char_to_line(doc.selection().head);
+            if let Ok(i) = lines.binary_search_by_key(&cursor_line, |(line, _)| *line) {
+                let rows = lines[i].1.translate(origin.to_vec2());
+                let rect = egui::Rect::from_x_y_ranges(visible.x_range(), rows.y_range());
+                let fill = ui.visuals().faint_bg_color;
+                ui.painter()
+                    .set(current_line_bg, egui::Shape::rect_filled(rect, 0.0, fill));
+            }
+
+            if line_numbers != LineNumbers::Off {
+                let rect = egui::Rect::from_min_size(
+                    visible.min,
+                    egui::vec2(gutter_width, visible.height()),
+                );
+                gutter::paint(
+                    ui,
+                    rect,
+                    &lines,
+                    origin,
+                    cursor_line,
+                    line_numbers,
+                    &font_id,
+                );
+                // Клик по номеру выделяет строку целиком.
+                let response = ui.interact(rect, edit_id.with("gutter"), egui::Sense::click());
+                if response.clicked()
+                    && let Some(pos) = response.interact_pointer_pos()
+                    && let Some(line) = gutter::line_at(&lines, origin, pos.y)
+                {
+                    let text = doc.text();
+                    let start = text.line_to_char(line);
+                    let end = if line + 1 < text.len_lines() {
+                        text.line_to_char(line + 1)
+                    } else {
+                        text.len_chars()
+                    };
+                    doc.set_selection(Selection {
+                        anchor: start,
+                        head: end,
+                    });
+                    output.response.request_focus();
+                    ui.ctx().request_repaint();
+                }
+            }
+        });
+    }
+}
+
+/// Строка результата поиска: номер строки и её текст с подсвеченным найденным.
+fn hit_job(
+    ui: &egui::Ui,
+    line: usize,
+    preview: &str,
+    found: Range<usize>,
+) -> egui::text::LayoutJob {
+    let mono = egui::TextStyle::Monospace.resolve(ui.style());
+    let plain = egui::TextFormat::simple(mono.clone(), ui.visuals().text_color());
+    let highlighted = egui::TextFormat {
+        background: ui.visuals().warn_fg_color.gamma_multiply(0.35),
+        ..plain.clone()
+    };
+    let mut job = egui::text::LayoutJob::default();
+    job.append(
+        &format!("{line:>6}  "),
+        0.0,
+        egui::TextFormat::simple(mono, ui.visuals().weak_text_color()),
+    );
+    job.append(&preview[..found.start], 0.0, plain.clone());
+    job.append(&preview[found.clone()], 0.0, highlighted);
+    job.append(&preview[found.end..], 0.0, plain);
+    job
+}
+
+/// Раскладка текста редактора: цвет символов из `colors` (подсветка
+/// синтаксиса) и фон из `highlights` (совпадения поиска). Диапазоны — в
+/// символах, по возрастанию и без пересечений внутри каждого списка.
+fn layout_job(
+    text: &str,
+    font_id: &egui::FontId,
+    color: Color32,
+    wrap_width: f32,
+    colors: &[(Range<usize>, Color32)],
+    highlights: &[(Range<usize>, Color32)],
+) -> egui::text::LayoutJob {
+    let format = |color, background| egui::TextFormat {
+        font_id: font_id.clone(),
+        color,
+        background,
+        ..Default::default()
+    };
+    let mut job = egui::text::LayoutJob::default();
+    job.wrap.max_width = wrap_width;
+
+    // Границы идут по порядку, поэтому символы в байты переводим одним проходом.
+    let mut walked = (0, 0);
+    let mut to_byte = |ch: usize| {
+        let (from_char, from_byte) = walked;
+        let byte = text[from_byte..]
+            .char_indices()
+            .nth(ch.saturating_sub(from_char))
+            .map_or(text.len(), |(i, _)| from_byte + i);
+        walked = (ch.max(from_char), byte);
+        byte
+    };
+    // Цвет, действующий на символе `pos`; `next` бежит по списку вперёд.
+    let covering = |list: &[(Range<usize>, Color32)], next: &mut usize, pos: usize| {
+        while list.get(*next).is_some_and(|(range, _)| range.end <= pos) {
+            *next += 1;
+        }
+        list.get(*next)
+            .filter(|(range, _)| range.start <= pos)
+            .map(|(_, color)| *color)
+    };
+
+    let mut bounds: Vec<usize> = colors
+        .iter()
+        .chain(highlights)
+        .flat_map(|(range, _)| [range.start, range.end])
+        .collect();
+    bounds.sort_unstable();
+    bounds.dedup();
+
+    let (mut next_color, mut next_highlight) = (0, 0);
+    let (mut pos, mut pos_byte) = (0, 0);
+    for bound in bounds.into_iter().filter(|&bound| bound > 0) {
+        let end = to_byte(bound);
+        let fg = covering(colors, &mut next_color, pos).unwrap_or(color);
+        let bg = covering(highlights, &mut next_highlight, pos).unwrap_or(Color32::TRANSPARENT);
+        job.append(&text[pos_byte..end], 0.0, format(fg, bg));
+        (pos, pos_byte) = (bound, end);
+    }
+    job.append(&text[pos_byte..], 0.0, format(color, Color32::TRANSPARENT));
+    job
+}
+
+fn diff_lines(old: &str, new: &str) -> Vec<(similar::ChangeTag, String)> {
+    similar::TextDiff::from_lines(old, new)
+        .iter_all_changes()
+        .map(|change| (change.tag(), change.to_string_lossy().into_owned()))
+        .collect()
+}
+
+/// Пишет текст документа в подкачку, если там ещё не последняя правка.
+fn write_swap(
+    doc: &Document,
+    swapped: &mut HashMap<usize, u64>,
+    notifications: &mut Notifications,
+) {
+    if swapped.get(&doc.id) == Some(&doc.revision()) {
+        return;
+    }
+    match swap::write(doc) {
+        Ok(()) => {
+            swapped.insert(doc.id, doc.revision());
+        }
+        Err(err) => notifications.error(format!(
+            "Не удалось записать файл подкачки «{}»: {err}",
+            doc.title
+        )),
+    }
+}
+
+fn remove_swap(doc_id: usize, notifications: &mut Notifications) {
+    if let Err(err) = swap::remove(doc_id) {
+        notifications.warning(format!("Не удалось удалить файл подкачки: {err}"));
+    }
+}
+
+/// Сохраняет историю отмены документа; ошибка — только предупреждение.
+fn store_history(doc: &Document, notifications: &mut Notifications) {
+    if let Err(err) = history_store::store(doc) {
+        notifications.warning(format!(
+            "Не удалось сохранить историю «{}»: {err}",
+            doc.title
+        ));
+    }
+}
+
+impl eframe::App for TextEditorApp {
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        session::save(storage, &self.session());
+    }
+
+    fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
+        for doc in &self.docs {
+            store_history(doc, &mut self.notifications);
+        }
+        // Окно закрыли в обход диалога выхода: несохранённые правки файлов
+        // дописываем в подкачку, из неё их можно восстановить. Безымянные
+        // документы и так лежат в сессии. После диалога правки, которые не
+        // сохранили, отброшены явно.
+        let keep = |doc: &Document| !self.quit_confirmed && doc.dirty && doc.path.is_some();
+        for doc in self.docs.iter().filter(|doc| keep(doc)) {
+            write_swap(doc, &mut self.swapped, &mut self.notifications);
+        }
+        let ids: Vec<usize> = self
+            .docs
+            .iter()
+            .filter(|doc| !keep(doc))
+            .map(|doc| doc.id)
+            .collect();
+        for id in ids {
+            self.drop_swap(id);
+        }
+    }
+
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        // Крестик окна, Alt+F4 и т. п.: закрытие откладываем, пока
+        // пользователь не решит, что делать с несохранённым.
+        if ctx.input(|i| i.viewport().close_requested()) && !self.quit_confirmed {
+            ctx.send_viewport_cmd(egui::ViewportCommand::CancelClose);
+            self.request_quit(ctx);
+        }
+
+        // Горячие клавиши забираем до TextEdit, иначе он обработает их сам.
+        for action in self.keymap.consume_pressed(ctx) {
+            self.run_action(ctx, action);
+        }
+
+        if !self.keymap.problems.is_empty() {
+            self.keymap_problems_window(ctx);
+        }
+        self.command_palette(ctx);
+        if let Some(symbol) = self.symbol_picker.show(ctx) {
+            self.go_to(symbol.line, symbol.column);
         }
 
+        // Верхнее меню
+        egui::TopBottomPanel::top("menu_bar").show(ctx, |ui| {
+            egui::MenuBar::new().ui(ui, |ui| {
+                self.file_menu(ui);
+                self.edit_menu(ui);
+                self.search_menu(ui);
+                self.view_menu(ui);
+            });
+        });
+
+        if self.show_undo_tree {
+            self.undo_tree_panel(ctx);
+        }
+        self.diff_window(ctx);
+        self.close_dialog(ctx);
+        let mut follow_up = self.notifications.show_toasts(ctx);
+        if !self.recovery.is_empty() {
+            self.recovery_window(ctx);
+        }
+        self.check_external_changes();
+        if !self.external_changes.is_empty() {
+            self.external_change_window(ctx);
+        }
+
+        self.status_bar(ctx);
+        if self.notifications.show_log {
+            follow_up = follow_up.or(self.notifications.log_panel(ctx));
+        }
+        self.search_results_panel(ctx);
+        self.folder_results_panel(ctx);
+        if let Some(follow_up) = follow_up {
+            self.run_follow_up(ctx, follow_up);
+        }
+
+        // Окно поиска / замены (отдельное, не меню). До редактора — чтобы
+        // подсветка успевала за набором.
+        if self.show_search_window {
+            self.search_window(ctx);
+        }
+        if self.show_folder_search {
+            self.folder_search_window(ctx);
+        }
+
+        // Центральная область: вкладки и редактор
+        egui::CentralPanel::default().show(ctx, |ui| {
+            self.tabs_bar(ui);
+            ui.separator();
+            self.editor_area(ui);
+        });
+
         // Автосохранение
         self.handle_autosave();
 
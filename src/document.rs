@@ -1,13 +1,173 @@
+use std::borrow::Cow;
 use std::fs;
-use std::path::PathBuf;
+use std::hash::Hasher;
+use std::io;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use ropey::Rope;
+use ropey::str_utils::char_to_byte_idx;
+use serde::{Deserialize, Serialize};
+
+use crate::editorconfig;
+use crate::encoding::{self, TextEncoding};
+use crate::file_io::{self, BackupMode};
+use crate::hash::{Fnv64, fnv64};
+use crate::history::{Edit, History, HistoryData, LineEndingChange, NodeId};
+use crate::indent::{self, Indent};
+use crate::line_commands::{self, LineCommand};
+use crate::line_ending::{self, LineEnding};
+use crate::merge;
+use crate::search::Query;
+use crate::syntax::{Highlighter, Language, State};
+
+/// Файлы больше этого числа символов или строк редактируются "окном":
+/// виджету отдаются только видимые строки, а не весь текст.
+const LARGE_FILE_CHARS: usize = 1_000_000;
+const LARGE_FILE_LINES: usize = 20_000;
+
+/// Выделение в символах документа.
+///
+/// `anchor` — где выделение началось, `head` — где сейчас курсор.
+/// Пустое выделение (`anchor == head`) — это просто курсор.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Selection {
+    pub anchor: usize,
+    pub head: usize,
+}
+
+impl Selection {
+    /// Выделенные символы, от меньшей позиции к большей.
+    pub fn range(&self) -> Range<usize> {
+        self.anchor.min(self.head)..self.anchor.max(self.head)
+    }
+
+    /// Сдвигает выделение с учётом замены `range` на `inserted` символов.
+    fn map_through(&mut self, range: &Range<usize>, inserted: usize) {
+        let map = |pos: usize| {
+            if pos <= range.start {
+                pos
+            } else if pos >= range.end {
+                pos - (range.end - range.start) + inserted
+            } else {
+                range.start + inserted
+            }
+        };
+        self.anchor = map(self.anchor);
+        self.head = map(self.head);
+    }
+}
+
+/// Кусок документа, который сейчас отдан виджету редактора.
+///
+/// Для обычных файлов это весь текст, для больших — видимые строки с запасом.
+/// Правки через [`Document`] обновляют его на месте, поэтому каждый кадр
+/// текст заново не копируется.
+#[derive(Default)]
+struct View {
+    range: Range<usize>,
+    text: String,
+    valid: bool,
+}
+
+/// Каким файл был на диске, когда мы его последний раз читали или писали.
+#[derive(Clone, Copy, Default)]
+struct DiskStamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+    /// Окончания строк в файле: хэш считается по тексту уже с `\n`.
+    line_endings: line_ending::Detected,
+}
+
+impl DiskStamp {
+    fn of(path: &Path, hash: u64, line_endings: line_ending::Detected) -> Self {
+        let meta = fs::metadata(path).ok();
+        Self {
+            modified: meta.as_ref().and_then(|m| m.modified().ok()),
+            len: meta.map_or(0, |m| m.len()),
+            hash,
+            line_endings,
+        }
+    }
+}
+
+/// Версия файла на диске: текст через `\n` и окончания строк в самом файле.
+pub struct DiskVersion {
+    pub text: String,
+    pub line_endings: line_ending::Detected,
+}
+
+fn title_for(path: &Path) -> String {
+    path.file_name()
+        .and_then(|s| s.to_str())
+        .unwrap_or("Документ")
+        .to_string()
+}
+
+/// Содержимое файла, приведённое к внутреннему виду.
+struct Loaded {
+    text: String,
+    encoding: TextEncoding,
+    utf16_bom: bool,
+    line_endings: line_ending::Detected,
+}
+
+/// Читает файл, перекодирует его в строку и приводит окончания строк к `\n`.
+fn read_file(path: &Path, encoding: Option<TextEncoding>) -> io::Result<Loaded> {
+    let bytes = fs::read(path)?;
+    let encoding = encoding.unwrap_or_else(|| encoding::detect(&bytes));
+    let utf16_bom = encoding::utf16_bom(&bytes, encoding);
+    let text = encoding::decode(&bytes, encoding)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    let line_endings = line_ending::detect(&text);
+    let text = match line_ending::normalize(&text) {
+        Cow::Borrowed(_) => text,
+        Cow::Owned(normalized) => normalized,
+    };
+    Ok(Loaded {
+        text,
+        encoding,
+        utf16_bom,
+        line_endings,
+    })
+}
+
+/// Текст файла таким, каким его откроет редактор: в определённой
+/// кодировке и со строками через `\n`.
+pub fn read_text(path: &Path) -> io::Result<String> {
+    read_file(path, None).map(|loaded| loaded.text)
+}
 
 pub struct Document {
     pub id: usize,
     pub path: Option<PathBuf>,
     pub title: String,
-    pub text: String,
-    undo_stack: Vec<String>,
-    redo_stack: Vec<String>,
+    text: Rope,
+    view: View,
+    selection: Selection,
+    /// Дополнительные курсоры, кроме основного `selection`, по порядку в тексте.
+    cursors: Vec<Selection>,
+    /// Где ищем и заменяем «В выделении». Сдвигается правками, как курсоры.
+    search_scope: Option<Range<usize>>,
+    /// Растёт при каждой правке — по нему кэшируется раскладка текста.
+    revision: u64,
+    history: History,
+    disk: DiskStamp,
+    /// Кодировка файла на диске.
+    encoding: TextEncoding,
+    /// Был ли BOM у файла в UTF-16: файл без него так и сохраняем.
+    utf16_bom: bool,
+    /// Окончания строк файла на диске; в `text` строки всегда через `\n`.
+    line_ending: LineEnding,
+    /// В файле были разные окончания строк — при сохранении они станут одинаковыми.
+    mixed_line_endings: bool,
+    /// Текст файла на момент `disk` — общий предок при слиянии с чужими правками.
+    base: Rope,
+    /// Язык подсветки и кэш состояний лексера по строкам.
+    syntax: Highlighter,
+    indent: Indent,
     pub dirty: bool,
 }
 
@@ -17,82 +177,1345 @@ impl Document {
             id,
             path: None,
             title: format!("Безымянный {}", id),
-            text: String::new(),
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            text: Rope::new(),
+            view: View::default(),
+            selection: Selection::default(),
+            cursors: Vec::new(),
+            search_scope: None,
+            revision: 0,
+            history: History::new(),
+            disk: DiskStamp::default(),
+            base: Rope::new(),
+            encoding: TextEncoding::default(),
+            utf16_bom: true,
+            line_ending: LineEnding::default(),
+            mixed_line_endings: false,
+            syntax: Highlighter::default(),
+            indent: Indent::default(),
             dirty: false,
         }
     }
 
-    pub fn from_file(id: usize, path: PathBuf) -> std::io::Result<Self> {
-        let text = fs::read_to_string(&path)?;
+    /// Безымянный документ с уже набранным текстом (из прошлой сессии).
+    pub fn untitled_with_text(id: usize, text: &str) -> Self {
+        let mut doc = Self::new_untitled(id);
+        doc.text = Rope::from_str(text);
+        doc.dirty = !text.is_empty();
+        doc
+    }
 
-        let title = path
-            .file_name()
-            .and_then(|s| s.to_str())
-            .unwrap_or("Документ")
-            .to_string();
+    /// Открывает файл. Без явной кодировки она определяется по содержимому.
+    pub fn from_file(id: usize, path: PathBuf, encoding: Option<TextEncoding>) -> io::Result<Self> {
+        let Loaded {
+            text,
+            encoding,
+            utf16_bom,
+            line_endings,
+        } = read_file(&path, encoding)?;
 
+        let title = title_for(&path);
+        let language = Language::from_path(&path);
+        let rope = Rope::from_str(&text);
+        let indent = editorconfig::indent_for(&path).resolve(Indent::detect(&rope));
         Ok(Self {
             id,
+            disk: DiskStamp::of(&path, fnv64(text.as_bytes()), line_endings),
             path: Some(path),
             title,
-            text,
-            undo_stack: Vec::new(),
-            redo_stack: Vec::new(),
+            base: rope.clone(),
+            text: rope,
+            view: View::default(),
+            selection: Selection::default(),
+            cursors: Vec::new(),
+            search_scope: None,
+            revision: 0,
+            history: History::new(),
+            encoding,
+            utf16_bom,
+            line_ending: line_endings.dominant,
+            mixed_line_endings: line_endings.mixed,
+            syntax: Highlighter::new(language),
+            indent,
             dirty: false,
         })
     }
 
-    pub fn save(&mut self) -> std::io::Result<()> {
+    pub fn save(&mut self, backup: BackupMode) -> io::Result<()> {
         if let Some(path) = &self.path {
-            fs::write(path, &self.text)?;
+            let text = self.text.to_string();
+            let text = line_ending::apply(&text, self.line_ending);
+            let bytes = encoding::encode(&text, self.encoding, self.utf16_bom)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            file_io::write_atomic(path, backup, |out| out.write_all(&bytes))?;
+            self.disk = DiskStamp::of(path, self.content_hash(), line_ending::detect(&text));
+            self.base = self.text.clone();
+            self.mixed_line_endings = false;
             self.dirty = false;
         }
         Ok(())
     }
 
-    pub fn save_as(&mut self, path: PathBuf) -> std::io::Result<()> {
+    pub fn save_as(&mut self, path: PathBuf, backup: BackupMode) -> io::Result<()> {
+        self.title = title_for(&path);
+        self.syntax.set_language(Language::from_path(&path));
+        self.indent = editorconfig::indent_for(&path).resolve(Some(self.indent));
         self.path = Some(path);
-        self.save()
+        self.save(backup)
+    }
+
+    pub fn encoding(&self) -> TextEncoding {
+        self.encoding
+    }
+
+    /// Сохраняет в другой кодировке, с `path` — как «Сохранить как».
+    /// Если текст в ней не записать, кодировка остаётся прежней.
+    pub fn save_with_encoding(
+        &mut self,
+        encoding: TextEncoding,
+        path: Option<PathBuf>,
+        backup: BackupMode,
+    ) -> io::Result<()> {
+        let previous = std::mem::replace(&mut self.encoding, encoding);
+        // В другую кодировку пишем с BOM, без него остаётся лишь прежний UTF-16.
+        let previous_bom = self.utf16_bom;
+        self.utf16_bom |= previous != encoding;
+        let result = match path {
+            Some(path) => self.save_as(path, backup),
+            None => self.save(backup),
+        };
+        if result.is_err() {
+            self.encoding = previous;
+            self.utf16_bom = previous_bom;
+        }
+        result
+    }
+
+    /// Перечитывает файл в указанной кодировке. Это обычная правка:
+    /// её можно отменить.
+    pub fn reopen_with_encoding(&mut self, encoding: TextEncoding) -> io::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let loaded = read_file(path, Some(encoding))?;
+        self.encoding = loaded.encoding;
+        self.utf16_bom = loaded.utf16_bom;
+        self.reload(&DiskVersion {
+            text: loaded.text,
+            line_endings: loaded.line_endings,
+        });
+        Ok(())
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    pub fn indent(&self) -> Indent {
+        self.indent
+    }
+
+    pub fn set_indent(&mut self, indent: Indent) {
+        self.indent = indent;
+    }
+
+    pub fn language(&self) -> Language {
+        self.syntax.language()
+    }
+
+    /// Язык, если он не тот, что определился бы по имени файла.
+    pub fn language_override(&self) -> Option<Language> {
+        let detected = self
+            .path
+            .as_deref()
+            .map_or(Language::PlainText, Language::from_path);
+        (self.language() != detected).then_some(self.language())
+    }
+
+    /// Подсветка выбрана вручную — до следующего «Сохранить как».
+    pub fn set_language(&mut self, language: Language) {
+        self.syntax.set_language(language);
+    }
+
+    /// Состояние подсветки в начале строки `line`: с него раскрашивается
+    /// видимая часть текста.
+    pub fn syntax_state(&mut self, line: usize) -> State {
+        self.syntax.state_at(&self.text, line)
+    }
+
+    pub fn mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+
+    /// Переводит весь документ на окончания `ending`; в файл они попадут
+    /// при сохранении. Перевод можно отменить.
+    pub fn set_line_ending(&mut self, ending: LineEnding) {
+        if ending != self.line_ending || self.mixed_line_endings {
+            let change = LineEndingChange {
+                from: self.line_ending,
+                from_mixed: self.mixed_line_endings,
+                to: ending,
+            };
+            self.history.record_line_ending(change, self.selection);
+            self.switch_line_ending(ending, false);
+        }
+    }
+
+    fn switch_line_ending(&mut self, ending: LineEnding, mixed: bool) {
+        self.line_ending = ending;
+        self.mixed_line_endings = mixed;
+        self.revision += 1;
+        self.dirty = true;
+    }
+
+    /// Проверяет, не изменил ли файл кто-то другой. Возвращает версию
+    /// с диска, если она отличается от той, что мы читали или писали, —
+    /// в том числе только окончаниями строк.
+    pub fn check_disk(&mut self) -> io::Result<Option<DiskVersion>> {
+        let Some(path) = &self.path else {
+            return Ok(None);
+        };
+        let meta = fs::metadata(path)?;
+        if meta.modified().ok() == self.disk.modified && meta.len() == self.disk.len {
+            return Ok(None);
+        }
+
+        let loaded = read_file(path, Some(self.encoding))?;
+        let hash = fnv64(loaded.text.as_bytes());
+        if hash == self.disk.hash && loaded.line_endings == self.disk.line_endings {
+            // Файл "потрогали", но содержимое то же.
+            self.disk = DiskStamp::of(path, hash, loaded.line_endings);
+            return Ok(None);
+        }
+        Ok(Some(DiskVersion {
+            text: loaded.text,
+            line_endings: loaded.line_endings,
+        }))
+    }
+
+    /// Заменяет текст версией с диска и берёт её окончания строк.
+    /// Перезагрузку текста можно отменить.
+    pub fn reload(&mut self, disk: &DiskVersion) {
+        self.set_text(&disk.text);
+        self.line_ending = disk.line_endings.dominant;
+        self.mixed_line_endings = disk.line_endings.mixed;
+        self.revision += 1;
+        self.accept_disk(disk);
+    }
+
+    /// Запоминает версию с диска, не трогая текст: при сохранении
+    /// она будет перезаписана.
+    pub fn accept_disk(&mut self, disk: &DiskVersion) {
+        if let Some(path) = &self.path {
+            self.disk = DiskStamp::of(path, fnv64(disk.text.as_bytes()), disk.line_endings);
+        }
+        self.base = Rope::from_str(&disk.text);
+        let same_endings = disk.line_endings.dominant == self.line_ending
+            && disk.line_endings.mixed == self.mixed_line_endings;
+        self.dirty = self.content_hash() != self.disk.hash || !same_endings;
+    }
+
+    /// Сливает свои несохранённые правки с версией с диска.
+    /// Возвращает число конфликтов — они остаются в тексте с маркерами.
+    pub fn merge_with_disk(&mut self, disk: &DiskVersion) -> usize {
+        let merged = merge::merge3(
+            &self.base.to_string(),
+            &self.text.to_string(),
+            &disk.text,
+            "мои правки",
+            "на диске",
+        );
+        self.set_text(&merged.text);
+        self.accept_disk(disk);
+        merged.conflicts
+    }
+
+    pub fn text(&self) -> &Rope {
+        &self.text
+    }
+
+    /// Хэш текста — по нему сверяем сохранённые данные с содержимым файла.
+    pub fn content_hash(&self) -> u64 {
+        let mut hasher = Fnv64::default();
+        for chunk in self.text.chunks() {
+            hasher.write(chunk.as_bytes());
+        }
+        hasher.finish()
+    }
+
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    pub fn selection(&self) -> Selection {
+        self.selection
+    }
+
+    pub fn search_scope(&self) -> Option<Range<usize>> {
+        self.search_scope.clone()
+    }
+
+    pub fn set_search_scope(&mut self, scope: Range<usize>) {
+        self.search_scope = Some(scope);
+    }
+
+    pub fn set_selection(&mut self, selection: Selection) {
+        self.selection = self.clamp(selection);
+        self.history.settle_selection(self.selection);
+    }
+
+    /// Дополнительные курсоры, без основного.
+    pub fn extra_cursors(&self) -> &[Selection] {
+        &self.cursors
+    }
+
+    /// Все курсоры по порядку в тексте и номер основного среди них.
+    pub fn all_cursors(&self) -> (Vec<Selection>, usize) {
+        let start = self.selection.range().start;
+        let primary = self.cursors.partition_point(|c| c.range().start < start);
+        let mut all = self.cursors.clone();
+        all.insert(primary, self.selection);
+        (all, primary)
+    }
+
+    /// Задаёт все курсоры сразу. Пересекающиеся выделения сливаются.
+    pub fn set_cursors(&mut self, primary: Selection, extra: Vec<Selection>) {
+        let mut all: Vec<(Selection, bool)> =
+            extra.into_iter().map(|c| (self.clamp(c), false)).collect();
+        all.push((self.clamp(primary), true));
+        all.sort_by_key(|(c, _)| (c.range().start, c.range().end));
+
+        let mut merged: Vec<(Selection, bool)> = Vec::with_capacity(all.len());
+        for (cursor, is_primary) in all {
+            match merged.last_mut() {
+                Some((last, last_primary))
+                    if cursor.range().start < last.range().end
+                        || cursor.range() == last.range() =>
+                {
+                    let end = cursor.range().end;
+                    if end > last.range().end {
+                        if last.head >= last.anchor {
+                            last.head = end;
+                        } else {
+                            last.anchor = end;
+                        }
+                    }
+                    *last_primary |= is_primary;
+                }
+                _ => merged.push((cursor, is_primary)),
+            }
+        }
+
+        let primary = merged.iter().position(|(_, p)| *p).unwrap_or(0);
+        self.selection = merged.remove(primary).0;
+        self.cursors = merged.into_iter().map(|(c, _)| c).collect();
+        self.history.settle_selection(self.selection);
+    }
+
+    pub fn clear_cursors(&mut self) {
+        self.cursors.clear();
+    }
+
+    /// Правка во всех курсорах одним шагом отмены; набор подряд сливается
+    /// в один шаг, как и с одним курсором. `edit` получает текст, номер
+    /// курсора по порядку и его выделение и возвращает, что чем заменить.
+    /// Курсор встаёт после вставленного.
+    pub fn edit_cursors(
+        &mut self,
+        mut edit: impl FnMut(&Rope, usize, Selection) -> (Range<usize>, String),
+    ) {
+        if self.cursors.is_empty() {
+            // Один курсор — обычная правка: она сливается с набором вокруг.
+            let (range, text) = edit(&self.text, 0, self.selection);
+            let pos = range.start + text.chars().count();
+            self.replace(range, &text);
+            self.set_selection(Selection {
+                anchor: pos,
+                head: pos,
+            });
+            return;
+        }
+        let extra = std::mem::take(&mut self.cursors);
+        self.set_cursors(self.selection, extra);
+        let (mut all, primary) = self.all_cursors();
+        self.history.begin_typing_group();
+        // С конца: правка не сдвигает курсоры перед ней.
+        for i in (0..all.len()).rev() {
+            let (range, text) = edit(&self.text, i, all[i]);
+            let inserted = text.chars().count();
+            self.replace(range.clone(), &text);
+            for later in &mut all[i + 1..] {
+                later.map_through(&range, inserted);
+            }
+            let pos = range.start + inserted;
+            all[i] = Selection {
+                anchor: pos,
+                head: pos,
+            };
+        }
+        self.history.end_group();
+        let main = all.remove(primary);
+        self.set_cursors(main, all);
+    }
+
+    /// Двигает все курсоры; сошедшиеся сливаются.
+    pub fn move_cursors(&mut self, step: impl Fn(&Rope, Selection) -> Selection) {
+        let primary = step(&self.text, self.selection);
+        let extra = self.cursors.iter().map(|&c| step(&self.text, c)).collect();
+        self.set_cursors(primary, extra);
+    }
+
+    /// Ctrl+D: следующее вхождение выделенного текста получает свой курсор.
+    /// Без выделения сначала выделяется слово под курсором.
+    pub fn add_next_occurrence(&mut self) {
+        let range = self.selection.range();
+        if range.is_empty() {
+            let word = self.word_at(range.start);
+            if !word.is_empty() {
+                self.set_selection(Selection {
+                    anchor: word.start,
+                    head: word.end,
+                });
+            }
+            return;
+        }
+
+        let needle = self.text.slice(range.clone()).to_string();
+        let text = self.text.to_string();
+        let from = self.text.char_to_byte(range.end);
+        let (all, _) = self.all_cursors();
+        let taken = |start: usize| all.iter().any(|c| c.range().start == start);
+        let next = text[from..]
+            .match_indices(&needle)
+            .map(|(i, _)| from + i)
+            .chain(text[..from].match_indices(&needle).map(|(i, _)| i))
+            .map(|byte| self.text.byte_to_char(byte))
+            .find(|&start| !taken(start));
+        if let Some(start) = next {
+            let mut extra = std::mem::take(&mut self.cursors);
+            extra.push(self.selection);
+            let found = Selection {
+                anchor: start,
+                head: start + range.len(),
+            };
+            self.set_cursors(found, extra);
+        }
+    }
+
+    /// Enter: перевод строки с автоотступом в каждом курсоре.
+    pub fn newline(&mut self) {
+        let (indent, language) = (self.indent, self.language());
+        self.edit_cursors(|text, _, selection| {
+            let range = selection.range();
+            (
+                range.clone(),
+                indent::line_break(text, range.start, indent, language),
+            )
+        });
+    }
+
+    /// Tab и Shift+Tab. Выделение на несколько строк сдвигается целиком,
+    /// иначе Tab вставляет отступ в каждом курсоре.
+    pub fn tab(&mut self, outdent: bool) {
+        let (all, _) = self.all_cursors();
+        let multiline = all.iter().any(|c| {
+            self.text.char_to_line(c.range().start) != self.text.char_to_line(c.range().end)
+        });
+        if outdent || multiline {
+            self.shift_lines(outdent);
+            return;
+        }
+        let indent = self.indent;
+        self.edit_cursors(|text, _, selection| {
+            let range = selection.range();
+            let column = range.start - text.line_to_char(text.char_to_line(range.start));
+            (range, indent.fill(column))
+        });
+    }
+
+    /// Добавляет или снимает уровень отступа у строк всех курсоров.
+    /// Пустые строки отступа не получают.
+    fn shift_lines(&mut self, outdent: bool) {
+        let (all, _) = self.all_cursors();
+        let mut lines: Vec<usize> = all
+            .iter()
+            .flat_map(|cursor| {
+                let (first, last) = self.lines_of(cursor.range());
+                first..=last
+            })
+            .collect();
+        lines.dedup();
+        let indent = self.indent;
+        self.edit_group(|doc| {
+            for &line in lines.iter().rev() {
+                let start = doc.text.line_to_char(line);
+                let content: String = doc.text.line(line).chars().collect();
+                if outdent {
+                    doc.remove(start..start + indent::outdent_len(&content, indent));
+                } else if !content.trim().is_empty() {
+                    doc.insert(start, &indent.unit());
+                }
+            }
+        });
+    }
+
+    /// Переписывает ведущие отступы всего текста табуляцией или пробелами
+    /// и делает этот отступ отступом документа. Одним шагом отмены.
+    pub fn convert_indentation(&mut self, tabs: bool) {
+        let width = self.indent.width();
+        let to = if tabs {
+            Indent::Tabs
+        } else {
+            Indent::Spaces(width as u8)
+        };
+        self.edit_group(|doc| {
+            for line in (0..doc.text.len_lines()).rev() {
+                let start = doc.text.line_to_char(line);
+                let leading: String = doc
+                    .text
+                    .line(line)
+                    .chars()
+                    .take_while(|&c| c == ' ' || c == '\t')
+                    .collect();
+                let rewritten = to.rewrite(&leading, width);
+                if rewritten != leading {
+                    doc.replace(start..start + leading.chars().count(), &rewritten);
+                }
+            }
+        });
+        self.indent = to;
+    }
+
+    /// Команда над строками выделения или строкой с курсором, одним шагом отмены.
+    pub fn line_command(&mut self, command: LineCommand) {
+        self.cursors.clear();
+        let (first, last) = self.selected_lines();
+        // Пустая строка после последнего перевода строки — не строка текста.
+        let len_lines = self.text.len_lines();
+        let count = if len_lines > 1 && self.text.line(len_lines - 1).len_chars() == 0 {
+            len_lines - 1
+        } else {
+            len_lines
+        };
+        let (first, last) = match command {
+            LineCommand::MoveUp if first == 0 => return,
+            LineCommand::MoveUp => (first - 1, last),
+            LineCommand::MoveDown if last + 1 >= count => return,
+            LineCommand::MoveDown => (first, last + 1),
+            LineCommand::Join if first == last && last + 1 < count => (first, last + 1),
+            _ => (first, last),
+        };
+
+        let start = self.text.line_to_char(first);
+        let end = if last + 1 < len_lines {
+            self.text.line_to_char(last + 1)
+        } else {
+            self.text.len_chars()
+        };
+        let block = self.text.slice(start..end).to_string();
+        let body = block.strip_suffix('\n').unwrap_or(&block);
+        let newline = if body.len() < block.len() { "\n" } else { "" };
+        let mut lines: Vec<&str> = body.split('\n').collect();
+        let line_len = |line: &str| line.chars().count() as isize + 1;
+        let selection = self.selection;
+        let moved = |by: isize| Selection {
+            anchor: selection.anchor.saturating_add_signed(by),
+            head: selection.head.saturating_add_signed(by),
+        };
+
+        let (range, text, after) = match command {
+            LineCommand::Duplicate => (
+                start..start,
+                format!("{body}\n"),
+                Some(moved(line_len(body))),
+            ),
+            LineCommand::Delete if newline.is_empty() && start > 0 => {
+                (start - 1..end, String::new(), None)
+            }
+            LineCommand::Delete => (start..end, String::new(), None),
+            LineCommand::MoveUp => {
+                let by = -line_len(lines[0]);
+                lines.rotate_left(1);
+                (start..end, lines.join("\n") + newline, Some(moved(by)))
+            }
+            LineCommand::MoveDown => {
+                let by = line_len(lines[lines.len() - 1]);
+                lines.rotate_right(1);
+                (start..end, lines.join("\n") + newline, Some(moved(by)))
+            }
+            _ => {
+                let result = line_commands::transform(command, &lines).join("\n");
+                let after = if selection.range().is_empty() {
+                    let column = (selection.head - start)
+                        .min(result.lines().next().map_or(0, |l| l.chars().count()));
+                    let pos = start + column;
+                    Selection {
+                        anchor: pos,
+                        head: pos,
+                    }
+                } else {
+                    Selection {
+                        anchor: start,
+                        head: start + result.chars().count(),
+                    }
+                };
+                (start..end, result + newline, Some(after))
+            }
+        };
+        if text == self.text.slice(range.clone()) {
+            return;
+        }
+
+        self.edit_group(|doc| doc.replace(range, &text));
+        let after = after.unwrap_or_else(|| {
+            // После удаления курсор — в начале строки, вставшей на место удалённых.
+            let line = first.min(self.text.len_lines() - 1);
+            let pos = self.text.line_to_char(line);
+            Selection {
+                anchor: pos,
+                head: pos,
+            }
+        });
+        self.set_selection(after);
+    }
+
+    /// Первая и последняя строки выделения. Выделение, которое кончается
+    /// в начале строки, эту строку не захватывает.
+    pub fn selected_lines(&self) -> (usize, usize) {
+        self.lines_of(self.selection.range())
+    }
+
+    fn lines_of(&self, range: Range<usize>) -> (usize, usize) {
+        let first = self.text.char_to_line(range.start);
+        let last = self.text.char_to_line(range.end);
+        if last > first && self.text.line_to_char(last) == range.end {
+            (first, last - 1)
+        } else {
+            (first, last)
+        }
     }
 
-    /// Устанавливаем новый текст с поддержкой undo/redo
-    pub fn set_text(&mut self, new_text: String) {
-        if new_text != self.text {
-            self.undo_stack.push(self.text.clone());
-            self.redo_stack.clear();
-            self.text = new_text;
-            self.dirty = true;
+    /// Слово (буквы, цифры, `_`) вокруг позиции `pos`.
+    fn word_at(&self, pos: usize) -> Range<usize> {
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+        let start = pos
+            - self
+                .text
+                .chars_at(pos)
+                .reversed()
+                .take_while(|&c| is_word(c))
+                .count();
+        let end = pos + self.text.chars_at(pos).take_while(|&c| is_word(c)).count();
+        start..end
+    }
+
+    fn clamp(&self, selection: Selection) -> Selection {
+        let len = self.text.len_chars();
+        Selection {
+            anchor: selection.anchor.min(len),
+            head: selection.head.min(len),
+        }
+    }
+
+    pub fn history(&self) -> &History {
+        &self.history
+    }
+
+    /// Подставляет сохранённую историю; `false`, если она испорчена.
+    pub fn restore_history(&mut self, data: HistoryData) -> bool {
+        self.history.restore(data)
+    }
+
+    /// Сколько байт может занимать история отмены.
+    pub fn set_undo_budget(&mut self, bytes: usize) {
+        self.history.set_budget(bytes);
+    }
+
+    /// Все правки внутри `f` отменяются одним шагом.
+    pub fn edit_group<R>(&mut self, f: impl FnOnce(&mut Self) -> R) -> R {
+        self.history.begin_group();
+        let result = f(self);
+        self.history.end_group();
+        result
+    }
+
+    /// Слишком большой документ, чтобы отдавать виджету весь текст целиком.
+    pub fn is_large(&self) -> bool {
+        self.text.len_chars() > LARGE_FILE_CHARS || self.text.len_lines() > LARGE_FILE_LINES
+    }
+
+    /// Диапазон символов, который сейчас лежит в окне просмотра.
+    pub fn view_range(&self) -> Range<usize> {
+        self.view.range.clone()
+    }
+
+    /// Текст окна просмотра (см. [`Document::prepare_view`]).
+    pub fn view_text(&self) -> &str {
+        &self.view.text
+    }
+
+    /// Готовит окно просмотра на диапазон `range` символов.
+    /// Если окно уже совпадает и не устарело, ничего не копируется.
+    pub fn prepare_view(&mut self, range: Range<usize>) {
+        let len = self.text.len_chars();
+        let range = range.start.min(len)..range.end.min(len);
+        if !self.view.valid || self.view.range != range {
+            self.view.text = self.text.slice(range.clone()).to_string();
+            self.view.range = range;
+            self.view.valid = true;
         }
     }
 
+    /// Вставка текста в позицию `char_idx`.
+    pub fn insert(&mut self, char_idx: usize, text: &str) {
+        self.replace(char_idx..char_idx, text);
+    }
+
+    /// Удаление диапазона символов.
+    pub fn remove(&mut self, range: Range<usize>) {
+        self.replace(range, "");
+    }
+
+    /// Замена диапазона символов на `text` — единственная точка правки текста.
+    pub fn replace(&mut self, range: Range<usize>, text: &str) {
+        if range.is_empty() && text.is_empty() {
+            return;
+        }
+
+        let edit = Edit {
+            at: self.text.char_to_byte(range.start),
+            deleted: self.text.slice(range.clone()).to_string(),
+            inserted: text.to_string(),
+        };
+        self.history.record(edit, self.selection);
+        self.splice(range, text);
+    }
+
+    /// Применяет правку из истории, заданную в байтах.
+    fn apply_bytes(&mut self, at: usize, old_len: usize, text: &str) {
+        let range = self.text.byte_to_char(at)..self.text.byte_to_char(at + old_len);
+        self.splice(range, text);
+    }
+
+    /// Правка текста без записи в историю.
+    fn splice(&mut self, range: Range<usize>, text: &str) {
+        let inserted = text.chars().count();
+        self.update_view(&range, text, inserted);
+        self.syntax
+            .invalidate_from(self.text.char_to_line(range.start));
+
+        self.text.remove(range.clone());
+        self.text.insert(range.start, text);
+        self.selection.map_through(&range, inserted);
+        for cursor in &mut self.cursors {
+            cursor.map_through(&range, inserted);
+        }
+        if let Some(scope) = &mut self.search_scope {
+            let mut mapped = Selection {
+                anchor: scope.start,
+                head: scope.end,
+            };
+            mapped.map_through(&range, inserted);
+            *scope = mapped.range();
+        }
+
+        self.revision += 1;
+        self.dirty = true;
+    }
+
+    /// Переносит правку в окно просмотра, не пересобирая его.
+    fn update_view(&mut self, range: &Range<usize>, text: &str, inserted: usize) {
+        let view = &mut self.view;
+        if !view.valid {
+            return;
+        }
+
+        let removed = range.end - range.start;
+        if range.start >= view.range.start && range.end <= view.range.end {
+            let start = char_to_byte_idx(&view.text, range.start - view.range.start);
+            let end = char_to_byte_idx(&view.text, range.end - view.range.start);
+            view.text.replace_range(start..end, text);
+            view.range.end = view.range.end - removed + inserted;
+        } else if range.end <= view.range.start {
+            view.range.start = view.range.start - removed + inserted;
+            view.range.end = view.range.end - removed + inserted;
+        } else if range.start < view.range.end {
+            view.valid = false;
+        }
+    }
+
+    /// Устанавливаем новый текст одним шагом отмены.
+    /// Заменяется только отличающаяся середина, общие начало и конец не трогаем.
+    pub fn set_text(&mut self, new_text: &str) {
+        let old_text = self.text.to_string();
+        if old_text == new_text {
+            return;
+        }
+
+        let prefix = old_text
+            .chars()
+            .zip(new_text.chars())
+            .take_while(|(a, b)| a == b)
+            .count();
+        let old_len = old_text.chars().count();
+        let new_len = new_text.chars().count();
+        let suffix = old_text
+            .chars()
+            .rev()
+            .zip(new_text.chars().rev())
+            .take_while(|(a, b)| a == b)
+            .count()
+            .min(old_len - prefix)
+            .min(new_len - prefix);
+
+        let middle: String = new_text
+            .chars()
+            .skip(prefix)
+            .take(new_len - prefix - suffix)
+            .collect();
+        self.edit_group(|doc| doc.replace(prefix..old_len - suffix, &middle));
+    }
+
+    /// Отменяет последний шаг и возвращает курсор туда, где он был до него.
     pub fn undo(&mut self) {
-        if let Some(prev) = self.undo_stack.pop() {
-            self.redo_stack.push(self.text.clone());
-            self.text = prev;
-            self.dirty = true;
+        if let Some(id) = self.history.undo_step() {
+            self.revert_node(id);
         }
     }
 
     pub fn redo(&mut self) {
-        if let Some(next) = self.redo_stack.pop() {
-            self.undo_stack.push(self.text.clone());
-            self.text = next;
-            self.dirty = true;
+        if let Some(id) = self.history.redo_step() {
+            self.apply_node(id);
+        }
+    }
+
+    /// Переход к любому состоянию дерева отмены.
+    pub fn jump_to(&mut self, target: NodeId) {
+        let Some((revert, apply)) = self.history.path_to(target) else {
+            return;
+        };
+        for id in revert {
+            self.revert_node(id);
+        }
+        for id in apply {
+            self.apply_node(id);
         }
+        self.history.set_current(target);
     }
 
-    /// Глобальная замена подстроки.
-    /// Возвращает, сколько вхождений было заменено.
-    pub fn replace_all(&mut self, needle: &str, replacement: &str) -> usize {
-        if needle.is_empty() {
-            return 0;
+    /// Текст в состоянии `target` дерева отмены; сам документ не меняется.
+    pub fn text_at(&self, target: NodeId) -> Option<Rope> {
+        let (revert, apply) = self.history.path_to(target)?;
+        let mut text = self.text.clone();
+        let mut splice = |at: usize, old_len: usize, new_text: &str| {
+            let start = text.byte_to_char(at);
+            text.remove(start..text.byte_to_char(at + old_len));
+            text.insert(start, new_text);
+        };
+        for id in revert {
+            for edit in self.history.transaction(id).edits.iter().rev() {
+                splice(edit.at, edit.inserted.len(), &edit.deleted);
+            }
         }
-        let count = self.text.matches(needle).count();
+        for id in apply {
+            for edit in &self.history.transaction(id).edits {
+                splice(edit.at, edit.deleted.len(), &edit.inserted);
+            }
+        }
+        Some(text)
+    }
+
+    fn revert_node(&mut self, id: NodeId) {
+        let tx = self.history.take_transaction(id);
+        for edit in tx.edits.iter().rev() {
+            self.apply_bytes(edit.at, edit.inserted.len(), &edit.deleted);
+        }
+        if let Some(change) = tx.line_ending {
+            self.switch_line_ending(change.from, change.from_mixed);
+        }
+        self.selection = self.clamp(tx.selection_before);
+        self.cursors.clear();
+        self.history.put_transaction(id, tx);
+    }
+
+    fn apply_node(&mut self, id: NodeId) {
+        let tx = self.history.take_transaction(id);
+        for edit in &tx.edits {
+            self.apply_bytes(edit.at, edit.deleted.len(), &edit.inserted);
+        }
+        if let Some(change) = tx.line_ending {
+            self.switch_line_ending(change.to, false);
+        }
+        self.selection = self.clamp(tx.selection_after);
+        self.cursors.clear();
+        self.history.put_transaction(id, tx);
+    }
+
+    /// Заменяет все совпадения `query` в диапазоне символов `within` одним
+    /// шагом отмены. Возвращает, сколько вхождений было заменено.
+    /// Совпадения `query` в диапазоне символов `within`. Ищем в самом
+    /// диапазоне: для `^`, `$` и `\b` его края — края текста.
+    pub fn find_matches(&self, query: &Query, within: Range<usize>) -> Vec<Range<usize>> {
+        query
+            .find_chars(&self.text.slice(within.clone()).to_string())
+            .into_iter()
+            .map(|found| found.start + within.start..found.end + within.start)
+            .collect()
+    }
+
+    /// Заменяет одно совпадение из [`Document::find_matches`] по той же
+    /// области `within`. Возвращает конец замены или `None`, если образец
+    /// там больше не совпадает.
+    pub fn replace_match(
+        &mut self,
+        query: &Query,
+        template: &str,
+        within: Range<usize>,
+        found: Range<usize>,
+    ) -> Option<usize> {
+        // Проверки `^`, `$` и `\b` смотрят лишь на соседние символы, так что
+        // хватит строк совпадения, обрезанных по области.
+        let text = &self.text;
+        let last_line = (text.char_to_line(found.end) + 1).min(text.len_lines());
+        let context = text
+            .line_to_char(text.char_to_line(found.start))
+            .max(within.start)..text.line_to_char(last_line).min(within.end);
+        let slice = text.slice(context.clone());
+        let bytes = slice.char_to_byte(found.start - context.start)
+            ..slice.char_to_byte(found.end - context.start);
+        let replacement = query.replacement_for(&slice.to_string(), bytes, template)?;
+        self.replace(found.clone(), &replacement);
+        Some(found.start + replacement.chars().count())
+    }
+
+    pub fn replace_all(&mut self, query: &Query, replacement: &str, within: Range<usize>) -> usize {
+        let (replaced, count) =
+            query.replace_all(&self.text.slice(within.clone()).to_string(), replacement);
         if count > 0 {
-            self.set_text(self.text.replace(needle, replacement));
+            let mut text = self.text.slice(..within.start).to_string();
+            text.push_str(&replaced);
+            text.push_str(&self.text.slice(within.end..).to_string());
+            self.set_text(&text);
+            // Правка на самой границе области сдвигом не различить: область
+            // поиска, в которой заменяли, — это весь заменённый кусок.
+            if self.search_scope.as_ref() == Some(&within) {
+                self.search_scope = Some(within.start..within.start + replaced.chars().count());
+            }
         }
         count
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::line_commands::SortOrder;
+    use crate::search::SearchOptions;
+
+    fn document_with(text: &str) -> Document {
+        let mut doc = Document::new_untitled(1);
+        doc.insert(0, text);
+        doc
+    }
+
+    #[test]
+    fn external_changes_merge_with_unsaved_edits() {
+        let path = std::env::temp_dir().join(format!("rte-external-{}.txt", std::process::id()));
+        fs::write(&path, "один\nдва\nтри\n").unwrap();
+        let mut doc = Document::from_file(1, path.clone(), None).unwrap();
+        assert!(doc.check_disk().unwrap().is_none());
+
+        doc.insert(0, "ноль\n");
+        // Длина другая, так что изменение заметно и при той же mtime.
+        fs::write(&path, "один\nдва\nтри\nчетыре\n").unwrap();
+        let disk = doc.check_disk().unwrap().expect("изменение на диске");
+
+        assert_eq!(doc.merge_with_disk(&disk), 0);
+        assert_eq!(doc.text().to_string(), "ноль\nодин\nдва\nтри\nчетыре\n");
+        assert!(doc.dirty);
+        assert!(doc.check_disk().unwrap().is_none());
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_file_encoding_on_save() {
+        let path = std::env::temp_dir().join(format!("rte-cp1251-{}.txt", std::process::id()));
+        let bytes = encoding::encode("Привет, мир\n", TextEncoding::Windows1251, true).unwrap();
+        fs::write(&path, &bytes).unwrap();
+
+        let mut doc = Document::from_file(1, path.clone(), None).unwrap();
+        assert_eq!(doc.encoding(), TextEncoding::Windows1251);
+        assert_eq!(doc.text().to_string(), "Привет, мир\n");
+        doc.save(BackupMode::None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        doc.save_with_encoding(TextEncoding::Utf8, None, BackupMode::None)
+            .unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "Привет, мир\n");
+        fs::remove_file(&path).unwrap();
+
+        // Безымянный документ сохраняется как «Сохранить как».
+        let path = path.with_extension("rs");
+        let mut doc = Document::untitled_with_text(2, "fn main() {}\n");
+        doc.save_with_encoding(
+            TextEncoding::Windows1251,
+            Some(path.clone()),
+            BackupMode::None,
+        )
+        .unwrap();
+        assert_eq!(doc.title, title_for(&path));
+        assert_eq!(doc.language(), Language::from_path(&path));
+        assert_eq!(doc.encoding(), TextEncoding::Windows1251);
+        fs::remove_file(&path).unwrap();
+
+        // UTF-16 без BOM так и остаётся без него, пока кодировку не сменят.
+        let path = path.with_extension("txt");
+        let bytes = encoding::encode("plain text\n", TextEncoding::Utf16Le, false).unwrap();
+        fs::write(&path, &bytes).unwrap();
+        let mut doc = Document::from_file(3, path.clone(), None).unwrap();
+        assert_eq!(doc.encoding(), TextEncoding::Utf16Le);
+        doc.save(BackupMode::None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        doc.save_with_encoding(TextEncoding::Utf16Le, None, BackupMode::None)
+            .unwrap();
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        doc.save_with_encoding(TextEncoding::Utf16Be, None, BackupMode::None)
+            .unwrap();
+        assert!(fs::read(&path).unwrap().starts_with(b"\xFE\xFF"));
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_crlf_when_typing() {
+        let path = std::env::temp_dir().join(format!("rte-crlf-{}.txt", std::process::id()));
+        fs::write(&path, "раз\r\nдва\r\n").unwrap();
+
+        let mut doc = Document::from_file(1, path.clone(), None).unwrap();
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+        assert_eq!(doc.text().to_string(), "раз\nдва\n");
+        doc.insert(doc.text().len_chars(), "три\n");
+        doc.save(BackupMode::None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "раз\r\nдва\r\nтри\r\n");
+
+        let revision = doc.revision();
+        doc.set_line_ending(LineEnding::Lf);
+        assert!(doc.dirty);
+        assert!(doc.revision() > revision);
+        doc.undo();
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+        doc.redo();
+        assert_eq!(doc.line_ending(), LineEnding::Lf);
+        doc.save(BackupMode::None).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "раз\nдва\nтри\n");
+
+        // Другая программа вернула CRLF: текст тот же, но файл изменился.
+        fs::write(&path, "раз\r\nдва\r\nтри\r\n").unwrap();
+        let disk = doc.check_disk().unwrap().expect("изменение на диске");
+        doc.reload(&disk);
+        assert_eq!(doc.line_ending(), LineEnding::CrLf);
+        assert!(!doc.dirty);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn replaces_within_range_in_one_undo_step() {
+        let mut doc = document_with("кот кот\nкот кот\n");
+        let query = Query::new("кот", Default::default()).unwrap();
+        assert_eq!(doc.replace_all(&query, "пёс", 4..11), 2);
+        assert_eq!(doc.text().to_string(), "кот пёс\nпёс кот\n");
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "кот кот\nкот кот\n");
+    }
+
+    #[test]
+    fn search_scope_follows_edits() {
+        let mut doc = document_with("кот и кот\nкот\n");
+        doc.set_search_scope(6..9);
+        doc.insert(0, "> ");
+        assert_eq!(doc.search_scope(), Some(8..11));
+
+        let query = Query::new("кот", Default::default()).unwrap();
+        assert_eq!(doc.replace_all(&query, "котёнок", 8..11), 1);
+        assert_eq!(doc.text().to_string(), "> кот и котёнок\nкот\n");
+        assert_eq!(doc.search_scope(), Some(8..15));
+
+        doc.undo();
+        assert_eq!(doc.search_scope(), Some(8..11));
+    }
+
+    #[test]
+    fn single_replace_matches_like_search_in_scope() {
+        let regex = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        // Область начинается и кончается посреди слов «скотина» и «котёл».
+        for pattern in [r"^кот", r"\bкот", r"кот$"] {
+            let mut doc = document_with("скотина кот\nкот котёл");
+            let within = 1..15;
+            let query = Query::new(pattern, regex).unwrap();
+            let found = doc.find_matches(&query, within.clone());
+            assert!(!found.is_empty(), "{pattern}");
+            // Заменяем с конца, чтобы найденные позиции не сдвигались.
+            for range in found.into_iter().rev() {
+                let end = doc.replace_match(&query, "пёс", within.clone(), range.clone());
+                assert_eq!(end, Some(range.start + 3), "{pattern}");
+            }
+        }
+
+        // Совпадение исчезло — заменять нечего.
+        let mut doc = document_with("кот");
+        let query = Query::new("кот", SearchOptions::default()).unwrap();
+        doc.insert(1, "о");
+        assert_eq!(doc.replace_match(&query, "пёс", 0..4, 0..3), None);
+        assert_eq!(doc.text().to_string(), "коот");
+    }
+
+    #[test]
+    fn view_follows_edits_in_place() {
+        let mut doc = document_with("привет\nмир\n");
+        doc.prepare_view(0..doc.text().len_chars());
+        doc.insert(7, "большой ");
+        doc.remove(0..1);
+        assert_eq!(doc.view_text(), "ривет\nбольшой мир\n");
+        assert_eq!(doc.view_text(), doc.text().to_string());
+    }
+
+    #[test]
+    fn edit_before_view_shifts_it() {
+        let mut doc = document_with("один\nдва\nтри\n");
+        let start = doc.text().line_to_char(1);
+        let end = doc.text().line_to_char(2);
+        doc.prepare_view(start..end);
+        doc.insert(0, "ноль\n");
+        assert_eq!(doc.view_range(), start + 5..end + 5);
+        assert_eq!(doc.view_text(), "два\n");
+    }
+
+    #[test]
+    fn set_text_replaces_only_the_difference() {
+        let mut doc = document_with("let x = 1;");
+        let cursor = Selection {
+            anchor: 10,
+            head: 10,
+        };
+        doc.set_selection(cursor);
+        doc.set_text("let y = 1;");
+        assert_eq!(doc.text().to_string(), "let y = 1;");
+        assert_eq!(doc.selection(), cursor);
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "let x = 1;");
+    }
+
+    #[test]
+    fn edits_at_every_cursor_in_one_undo_step() {
+        let mut doc = document_with("ёж\nуж\nёж\n");
+        doc.history.break_merge();
+        let cursor = |pos| Selection {
+            anchor: pos,
+            head: pos,
+        };
+        doc.set_cursors(cursor(0), vec![cursor(3), cursor(6)]);
+        doc.edit_cursors(|_, _, selection| (selection.range(), "- ".to_string()));
+        assert_eq!(doc.text().to_string(), "- ёж\n- уж\n- ёж\n");
+        assert_eq!(doc.extra_cursors(), [cursor(7), cursor(12)]);
+
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "ёж\nуж\nёж\n");
+        assert!(doc.extra_cursors().is_empty());
+
+        // Ctrl+D: слово под курсором, потом его следующее вхождение.
+        doc.set_selection(cursor(1));
+        doc.add_next_occurrence();
+        doc.add_next_occurrence();
+        assert_eq!(doc.selection(), Selection { anchor: 6, head: 8 });
+        assert_eq!(doc.extra_cursors(), [Selection { anchor: 0, head: 2 }]);
+        doc.add_next_occurrence();
+        assert_eq!(doc.extra_cursors().len(), 1);
+    }
+
+    #[test]
+    fn line_commands_work_on_current_or_selected_lines() {
+        let mut doc = document_with("в\nб\nа");
+        let cursor = |pos| Selection {
+            anchor: pos,
+            head: pos,
+        };
+        doc.set_selection(cursor(3));
+        doc.line_command(LineCommand::MoveDown);
+        assert_eq!(doc.text().to_string(), "в\nа\nб");
+        assert_eq!(doc.selection(), cursor(5));
+        doc.line_command(LineCommand::Duplicate);
+        assert_eq!(doc.text().to_string(), "в\nа\nб\nб");
+        doc.line_command(LineCommand::Delete);
+        doc.line_command(LineCommand::Delete);
+        assert_eq!(doc.text().to_string(), "в\nа");
+
+        doc.set_selection(Selection { anchor: 0, head: 3 });
+        doc.line_command(LineCommand::Sort(SortOrder::Ascending));
+        assert_eq!(doc.text().to_string(), "а\nв");
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "в\nа");
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "в\nа\nб");
+    }
+
+    #[test]
+    fn indents_with_tab_enter_and_conversion() {
+        let mut doc = document_with("fn f() {\nx\n\ny\n");
+        doc.set_indent(Indent::Spaces(2));
+        doc.set_selection(Selection {
+            anchor: 9,
+            head: 14,
+        });
+        doc.tab(false);
+        assert_eq!(doc.text().to_string(), "fn f() {\n  x\n\n  y\n");
+        doc.tab(true);
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "fn f() {\n  x\n\n  y\n");
+
+        doc.set_selection(Selection { anchor: 8, head: 8 });
+        doc.newline();
+        assert_eq!(doc.text().to_string(), "fn f() {\n  \n  x\n\n  y\n");
+        assert_eq!(doc.selection().head, 11);
+
+        doc.convert_indentation(true);
+        assert_eq!(doc.text().to_string(), "fn f() {\n\t\n\tx\n\n\ty\n");
+        assert_eq!(doc.indent(), Indent::Tabs);
+    }
+
+    #[test]
+    fn enter_keeps_typing_in_one_undo_step() {
+        let mut doc = document_with("fn f() {}\n");
+        doc.history.break_merge();
+        let type_text = |doc: &mut Document, text: &str| {
+            for ch in text.chars() {
+                let pos = doc.selection().head;
+                doc.insert(pos, &ch.to_string());
+                doc.set_selection(Selection {
+                    anchor: pos + 1,
+                    head: pos + 1,
+                });
+            }
+        };
+        doc.set_selection(Selection {
+            anchor: 10,
+            head: 10,
+        });
+        type_text(&mut doc, "ab");
+        doc.newline();
+        type_text(&mut doc, "cd");
+        assert_eq!(doc.text().to_string(), "fn f() {}\nab\ncd");
+
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "fn f() {}\n");
+    }
+
+    #[test]
+    fn typing_is_one_undo_step_and_restores_cursor() {
+        let mut doc = document_with("fn main() {}\n");
+        doc.history.break_merge();
+        doc.set_selection(Selection {
+            anchor: 13,
+            head: 13,
+        });
+        for (i, ch) in "// ок".chars().enumerate() {
+            doc.insert(13 + i, &ch.to_string());
+            doc.set_selection(Selection {
+                anchor: 14 + i,
+                head: 14 + i,
+            });
+        }
+        doc.remove(17..18);
+
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "fn main() {}\n// ок");
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "fn main() {}\n");
+        assert_eq!(doc.selection().head, 13);
+        doc.redo();
+        assert_eq!(doc.text().to_string(), "fn main() {}\n// ок");
+        assert_eq!(doc.selection().head, 18);
+    }
+
+    fn current_node(doc: &Document) -> NodeId {
+        doc.history()
+            .rows()
+            .iter()
+            .find(|row| row.current)
+            .unwrap()
+            .id
+    }
+
+    #[test]
+    fn typing_after_undo_keeps_the_old_branch() {
+        let mut doc = document_with("a");
+        let first = current_node(&doc);
+        doc.edit_group(|doc| doc.insert(1, "b"));
+        let branch = current_node(&doc);
+        doc.undo();
+        doc.edit_group(|doc| doc.insert(1, "c"));
+        assert_eq!(doc.text().to_string(), "ac");
+
+        assert_eq!(doc.text_at(branch).unwrap().to_string(), "ab");
+        doc.jump_to(branch);
+        assert_eq!(doc.text().to_string(), "ab");
+        doc.jump_to(first);
+        assert_eq!(doc.text().to_string(), "a");
+        doc.redo();
+        assert_eq!(doc.text().to_string(), "ab");
+    }
+
+    #[test]
+    fn history_stays_within_budget() {
+        let mut doc = Document::new_untitled(1);
+        doc.set_undo_budget(4096);
+        for i in 0..100 {
+            doc.edit_group(|doc| doc.insert(0, &"x".repeat(100 + i)));
+        }
+        let history = doc.history();
+        let ids: Vec<NodeId> = history.rows().iter().map(|row| row.id).collect();
+        let used: usize = ids.iter().map(|&id| history.transaction(id).size()).sum();
+        assert!(used <= 4096, "{used} байт при бюджете 4096");
+        // Выброшены самые старые шаги: остались последние подряд, до сотого.
+        assert!(ids.len() > 1 && ids[0] > 1);
+        assert!(ids.windows(2).all(|pair| pair[1] == pair[0] + 1));
+        assert_eq!(ids.last(), Some(&100));
+
+        for _ in 0..100 {
+            doc.undo();
+        }
+        assert!(doc.text().len_chars() > 0);
+    }
+
+    /// Задержка набора не должна зависеть от размера файла.
+    /// Запуск: `cargo test --release -- --ignored --nocapture typing_latency`
+    #[test]
+    #[ignore]
+    fn typing_latency_is_flat_on_large_files() {
+        fn median_keystroke(size: usize) -> Duration {
+            let line = "INSERT INTO log VALUES (42, 'съешь ещё этих мягких булок');\n";
+            let mut doc = document_with(&line.repeat(size / line.len()));
+            let middle = doc.text().len_chars() / 2;
+            let line_idx = doc.text().char_to_line(middle);
+            let start = doc.text().line_to_char(line_idx);
+            doc.prepare_view(start..doc.text().line_to_char(line_idx + 100));
+
+            let mut samples: Vec<Duration> = (0..2000)
+                .map(|i| {
+                    let started = Instant::now();
+                    doc.insert(start + i, "ж");
+                    started.elapsed()
+                })
+                .collect();
+            samples.sort();
+            samples[samples.len() / 2]
+        }
+
+        let small = median_keystroke(1 << 20);
+        let large = median_keystroke(50 << 20);
+        println!("1 МБ: {small:?} на символ, 50 МБ: {large:?} на символ");
+        assert!(large < small * 10 + Duration::from_micros(50));
+    }
+}
@@ -0,0 +1,199 @@
+//! Отступы из `.editorconfig` (https://editorconfig.org): файлы ищутся
+//! от папки документа вверх до того, где стоит `root = true`. Ближний
+//! к документу файл и нижние секции в нём важнее.
+
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+
+use crate::indent::Indent;
+
+/// Что `.editorconfig` говорит об отступе. Чего там нет, берётся из текста.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IndentConfig {
+    pub tabs: Option<bool>,
+    pub size: Option<u8>,
+}
+
+impl IndentConfig {
+    /// Отступ документа: настройки поверх угаданного по тексту.
+    pub fn resolve(self, detected: Option<Indent>) -> Indent {
+        let detected = detected.unwrap_or_default();
+        let tabs = self.tabs.unwrap_or(detected == Indent::Tabs);
+        match (tabs, self.size, detected) {
+            (true, _, _) => Indent::Tabs,
+            (false, Some(size), _) => Indent::Spaces(size),
+            (false, None, Indent::Spaces(size)) => Indent::Spaces(size),
+            (false, None, Indent::Tabs) => Indent::default(),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match (key, value) {
+            ("indent_style", "tab") => self.tabs = Some(true),
+            ("indent_style", "space") => self.tabs = Some(false),
+            ("indent_style", _) => self.tabs = None,
+            ("indent_size", size) => {
+                self.size = size.parse().ok().filter(|size| (1..=8).contains(size))
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Настройки отступа для файла `path` из всех `.editorconfig` над ним.
+pub fn indent_for(path: &Path) -> IndentConfig {
+    let mut sources = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        let Ok(source) = fs::read_to_string(dir.join(".editorconfig")) else {
+            continue;
+        };
+        let root = is_root(&source);
+        sources.push((dir, source));
+        if root {
+            break;
+        }
+    }
+
+    let mut config = IndentConfig::default();
+    // Дальние файлы первыми: ближние их перекрывают.
+    for (dir, source) in sources.iter().rev() {
+        let Ok(relative) = path.strip_prefix(dir) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        apply(&mut config, source, &relative);
+    }
+    config
+}
+
+fn is_root(source: &str) -> bool {
+    for line in source.lines().map(str::trim) {
+        if line.starts_with('[') {
+            return false;
+        }
+        if let Some((key, value)) = line.split_once('=')
+            && key.trim().eq_ignore_ascii_case("root")
+        {
+            return value.trim().eq_ignore_ascii_case("true");
+        }
+    }
+    false
+}
+
+/// Применяет секции `source`, подходящие к пути `relative` (через `/`).
+fn apply(config: &mut IndentConfig, source: &str, relative: &str) {
+    let mut matches = false;
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            matches = glob_regex(section).is_some_and(|regex| regex.is_match(relative));
+        } else if matches && let Some((key, value)) = line.split_once('=') {
+            config.set(&key.trim().to_lowercase(), &value.trim().to_lowercase());
+        }
+    }
+}
+
+/// Шаблон секции как регулярное выражение. Шаблон без `/` подходит
+/// к имени файла в любой папке.
+fn glob_regex(glob: &str) -> Option<Regex> {
+    let mut pattern = String::from("^");
+    if !glob.contains('/') {
+        pattern.push_str("(?:.*/)?");
+    }
+    let glob = glob.strip_prefix('/').unwrap_or(glob);
+    let mut chars = glob.chars().peekable();
+    let mut braces = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                pattern.push_str(".*");
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => {
+                pattern.push('[');
+                if chars.next_if_eq(&'!').is_some() {
+                    pattern.push('^');
+                }
+                for c in chars.by_ref() {
+                    if c == ']' {
+                        break;
+                    }
+                    if c == '\\' || c == '[' {
+                        pattern.push('\\');
+                    }
+                    pattern.push(c);
+                }
+                pattern.push(']');
+            }
+            '{' => {
+                braces += 1;
+                pattern.push_str("(?:");
+            }
+            ',' if braces > 0 => pattern.push('|'),
+            '}' if braces > 0 => {
+                braces -= 1;
+                pattern.push(')');
+            }
+            '\\' => {
+                if let Some(c) = chars.next() {
+                    pattern.push_str(&regex::escape(&c.to_string()));
+                }
+            }
+            c => pattern.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    pattern.push('$');
+    Regex::new(&pattern).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_indent_from_nearest_editorconfig() {
+        let root = std::env::temp_dir().join(format!("rte-editorconfig-{}", std::process::id()));
+        let nested = root.join("src/web");
+        fs::create_dir_all(&nested).unwrap();
+        fs::write(
+            root.join(".editorconfig"),
+            "root = true\n\n[*]\nindent_style = space\nindent_size = 4\n\n[Makefile]\nindent_style = tab\n",
+        )
+        .unwrap();
+        fs::write(
+            nested.join(".editorconfig"),
+            "[*.{js,ts}]\nindent_size = 2\n",
+        )
+        .unwrap();
+
+        let config = indent_for(&nested.join("app.ts"));
+        assert_eq!(
+            config,
+            IndentConfig {
+                tabs: Some(false),
+                size: Some(2)
+            }
+        );
+        assert_eq!(config.resolve(Some(Indent::Tabs)), Indent::Spaces(2));
+        assert_eq!(
+            indent_for(&root.join("src/main.rs")).resolve(None),
+            Indent::Spaces(4)
+        );
+        assert_eq!(
+            indent_for(&root.join("Makefile")).resolve(None),
+            Indent::Tabs
+        );
+        assert_eq!(
+            IndentConfig::default().resolve(Some(Indent::Spaces(3))),
+            Indent::Spaces(3)
+        );
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
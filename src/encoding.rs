@@ -0,0 +1,253 @@
+//! Кодировки файлов: определение при открытии и перекодирование при
+//! сохранении. Внутри редактора текст всегда в UTF-8.
+
+use encoding_rs::{Encoding, IBM866, KOI8_R, WINDOWS_1251};
+use serde::{Deserialize, Serialize};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// Самые частые строчные буквы русского текста — по ним выбираем
+/// однобайтовую кодировку.
+const FREQUENT_LETTERS: &str = "оеаинтсрвлкмдпу";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Windows1251,
+    Koi8R,
+    Cp866,
+}
+
+impl TextEncoding {
+    pub const ALL: [TextEncoding; 7] = [
+        TextEncoding::Utf8,
+        TextEncoding::Utf8Bom,
+        TextEncoding::Utf16Le,
+        TextEncoding::Utf16Be,
+        TextEncoding::Windows1251,
+        TextEncoding::Koi8R,
+        TextEncoding::Cp866,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            TextEncoding::Utf8 => "UTF-8",
+            TextEncoding::Utf8Bom => "UTF-8 с BOM",
+            TextEncoding::Utf16Le => "UTF-16 LE",
+            TextEncoding::Utf16Be => "UTF-16 BE",
+            TextEncoding::Windows1251 => "Windows-1251",
+            TextEncoding::Koi8R => "KOI8-R",
+            TextEncoding::Cp866 => "CP866",
+        }
+    }
+
+    /// Кодировка по имени, как его набирают руками: `cp1251`, `koi8-r`, `utf-16`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let normalize = |s: &str| {
+            s.to_lowercase()
+                .chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+        };
+        let name = normalize(name);
+        let alias = match name.as_str() {
+            "utf8bom" => Some(TextEncoding::Utf8Bom),
+            "utf16" => Some(TextEncoding::Utf16Le),
+            "cp1251" | "win1251" => Some(TextEncoding::Windows1251),
+            "ibm866" | "dos" => Some(TextEncoding::Cp866),
+            _ => None,
+        };
+        alias.or_else(|| Self::ALL.into_iter().find(|e| normalize(e.label()) == name))
+    }
+
+    fn single_byte(self) -> Option<&'static Encoding> {
+        match self {
+            TextEncoding::Windows1251 => Some(WINDOWS_1251),
+            TextEncoding::Koi8R => Some(KOI8_R),
+            TextEncoding::Cp866 => Some(IBM866),
+            _ => None,
+        }
+    }
+}
+
+/// Угадывает кодировку: сначала BOM, потом признаки UTF-16 без BOM,
+/// корректность UTF-8 и частоты русских букв.
+pub fn detect(bytes: &[u8]) -> TextEncoding {
+    if bytes.starts_with(UTF8_BOM) {
+        return TextEncoding::Utf8Bom;
+    }
+    if bytes.starts_with(UTF16LE_BOM) {
+        return TextEncoding::Utf16Le;
+    }
+    if bytes.starts_with(UTF16BE_BOM) {
+        return TextEncoding::Utf16Be;
+    }
+
+    // В UTF-16 у латиницы и цифр старший байт нулевой. Проверяем до UTF-8:
+    // такие байты часто оказываются и корректным UTF-8 с нулями.
+    let sample = &bytes[..bytes.len().min(4096)];
+    let zeros_at = |parity: usize| {
+        sample
+            .iter()
+            .skip(parity)
+            .step_by(2)
+            .filter(|&&b| b == 0)
+            .count()
+    };
+    let pairs = sample.len() / 2;
+    if pairs > 0 {
+        if zeros_at(1) * 3 > pairs && zeros_at(0) * 10 < pairs {
+            return TextEncoding::Utf16Le;
+        }
+        if zeros_at(0) * 3 > pairs && zeros_at(1) * 10 < pairs {
+            return TextEncoding::Utf16Be;
+        }
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return TextEncoding::Utf8;
+    }
+
+    [
+        TextEncoding::Windows1251,
+        TextEncoding::Koi8R,
+        TextEncoding::Cp866,
+    ]
+    .into_iter()
+    .max_by_key(|encoding| {
+        let (text, _) = encoding
+            .single_byte()
+            .expect("однобайтовая кодировка")
+            .decode_without_bom_handling(sample);
+        text.chars()
+            .filter(|c| FREQUENT_LETTERS.contains(*c))
+            .count()
+    })
+    .unwrap_or(TextEncoding::Windows1251)
+}
+
+/// Перекодирует содержимое файла в строку. BOM в текст не попадает.
+pub fn decode(bytes: &[u8], encoding: TextEncoding) -> Result<String, String> {
+    let invalid = || format!("Файл не читается как {}", encoding.label());
+    match encoding {
+        TextEncoding::Utf8 | TextEncoding::Utf8Bom => {
+            let bytes = bytes.strip_prefix(UTF8_BOM).unwrap_or(bytes);
+            String::from_utf8(bytes.to_vec()).map_err(|_| invalid())
+        }
+        TextEncoding::Utf16Le | TextEncoding::Utf16Be => {
+            let (bom, from_bytes): (_, fn([u8; 2]) -> u16) = match encoding {
+                TextEncoding::Utf16Le => (UTF16LE_BOM, u16::from_le_bytes),
+                _ => (UTF16BE_BOM, u16::from_be_bytes),
+            };
+            let bytes = bytes.strip_prefix(bom).unwrap_or(bytes);
+            if bytes.len() % 2 != 0 {
+                return Err(invalid());
+            }
+            let units = bytes
+                .chunks_exact(2)
+                .map(|pair| from_bytes([pair[0], pair[1]]));
+            char::decode_utf16(units)
+                .collect::<Result<String, _>>()
+                .map_err(|_| invalid())
+        }
+        _ => {
+            let encoding = encoding.single_byte().expect("однобайтовая кодировка");
+            encoding
+                .decode_without_bom_handling_and_without_replacement(bytes)
+                .map(|text| text.into_owned())
+                .ok_or_else(invalid)
+        }
+    }
+}
+
+/// Начинается ли файл в UTF-16 с BOM. Для других кодировок — `true`: у
+/// UTF-8 BOM задаёт сама кодировка, у однобайтовых его не бывает.
+pub fn utf16_bom(bytes: &[u8], encoding: TextEncoding) -> bool {
+    match encoding {
+        TextEncoding::Utf16Le => bytes.starts_with(UTF16LE_BOM),
+        TextEncoding::Utf16Be => bytes.starts_with(UTF16BE_BOM),
+        _ => true,
+    }
+}
+
+/// Перекодирует текст для записи в файл. `utf16_bom` — писать ли BOM в
+/// начало UTF-16. Если какой-то символ в кодировке не представим,
+/// возвращает ошибку с этим символом.
+pub fn encode(text: &str, encoding: TextEncoding, utf16_bom: bool) -> Result<Vec<u8>, String> {
+    let utf16 = |bom: &[u8], to_bytes: fn(u16) -> [u8; 2]| {
+        let bom = if utf16_bom { bom } else { &[] };
+        bom.iter()
+            .copied()
+            .chain(text.encode_utf16().flat_map(to_bytes))
+            .collect()
+    };
+    match encoding {
+        TextEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        TextEncoding::Utf8Bom => Ok([UTF8_BOM, text.as_bytes()].concat()),
+        TextEncoding::Utf16Le => Ok(utf16(UTF16LE_BOM, u16::to_le_bytes)),
+        TextEncoding::Utf16Be => Ok(utf16(UTF16BE_BOM, u16::to_be_bytes)),
+        _ => {
+            let single = encoding.single_byte().expect("однобайтовая кодировка");
+            let (bytes, _, had_errors) = single.encode(text);
+            if !had_errors {
+                return Ok(bytes.into_owned());
+            }
+            let bad = text
+                .chars()
+                .find(|c| single.encode(c.encode_utf8(&mut [0; 4])).2)
+                .unwrap_or(char::REPLACEMENT_CHARACTER);
+            Err(format!(
+                "Символ «{bad}» нельзя записать в кодировке {}",
+                encoding.label()
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "Съешь же ещё этих мягких французских булок, да выпей чаю.\nHello!\n";
+
+    #[test]
+    fn round_trips_every_encoding() {
+        for encoding in TextEncoding::ALL {
+            let bytes = encode(SAMPLE, encoding, true).unwrap();
+            assert_eq!(detect(&bytes), encoding, "{}", encoding.label());
+            assert_eq!(
+                decode(&bytes, encoding).unwrap(),
+                SAMPLE,
+                "{}",
+                encoding.label()
+            );
+        }
+    }
+
+    #[test]
+    fn detects_utf16_without_bom() {
+        let bytes = encode("plain text, без BOM\n", TextEncoding::Utf16Le, false).unwrap();
+        assert_eq!(detect(&bytes), TextEncoding::Utf16Le);
+        assert!(!utf16_bom(&bytes, TextEncoding::Utf16Le));
+        assert_eq!(
+            decode(&bytes, TextEncoding::Utf16Le).unwrap(),
+            "plain text, без BOM\n"
+        );
+    }
+
+    #[test]
+    fn reports_unencodable_characters() {
+        let err = encode("цена: 5 €", TextEncoding::Koi8R, true).unwrap_err();
+        assert!(err.contains('€'));
+        assert_eq!(
+            TextEncoding::from_name("cp1251"),
+            Some(TextEncoding::Windows1251)
+        );
+        assert_eq!(TextEncoding::from_name("koi8-r"), Some(TextEncoding::Koi8R));
+    }
+}
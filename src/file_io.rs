@@ -0,0 +1,192 @@
+//! Запись файлов на диск без риска потерять содержимое.
+//!
+//! Текст пишется во временный файл рядом с целевым, сбрасывается на диск и
+//! только потом переименовывается поверх оригинала. Если запись оборвётся
+//! (сбой, кончилось место), старый файл останется нетронутым.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Резервная копия прежнего содержимого перед перезаписью.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BackupMode {
+    #[default]
+    None,
+    /// `файл~`, каждый раз перезаписывается.
+    Simple,
+    /// `файл.~1~`, `файл.~2~`, ... — копии копятся.
+    Numbered,
+}
+
+impl BackupMode {
+    pub const ALL: [BackupMode; 3] = [BackupMode::None, BackupMode::Simple, BackupMode::Numbered];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            BackupMode::None => "Нет",
+            BackupMode::Simple => "файл~",
+            BackupMode::Numbered => "файл.~N~",
+        }
+    }
+}
+
+/// Атомарно заменяет содержимое `path` тем, что запишет `write`.
+///
+/// Права доступа и (в Unix) владелец прежнего файла сохраняются, насколько
+/// это позволяет система. Символическая ссылка остаётся ссылкой: пишем в файл,
+/// на который она указывает.
+pub fn write_atomic(
+    path: &Path,
+    backup: BackupMode,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let original = fs::metadata(&path).ok();
+    let tmp = temp_path(&path);
+
+    let result = (|| {
+        let file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        let mut out = BufWriter::new(file);
+        write(&mut out)?;
+        let file = out.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+
+        if let Some(meta) = &original {
+            fs::set_permissions(&tmp, meta.permissions())?;
+            copy_owner(&tmp, meta);
+            make_backup(&path, backup)?;
+        }
+        fs::rename(&tmp, &path)
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+        return result;
+    }
+    // Переименование тоже должно попасть на диск.
+    #[cfg(unix)]
+    if let Some(dir) = path.parent()
+        && let Ok(dir) = File::open(dir)
+    {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Временный файл в том же каталоге: rename между файловыми системами
+/// не атомарен.
+fn temp_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!(".{name}.{}.tmp", std::process::id()))
+}
+
+#[cfg(unix)]
+fn copy_owner(path: &Path, meta: &fs::Metadata) {
+    use std::os::unix::fs::MetadataExt;
+    // Сменить владельца может только root — для остальных это не ошибка.
+    let _ = std::os::unix::fs::chown(path, Some(meta.uid()), Some(meta.gid()));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_path: &Path, _meta: &fs::Metadata) {}
+
+fn make_backup(path: &Path, mode: BackupMode) -> io::Result<()> {
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let backup = match mode {
+        BackupMode::None => return Ok(()),
+        BackupMode::Simple => path.with_file_name(format!("{name}~")),
+        BackupMode::Numbered => {
+            let prefix = format!("{name}.~");
+            let last = path
+                .parent()
+                .and_then(|dir| fs::read_dir(dir).ok())
+                .into_iter()
+                .flatten()
+                .flatten()
+                .filter_map(|entry| {
+                    let file = entry.file_name().to_string_lossy().into_owned();
+                    file.strip_prefix(&prefix)?
+                        .strip_suffix('~')?
+                        .parse::<u32>()
+                        .ok()
+                })
+                .max()
+                .unwrap_or(0);
+            path.with_file_name(format!("{prefix}{}~", last + 1))
+        }
+    };
+    fs::copy(path, backup).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rte-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn save(path: &Path, text: &str, backup: BackupMode) {
+        write_atomic(path, backup, |out| out.write_all(text.as_bytes())).unwrap();
+    }
+
+    #[test]
+    fn replaces_file_and_keeps_backups() {
+        let dir = scratch_dir("backups");
+        let file = dir.join("a.txt");
+        save(&file, "один", BackupMode::None);
+        save(&file, "два", BackupMode::Simple);
+        save(&file, "три", BackupMode::Numbered);
+        save(&file, "четыре", BackupMode::Numbered);
+
+        assert_eq!(fs::read_to_string(&file).unwrap(), "четыре");
+        assert_eq!(fs::read_to_string(dir.join("a.txt~")).unwrap(), "один");
+        assert_eq!(fs::read_to_string(dir.join("a.txt.~1~")).unwrap(), "два");
+        assert_eq!(fs::read_to_string(dir.join("a.txt.~2~")).unwrap(), "три");
+        // Временных файлов не осталось.
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 4);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn failed_write_leaves_original() {
+        let dir = scratch_dir("failed");
+        let file = dir.join("a.txt");
+        save(&file, "цело", BackupMode::None);
+        let result = write_atomic(&file, BackupMode::None, |out| {
+            out.write_all(b"half")?;
+            Err(io::Error::other("диск заполнен"))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(fs::read_to_string(&file).unwrap(), "цело");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch_dir("perms");
+        let file = dir.join("run.sh");
+        save(&file, "#!/bin/sh", BackupMode::None);
+        fs::set_permissions(&file, fs::Permissions::from_mode(0o750)).unwrap();
+        save(&file, "#!/bin/sh\necho", BackupMode::None);
+
+        let mode = fs::metadata(&file).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,242 @@
+//! Поиск по всем файлам папки. Обход идёт в отдельном потоке, а найденное
+//! приходит по каналу, чтобы интерфейс не замирал на больших деревьях.
+//!
+//! Файлы из `.gitignore`, каталог `.git` и двоичные файлы пропускаются,
+//! скрытые файлы вроде `.env` просматриваются.
+
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use eframe::egui;
+use ignore::WalkBuilder;
+
+use crate::encoding::{self, TextEncoding};
+use crate::line_ending;
+use crate::search::{self, Query};
+
+/// После стольких найденных строк поиск останавливается.
+pub const HIT_LIMIT: usize = 10_000;
+/// Файлы больше этого не читаем: вряд ли это текст, который ищут.
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+/// Строка файла с совпадением.
+pub struct LineHit {
+    /// Номер строки, с 1.
+    pub line: usize,
+    /// Символы первого совпадения в строке.
+    pub columns: Range<usize>,
+    pub preview: String,
+    /// Байты совпадения в `preview`.
+    pub highlight: Range<usize>,
+}
+
+pub struct FileHits {
+    pub path: PathBuf,
+    pub hits: Vec<LineHit>,
+}
+
+enum Message {
+    Found(FileHits),
+    Searched(usize),
+    Truncated,
+}
+
+pub struct FolderSearch {
+    pub root: PathBuf,
+    pub pattern: String,
+    pub files: Vec<FileHits>,
+    /// Сколько файлов просмотрено.
+    pub searched: usize,
+    pub done: bool,
+    /// Нашлось больше [`HIT_LIMIT`] строк, остальное не искали.
+    pub truncated: bool,
+    receiver: Receiver<Message>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl FolderSearch {
+    /// Запускает поиск `query` в `root`. Поток будит `ctx`, когда что-то нашёл.
+    pub fn start(root: PathBuf, pattern: String, query: Query, ctx: egui::Context) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancel = Arc::new(AtomicBool::new(false));
+        let stop = cancel.clone();
+        let walk_root = root.clone();
+        thread::spawn(move || {
+            search(&walk_root, &query, &stop, &mut |message| {
+                let _ = sender.send(message);
+                ctx.request_repaint();
+            });
+        });
+
+        Self {
+            root,
+            pattern,
+            files: Vec::new(),
+            searched: 0,
+            done: false,
+            truncated: false,
+            receiver,
+            cancel,
+        }
+    }
+
+    /// Забирает то, что поток нашёл с прошлого кадра.
+    pub fn poll(&mut self) {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Message::Found(file)) => self.files.push(file),
+                Ok(Message::Searched(count)) => self.searched = count,
+                Ok(Message::Truncated) => self.truncated = true,
+                Err(mpsc::TryRecvError::Empty) => break,
+                // Поток закончил: канал закрывается вместе с ним.
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.done = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    pub fn stop(&self) {
+        self.cancel.store(true, Ordering::Relaxed);
+    }
+
+    pub fn total_hits(&self) -> usize {
+        self.files.iter().map(|file| file.hits.len()).sum()
+    }
+}
+
+impl Drop for FolderSearch {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn search(root: &Path, query: &Query, stop: &AtomicBool, send: &mut dyn FnMut(Message)) {
+    let mut searched = 0;
+    let mut total = 0;
+    let walker = WalkBuilder::new(root)
+        // .gitignore учитываем и вне git-репозитория.
+        .require_git(false)
+        // Скрытые файлы (.env, .github/…) тоже ищем, кроме самого .git.
+        .hidden(false)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build();
+    for entry in walker.flatten() {
+        if stop.load(Ordering::Relaxed) {
+            break;
+        }
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        searched += 1;
+        if searched % 100 == 0 {
+            send(Message::Searched(searched));
+        }
+
+        let hits = search_file(entry.path(), query);
+        if hits.is_empty() {
+            continue;
+        }
+        total += hits.len();
+        send(Message::Found(FileHits {
+            path: entry.into_path(),
+            hits,
+        }));
+        if total >= HIT_LIMIT {
+            send(Message::Truncated);
+            break;
+        }
+    }
+    send(Message::Searched(searched));
+}
+
+/// Первое совпадение в каждой строке файла. Непрочитанные и двоичные
+/// файлы дают пустой список.
+fn search_file(path: &Path, query: &Query) -> Vec<LineHit> {
+    let text = fs::metadata(path)
+        .ok()
+        .filter(|meta| meta.len() <= MAX_FILE_BYTES)
+        .and_then(|_| fs::read(path).ok())
+        .and_then(|bytes| read_text(&bytes));
+    let Some(text) = text else {
+        return Vec::new();
+    };
+
+    text.lines()
+        .enumerate()
+        .filter_map(|(n, line)| {
+            let columns = query.find_chars(line).into_iter().next()?;
+            let (preview, highlight) = search::preview_line(line, columns.clone());
+            Some(LineHit {
+                line: n + 1,
+                columns,
+                preview,
+                highlight,
+            })
+        })
+        .collect()
+}
+
+/// Текст файла так, как его покажет редактор. Нулевые байты не в UTF-16 —
+/// признак двоичного файла.
+fn read_text(bytes: &[u8]) -> Option<String> {
+    let encoding = encoding::detect(bytes);
+    let utf16 = matches!(encoding, TextEncoding::Utf16Le | TextEncoding::Utf16Be);
+    if !utf16 && bytes[..bytes.len().min(8192)].contains(&0) {
+        return None;
+    }
+    let text = encoding::decode(bytes, encoding).ok()?;
+    Some(line_ending::normalize(&text).into_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::SearchOptions;
+
+    #[test]
+    fn skips_ignored_and_binary_files() {
+        let dir = std::env::temp_dir().join(format!("rte-folder-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("src")).unwrap();
+        fs::write(dir.join(".gitignore"), "target/\n").unwrap();
+        fs::write(dir.join(".env"), "искомое\n").unwrap();
+        fs::create_dir_all(dir.join(".git")).unwrap();
+        fs::write(dir.join(".git/COMMIT_EDITMSG"), "искомое\n").unwrap();
+        fs::create_dir_all(dir.join("target")).unwrap();
+        fs::write(dir.join("target/out.txt"), "искомое\n").unwrap();
+        fs::write(
+            dir.join("data.bin"),
+            [&[0, 1], "искомое".as_bytes()].concat(),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("src/main.rs"),
+            "fn main() {}\r\n// искомое тут\r\n",
+        )
+        .unwrap();
+
+        let query = Query::new("искомое", SearchOptions::default()).unwrap();
+        let mut files = Vec::new();
+        search(&dir, &query, &AtomicBool::new(false), &mut |message| {
+            if let Message::Found(file) = message {
+                files.push(file);
+            }
+        });
+
+        files.sort_by(|a, b| a.path.cmp(&b.path));
+        assert_eq!(files.len(), 2);
+        assert!(files[0].path.ends_with(".env"));
+        assert!(files[1].path.ends_with("src/main.rs"));
+        let hit = &files[1].hits[0];
+        assert_eq!((hit.line, hit.columns.clone()), (2, 3..10));
+        assert_eq!(&hit.preview[hit.highlight.clone()], "искомое");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
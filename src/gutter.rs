@@ -0,0 +1,157 @@
+//! Колонка номеров строк слева от текста. Номера берутся из раскладки
+//! текста, поэтому перенесённая строка получает один номер на все свои ряды.
+
+use eframe::egui::{self, Align2, FontId, Pos2, Rect};
+use serde::{Deserialize, Serialize};
+
+/// Отступ номеров от краёв колонки.
+const PADDING: f32 = 8.0;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineNumbers {
+    Off,
+    #[default]
+    Absolute,
+    /// Расстояние от строки с курсором, как `relativenumber` в Vim.
+    Relative,
+}
+
+impl LineNumbers {
+    pub const ALL: [LineNumbers; 3] = [
+        LineNumbers::Off,
+        LineNumbers::Absolute,
+        LineNumbers::Relative,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LineNumbers::Off => "Не показывать",
+            LineNumbers::Absolute => "Обычные",
+            LineNumbers::Relative => "Относительные",
+        }
+    }
+
+    /// Подпись строки `line` (с нуля), когда курсор на строке `current`.
+    fn label_for(self, line: usize, current: usize) -> String {
+        match self {
+            LineNumbers::Relative if line != current => line.abs_diff(current).to_string(),
+            _ => (line + 1).to_string(),
+        }
+    }
+}
+
+/// Ширина колонки под номера строк до `lines`.
+pub fn width(ui: &egui::Ui, font_id: &FontId, mode: LineNumbers, lines: usize) -> f32 {
+    if mode == LineNumbers::Off {
+        return 0.0;
+    }
+    let digits = lines.max(1).ilog10() as f32 + 1.0;
+    let digit_width = ui.fonts_mut(|f| f.glyph_width(font_id, '0'));
+    digits.max(3.0) * digit_width + 2.0 * PADDING
+}
+
+/// Строки документа в раскладке `galley`, которая начинается со строки
+/// `first_line`: номер строки и прямоугольник всех её рядов.
+pub fn line_rects(galley: &egui::Galley, first_line: usize) -> Vec<(usize, Rect)> {
+    let mut lines: Vec<(usize, Rect)> = Vec::new();
+    let mut line = first_line;
+    let mut new_line = true;
+    for row in &galley.rows {
+        match lines.last_mut() {
+            Some((_, rect)) if !new_line => *rect = rect.union(row.rect()),
+            _ => lines.push((line, row.rect())),
+        }
+        new_line = row.ends_with_newline;
+        if new_line {
+            line += 1;
+        }
+    }
+    lines
+}
+
+/// Строка под точкой `y` экрана; раскладка нарисована в `origin`.
+pub fn line_at(lines: &[(usize, Rect)], origin: Pos2, y: f32) -> Option<usize> {
+    let y = y - origin.y;
+    let i = lines.partition_point(|(_, rect)| rect.max.y <= y);
+    lines
+        .get(i)
+        .filter(|(_, rect)| rect.min.y <= y)
+        .map(|(line, _)| *line)
+}
+
+/// Рисует колонку в `rect` поверх всего, что под ней: при прокрутке
+/// вбок текст уходит под номера.
+pub fn paint(
+    ui: &egui::Ui,
+    rect: Rect,
+    lines: &[(usize, Rect)],
+    origin: Pos2,
+    current: usize,
+    mode: LineNumbers,
+    font_id: &FontId,
+) {
+    let painter = ui.painter_at(rect);
+    let visuals = ui.visuals();
+    painter.rect_filled(rect, 0.0, visuals.panel_fill);
+    painter.vline(
+        rect.right() - 0.5,
+        rect.y_range(),
+        visuals.widgets.noninteractive.bg_stroke,
+    );
+
+    let first = lines.partition_point(|(_, line)| line.max.y + origin.y < rect.top());
+    for (line, line_rect) in &lines[first..] {
+        let top = origin.y + line_rect.min.y;
+        if top > rect.bottom() {
+            break;
+        }
+        let color = if *line == current {
+            visuals.strong_text_color()
+        } else {
+            visuals.weak_text_color()
+        };
+        painter.text(
+            Pos2::new(rect.right() - PADDING, top),
+            Align2::RIGHT_TOP,
+            mode.label_for(*line, current),
+            font_id.clone(),
+            color,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_wrapped_lines_once() {
+        let ctx = egui::Context::default();
+        let _ = ctx.run(Default::default(), |ctx| {
+            let font_id = FontId::monospace(14.0);
+            let mut job = egui::text::LayoutJob::simple(
+                "короткая\nдлинная строка, которая не влезет\n".to_string(),
+                font_id.clone(),
+                egui::Color32::WHITE,
+                f32::INFINITY,
+            );
+            job.wrap.max_width = ctx.fonts_mut(|f| f.glyph_width(&font_id, 'ж')) * 12.0;
+            let galley = ctx.fonts_mut(|f| f.layout_job(job));
+
+            let lines = line_rects(&galley, 10);
+            let numbers: Vec<usize> = lines.iter().map(|(line, _)| *line).collect();
+            assert_eq!(numbers, [10, 11, 12]);
+            assert!(galley.rows.len() > 3);
+            let wrapped = lines[1].1;
+            assert!(wrapped.height() > lines[0].1.height());
+            assert_eq!(
+                line_at(&lines, Pos2::ZERO, wrapped.bottom() - 1.0),
+                Some(11)
+            );
+            assert_eq!(line_at(&lines, Pos2::ZERO, -1.0), None);
+        });
+
+        assert_eq!(LineNumbers::Relative.label_for(7, 10), "3");
+        assert_eq!(LineNumbers::Relative.label_for(10, 10), "11");
+    }
+}
@@ -0,0 +1,552 @@
+use std::collections::{BTreeMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::document::Selection;
+use crate::line_ending::LineEnding;
+
+/// Набор символов, идущий подряд с паузами меньше этой, отменяется одним шагом.
+const MERGE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Память истории по умолчанию.
+pub const DEFAULT_BUDGET_BYTES: usize = 64 * 1024 * 1024;
+
+/// Примерная цена одной правки без учёта самого текста.
+const EDIT_OVERHEAD: usize = std::mem::size_of::<Edit>();
+
+/// Одна правка: с байта `at` текст `deleted` заменён на `inserted`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Edit {
+    pub at: usize,
+    pub deleted: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    fn size(&self) -> usize {
+        EDIT_OVERHEAD + self.deleted.len() + self.inserted.len()
+    }
+
+    /// Пытается дописать `next` к этой правке, если это продолжение набора
+    /// (вместе с Enter) или стирания. Стёртый перевод строки начинает новый шаг.
+    fn absorb(&mut self, next: &Edit) -> bool {
+        if next.deleted.is_empty() {
+            if next.at != self.at + self.inserted.len() {
+                return false;
+            }
+            self.inserted.push_str(&next.inserted);
+            return true;
+        }
+
+        if !next.inserted.is_empty() || !self.inserted.is_empty() || next.deleted.contains('\n') {
+            return false;
+        }
+        if next.at + next.deleted.len() == self.at {
+            // Backspace
+            self.at = next.at;
+            self.deleted.insert_str(0, &next.deleted);
+            true
+        } else if next.at == self.at {
+            // Delete
+            self.deleted.push_str(&next.deleted);
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Перевод документа на другие окончания строк: текст (всегда через `\n`)
+/// не меняется, меняется только то, что попадёт в файл.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LineEndingChange {
+    pub from: LineEnding,
+    /// До перевода окончания были разными.
+    pub from_mixed: bool,
+    pub to: LineEnding,
+}
+
+/// Шаг отмены: правки в порядке применения и выделение до и после них.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Transaction {
+    pub edits: Vec<Edit>,
+    #[serde(default)]
+    pub line_ending: Option<LineEndingChange>,
+    pub selection_before: Selection,
+    pub selection_after: Selection,
+}
+
+impl Transaction {
+    /// Сколько байт занимают правки шага.
+    pub fn size(&self) -> usize {
+        self.edits.iter().map(Edit::size).sum()
+    }
+
+    /// Короткое описание шага для панели истории.
+    pub fn summary(&self) -> String {
+        fn excerpt(text: &str) -> String {
+            let line = text.lines().next().unwrap_or("");
+            let mut short: String = line.chars().take(24).collect();
+            if short.len() < text.len() {
+                short.push('…');
+            }
+            short
+        }
+
+        if let Some(change) = self.line_ending {
+            return format!("окончания строк → {}", change.to.label());
+        }
+        match self.edits.as_slice() {
+            [] => "исходное состояние".to_string(),
+            [edit] if edit.deleted.is_empty() => format!("+ «{}»", excerpt(&edit.inserted)),
+            [edit] if edit.inserted.is_empty() => format!("− «{}»", excerpt(&edit.deleted)),
+            [edit] => format!(
+                "«{}» → «{}»",
+                excerpt(&edit.deleted),
+                excerpt(&edit.inserted)
+            ),
+            edits => format!("{} правок", edits.len()),
+        }
+    }
+}
+
+pub type NodeId = u64;
+
+/// Состояние текста в дереве отмены.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Node {
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    /// Правки от состояния родителя к этому.
+    tx: Transaction,
+    time: SystemTime,
+    /// Куда идти по "Повторить": последний потомок, из которого вернулись.
+    redo_child: Option<NodeId>,
+}
+
+/// Строка панели дерева отмены.
+pub struct TreeRow {
+    pub id: NodeId,
+    /// Отступ ветки: первый потомок продолжает ветку родителя.
+    pub depth: usize,
+    pub time: SystemTime,
+    pub summary: String,
+    pub current: bool,
+}
+
+/// Сохраняемая часть истории (см. `history_store`).
+#[derive(Serialize, Deserialize)]
+pub struct HistoryData {
+    nodes: BTreeMap<NodeId, Node>,
+    root: NodeId,
+    current: NodeId,
+    next_id: NodeId,
+}
+
+impl HistoryData {
+    /// Дерево цело: все ссылки между узлами взаимны, из корня достижим
+    /// каждый узел ровно один раз, а номера новых узлов не займут старые.
+    fn is_valid(&self) -> bool {
+        let Some(root) = self.nodes.get(&self.root) else {
+            return false;
+        };
+        if root.parent.is_some() || !self.nodes.contains_key(&self.current) {
+            return false;
+        }
+        let links_ok = self.nodes.iter().all(|(&id, node)| {
+            let parent_ok = match node.parent {
+                Some(parent) => self
+                    .nodes
+                    .get(&parent)
+                    .is_some_and(|p| p.children.contains(&id)),
+                None => id == self.root,
+            };
+            let children_ok = node
+                .children
+                .iter()
+                .all(|child| self.nodes.get(child).is_some_and(|c| c.parent == Some(id)));
+            let redo_ok = node
+                .redo_child
+                .is_none_or(|child| node.children.contains(&child));
+            id < self.next_id && parent_ok && children_ok && redo_ok
+        });
+        if !links_ok {
+            return false;
+        }
+
+        let mut seen = HashSet::new();
+        let mut stack = vec![self.root];
+        while let Some(id) = stack.pop() {
+            if !seen.insert(id) {
+                return false;
+            }
+            stack.extend(&self.nodes[&id].children);
+        }
+        seen.len() == self.nodes.len()
+    }
+}
+
+/// Дерево отмены, как `:undotree` в vim: правка после отмены начинает новую
+/// ветку, а старая остаётся, и к любому состоянию можно вернуться.
+///
+/// Узлы хранят правки (операции), а не снимки текста. Номера узлов растут
+/// со временем создания.
+pub struct History {
+    nodes: BTreeMap<NodeId, Node>,
+    root: NodeId,
+    current: NodeId,
+    next_id: NodeId,
+    /// Сколько байт сейчас занимают правки всех узлов.
+    used: usize,
+    budget: usize,
+    /// Когда в текущий узел можно было дописывать набор.
+    last_edit: Option<Instant>,
+    /// Глубина открытых групп (см. [`History::begin_group`]).
+    group_depth: usize,
+    group_started: bool,
+    /// Открытая группа — набор (см. [`History::begin_typing_group`]).
+    typing_group: bool,
+    /// Выделение после последней правки ещё не известно.
+    selection_pending: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        let root = Node {
+            parent: None,
+            children: Vec::new(),
+            tx: Transaction::default(),
+            time: SystemTime::now(),
+            redo_child: None,
+        };
+        Self {
+            nodes: BTreeMap::from([(0, root)]),
+            root: 0,
+            current: 0,
+            next_id: 1,
+            used: 0,
+            budget: DEFAULT_BUDGET_BYTES,
+            last_edit: None,
+            group_depth: 0,
+            group_started: false,
+            typing_group: false,
+            selection_pending: false,
+        }
+    }
+
+    pub fn set_budget(&mut self, bytes: usize) {
+        self.budget = bytes;
+        self.enforce_budget();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.len() == 1
+    }
+
+    pub fn to_data(&self) -> HistoryData {
+        HistoryData {
+            nodes: self.nodes.clone(),
+            root: self.root,
+            current: self.current,
+            next_id: self.next_id,
+        }
+    }
+
+    /// Заменяет историю сохранённой. Текст документа при этом должен
+    /// совпадать с тем, на котором историю сохраняли. Испорченное дерево
+    /// не принимаем: возвращает `false`, история остаётся прежней.
+    pub fn restore(&mut self, data: HistoryData) -> bool {
+        if !data.is_valid() {
+            return false;
+        }
+        self.used = data.nodes.values().map(|n| n.tx.size()).sum();
+        self.nodes = data.nodes;
+        self.root = data.root;
+        self.current = data.current;
+        self.next_id = data.next_id;
+        self.break_merge();
+        self.selection_pending = false;
+        self.enforce_budget();
+        true
+    }
+
+    /// Все правки до [`History::end_group`] станут одним шагом отмены.
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group_started = false;
+        }
+        self.group_depth += 1;
+    }
+
+    /// Группа набора в нескольких курсорах: она дописывается к прошлому
+    /// шагу, если он был набором и закончился меньше [`MERGE_TIMEOUT`]
+    /// назад, и к ней самой так же допишется следующий набор.
+    pub fn begin_typing_group(&mut self) {
+        let recent = self.last_edit.is_some_and(|t| t.elapsed() < MERGE_TIMEOUT);
+        self.begin_group();
+        if self.group_depth == 1 {
+            self.typing_group = true;
+            self.group_started = recent
+                && self.current != self.root
+                && self.nodes[&self.current].children.is_empty();
+        }
+    }
+
+    pub fn end_group(&mut self) {
+        self.group_depth = self.group_depth.saturating_sub(1);
+        if self.group_depth == 0 {
+            if std::mem::take(&mut self.typing_group) && self.group_started {
+                self.last_edit = Some(Instant::now());
+            } else {
+                self.break_merge();
+            }
+        }
+    }
+
+    /// Следующая правка начнёт новый шаг, даже если продолжает набор.
+    pub fn break_merge(&mut self) {
+        self.last_edit = None;
+    }
+
+    pub fn record(&mut self, edit: Edit, selection_before: Selection) {
+        let now = Instant::now();
+        let in_group = self.group_depth > 0 && self.group_started;
+        let merging = self.group_depth == 0
+            && self
+                .last_edit
+                .is_some_and(|t| now.duration_since(t) < MERGE_TIMEOUT);
+
+        // Дописывать можно только в лист: от остальных узлов зависят ветки.
+        let extendable = (in_group || merging)
+            && self.current != self.root
+            && self.nodes[&self.current].children.is_empty();
+
+        let mut absorbed = false;
+        if extendable {
+            let tx = &mut self.node_mut(self.current).tx;
+            if in_group || tx.edits.len() == 1 {
+                absorbed = tx.edits.last_mut().is_some_and(|e| e.absorb(&edit));
+            }
+        }
+
+        if absorbed {
+            // Слитая правка не добавляет новой записи, только текст.
+            self.used += edit.size() - EDIT_OVERHEAD;
+        } else if extendable && in_group {
+            self.used += edit.size();
+            self.node_mut(self.current).tx.edits.push(edit);
+        } else {
+            self.used += edit.size();
+            self.push_node(Transaction {
+                edits: vec![edit],
+                line_ending: None,
+                selection_before,
+                selection_after: selection_before,
+            });
+        }
+
+        self.last_edit = (self.group_depth == 0).then_some(now);
+        self.selection_pending = true;
+        self.enforce_budget();
+    }
+
+    /// Перевод на другие окончания строк — отдельный шаг отмены.
+    pub fn record_line_ending(&mut self, change: LineEndingChange, selection: Selection) {
+        self.push_node(Transaction {
+            edits: Vec::new(),
+            line_ending: Some(change),
+            selection_before: selection,
+            selection_after: selection,
+        });
+        self.break_merge();
+        self.selection_pending = false;
+    }
+
+    /// Новый узел — потомок текущего — становится текущим.
+    fn push_node(&mut self, tx: Transaction) {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.nodes.insert(
+            id,
+            Node {
+                parent: Some(self.current),
+                children: Vec::new(),
+                tx,
+                time: SystemTime::now(),
+                redo_child: None,
+            },
+        );
+        let parent = self.node_mut(self.current);
+        parent.children.push(id);
+        parent.redo_child = Some(id);
+        self.current = id;
+        self.group_started = self.group_depth > 0;
+    }
+
+    /// Запоминает выделение, получившееся после только что записанной правки.
+    pub fn settle_selection(&mut self, selection: Selection) {
+        if self.selection_pending {
+            let current = self.current;
+            self.node_mut(current).tx.selection_after = selection;
+            self.selection_pending = false;
+        }
+    }
+
+    /// Шаг назад: текущим становится родитель. Возвращает узел, правки
+    /// которого надо откатить.
+    pub fn undo_step(&mut self) -> Option<NodeId> {
+        self.break_merge();
+        self.selection_pending = false;
+        let id = self.current;
+        let parent = self.nodes[&id].parent?;
+        self.node_mut(parent).redo_child = Some(id);
+        self.current = parent;
+        Some(id)
+    }
+
+    /// Шаг вперёд по последней посещённой ветке. Возвращает узел, правки
+    /// которого надо применить.
+    pub fn redo_step(&mut self) -> Option<NodeId> {
+        self.break_merge();
+        self.selection_pending = false;
+        let node = &self.nodes[&self.current];
+        let child = node.redo_child.or_else(|| node.children.last().copied())?;
+        self.current = child;
+        Some(child)
+    }
+
+    /// Путь от текущего состояния к `target`: сначала узлы, правки которых
+    /// откатываются (снизу вверх), потом узлы, правки которых применяются
+    /// (сверху вниз).
+    pub fn path_to(&self, target: NodeId) -> Option<(Vec<NodeId>, Vec<NodeId>)> {
+        if !self.nodes.contains_key(&target) {
+            return None;
+        }
+        let up_from_target = self.ancestors(target);
+        let on_target_path: HashSet<NodeId> = up_from_target.iter().copied().collect();
+
+        let mut revert = Vec::new();
+        let mut id = self.current;
+        while !on_target_path.contains(&id) {
+            revert.push(id);
+            id = self.nodes[&id].parent?;
+        }
+        let common = id;
+
+        let mut apply: Vec<NodeId> = up_from_target
+            .into_iter()
+            .take_while(|&n| n != common)
+            .collect();
+        apply.reverse();
+        Some((revert, apply))
+    }
+
+    /// Делает текущим `target`, после того как документ прошёл [`History::path_to`].
+    pub fn set_current(&mut self, target: NodeId) {
+        self.break_merge();
+        self.selection_pending = false;
+        // "Повторить" после перехода ведёт по той же ветке.
+        let mut id = target;
+        while let Some(parent) = self.nodes[&id].parent {
+            self.node_mut(parent).redo_child = Some(id);
+            id = parent;
+        }
+        self.current = target;
+    }
+
+    pub fn take_transaction(&mut self, id: NodeId) -> Transaction {
+        std::mem::take(&mut self.node_mut(id).tx)
+    }
+
+    pub fn put_transaction(&mut self, id: NodeId, tx: Transaction) {
+        self.node_mut(id).tx = tx;
+    }
+
+    pub fn transaction(&self, id: NodeId) -> &Transaction {
+        &self.nodes[&id].tx
+    }
+
+    /// Узлы дерева для панели: обход в глубину, новые ветки со сдвигом вправо.
+    pub fn rows(&self) -> Vec<TreeRow> {
+        let mut rows = Vec::with_capacity(self.nodes.len());
+        let mut stack = vec![(self.root, 0)];
+        while let Some((id, depth)) = stack.pop() {
+            let node = &self.nodes[&id];
+            rows.push(TreeRow {
+                id,
+                depth,
+                time: node.time,
+                summary: node.tx.summary(),
+                current: id == self.current,
+            });
+            for (i, &child) in node.children.iter().enumerate().rev() {
+                stack.push((child, depth + i));
+            }
+        }
+        rows
+    }
+
+    fn node_mut(&mut self, id: NodeId) -> &mut Node {
+        self.nodes.get_mut(&id).expect("узел истории")
+    }
+
+    /// Узел и все его предки до корня.
+    fn ancestors(&self, mut id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![id];
+        while let Some(parent) = self.nodes[&id].parent {
+            path.push(parent);
+            id = parent;
+        }
+        path
+    }
+
+    /// Укладывает историю в бюджет: сперва выбрасывает самые старые ветки,
+    /// не ведущие к текущему состоянию, потом самые старые шаги на пути к
+    /// нему. Последний шаг оставляем всегда, даже если он один больше бюджета.
+    fn enforce_budget(&mut self) {
+        if self.used <= self.budget {
+            return;
+        }
+        let keep: HashSet<NodeId> = self.ancestors(self.current).into_iter().collect();
+
+        while self.used > self.budget {
+            let oldest_leaf = self
+                .nodes
+                .iter()
+                .find(|(id, node)| node.children.is_empty() && !keep.contains(id))
+                .map(|(&id, _)| id);
+            if let Some(leaf) = oldest_leaf {
+                self.remove_node(leaf);
+                continue;
+            }
+
+            // Остался только путь к текущему: корнем становится следующий узел.
+            let root = self.root;
+            let Some(&next) = self.nodes[&root].children.first() else {
+                break;
+            };
+            if next == self.current {
+                break;
+            }
+            self.nodes.remove(&root);
+            let node = self.node_mut(next);
+            node.parent = None;
+            let tx = std::mem::take(&mut node.tx);
+            self.used -= tx.size();
+            self.root = next;
+        }
+    }
+
+    fn remove_node(&mut self, id: NodeId) {
+        if let Some(node) = self.nodes.remove(&id) {
+            self.used -= node.tx.size();
+            if let Some(parent) = node.parent.and_then(|p| self.nodes.get_mut(&p)) {
+                parent.children.retain(|&c| c != id);
+                if parent.redo_child == Some(id) {
+                    parent.redo_child = None;
+                }
+            }
+        }
+    }
+}
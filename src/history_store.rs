@@ -0,0 +1,206 @@
+//! Историю отмены сохраняем рядом с сессией, чтобы Ctrl+Z работал и после
+//! перезапуска редактора.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::document::Document;
+use crate::hash::fnv64;
+use crate::history::HistoryData;
+
+const FORMAT_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct StoredHistory {
+    version: u32,
+    path: PathBuf,
+    /// Хэш текста, на котором история заканчивается.
+    content_hash: u64,
+    history: HistoryData,
+}
+
+fn history_dir() -> Option<PathBuf> {
+    eframe::storage_dir(crate::APP_NAME).map(|dir| dir.join("history"))
+}
+
+fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Файл истории для документа по пути `path`.
+fn history_file(dir: &Path, path: &Path) -> PathBuf {
+    let path = canonical(path);
+    let key = fnv64(path.as_os_str().as_encoded_bytes());
+    dir.join(format!("{key:016x}.json"))
+}
+
+/// Сохраняет историю документа.
+///
+/// Пишем её, только когда в документе нет несохранённых изменений: тогда текст
+/// совпадает с файлом на диске и при следующем открытии хэш можно будет сверить.
+pub fn store(doc: &Document) -> io::Result<()> {
+    match history_dir() {
+        Some(dir) => store_in(&dir, doc),
+        None => Ok(()),
+    }
+}
+
+fn store_in(dir: &Path, doc: &Document) -> io::Result<()> {
+    let Some(path) = &doc.path else {
+        return Ok(());
+    };
+    if doc.dirty {
+        return Ok(());
+    }
+    let file = history_file(dir, path);
+
+    if doc.history().is_empty() {
+        return match fs::remove_file(&file) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        };
+    }
+
+    let stored = StoredHistory {
+        version: FORMAT_VERSION,
+        path: canonical(path),
+        content_hash: doc.content_hash(),
+        history: doc.history().to_data(),
+    };
+    fs::create_dir_all(dir)?;
+    fs::write(&file, serde_json::to_vec(&stored)?)
+}
+
+/// Подхватывает сохранённую историю только что открытого документа.
+///
+/// Если файл с тех пор изменили на диске, хэш не совпадёт — такую историю
+/// применять нельзя, её удаляем. Испорченный файл истории и историю другого
+/// файла с тем же хэшем пути тоже удаляем.
+/// Возвращает, была ли история восстановлена.
+pub fn restore(doc: &mut Document) -> io::Result<bool> {
+    match history_dir() {
+        Some(dir) => restore_from(&dir, doc),
+        None => Ok(false),
+    }
+}
+
+fn restore_from(dir: &Path, doc: &mut Document) -> io::Result<bool> {
+    let Some(path) = doc.path.as_deref().map(canonical) else {
+        return Ok(false);
+    };
+    let file = history_file(dir, &path);
+    let bytes = match fs::read(&file) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(err),
+    };
+
+    let restored = match serde_json::from_slice::<StoredHistory>(&bytes) {
+        Ok(stored)
+            if stored.version == FORMAT_VERSION
+                && stored.path == path
+                && stored.content_hash == doc.content_hash() =>
+        {
+            doc.restore_history(stored.history)
+        }
+        _ => false,
+    };
+    if !restored {
+        fs::remove_file(&file)?;
+    }
+    Ok(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Документ из файла в `dir` с одной правкой, уже сохранённой на диск.
+    fn edited_file(dir: &Path) -> Document {
+        let path = dir.join("text.txt");
+        fs::write(&path, "начало\n").unwrap();
+        let mut doc = Document::from_file(1, path.clone(), None).unwrap();
+        doc.insert(0, "новое ");
+        fs::write(&path, doc.text().to_string()).unwrap();
+        doc.dirty = false;
+        doc
+    }
+
+    #[test]
+    fn restores_history_only_for_the_same_text() {
+        let dir = std::env::temp_dir().join(format!("rte-history-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let doc = edited_file(&dir);
+        let path = doc.path.clone().unwrap();
+        let file = history_file(&dir, &path);
+        store_in(&dir, &doc).unwrap();
+
+        // Тот же текст: история на месте, Ctrl+Z возвращает исходный.
+        let mut reopened = Document::from_file(2, path.clone(), None).unwrap();
+        assert!(restore_from(&dir, &mut reopened).unwrap());
+        reopened.undo();
+        assert_eq!(reopened.text().to_string(), "начало\n");
+
+        // Под тем же ключом оказалась история другого файла: её не применяем.
+        let mut json: serde_json::Value =
+            serde_json::from_slice(&fs::read(&file).unwrap()).unwrap();
+        json["path"] = dir.join("другой.txt").to_string_lossy().as_ref().into();
+        fs::write(&file, serde_json::to_vec(&json).unwrap()).unwrap();
+        let mut other = Document::from_file(4, path.clone(), None).unwrap();
+        assert!(!restore_from(&dir, &mut other).unwrap());
+        assert!(other.history().is_empty());
+        assert!(!file.exists());
+        store_in(&dir, &doc).unwrap();
+
+        // Файл изменили снаружи: хэш не сходится, историю выбрасываем.
+        fs::write(&path, "другое\n").unwrap();
+        let mut changed = Document::from_file(3, path.clone(), None).unwrap();
+        assert!(!restore_from(&dir, &mut changed).unwrap());
+        assert!(changed.history().is_empty());
+        assert!(!file.exists());
+
+        // Нечитаемый файл истории удаляется и ничего не ломает.
+        fs::write(&file, "{не json").unwrap();
+        assert!(!restore_from(&dir, &mut changed).unwrap());
+        assert!(!file.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_broken_tree() {
+        let dir = std::env::temp_dir().join(format!("rte-history-tree-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let doc = edited_file(&dir);
+        let path = doc.path.clone().unwrap();
+        let file = history_file(&dir, &path);
+
+        let corrupt = |edit: &dyn Fn(&mut serde_json::Value)| {
+            store_in(&dir, &doc).unwrap();
+            let mut json: serde_json::Value =
+                serde_json::from_slice(&fs::read(&file).unwrap()).unwrap();
+            edit(&mut json["history"]);
+            fs::write(&file, serde_json::to_vec(&json).unwrap()).unwrap();
+            let mut reopened = Document::from_file(2, path.clone(), None).unwrap();
+            let restored = restore_from(&dir, &mut reopened).unwrap();
+            assert!(reopened.history().is_empty() != restored);
+            restored
+        };
+
+        assert!(corrupt(&|_| {}));
+        assert!(!corrupt(&|h| h["nodes"]["1"]["parent"] = 7.into()));
+        assert!(!corrupt(
+            &|h| h["nodes"]["0"]["children"] = serde_json::json!([1, 5])
+        ));
+        assert!(!corrupt(&|h| h["next_id"] = 1.into()));
+        assert!(!corrupt(&|h| h["current"] = 9.into()));
+        assert!(!file.exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+//! Отступы документа: табуляция или пробелы и сколько их на уровень.
+
+use ropey::Rope;
+
+use crate::syntax::Language;
+
+/// Сколько строк смотрим, чтобы угадать отступ файла.
+const DETECT_LINES: usize = 2000;
+
+/// Ширина табуляции, когда отступ — табуляцией: для перевода в пробелы.
+const TAB_WIDTH: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Indent {
+    Tabs,
+    Spaces(u8),
+}
+
+impl Default for Indent {
+    fn default() -> Self {
+        Indent::Spaces(4)
+    }
+}
+
+impl Indent {
+    /// Варианты для меню.
+    pub const CHOICES: [Indent; 4] = [
+        Indent::Tabs,
+        Indent::Spaces(2),
+        Indent::Spaces(4),
+        Indent::Spaces(8),
+    ];
+
+    pub fn label(self) -> String {
+        match self {
+            Indent::Tabs => "Табуляция".to_string(),
+            Indent::Spaces(width) => format!("Пробелы: {width}"),
+        }
+    }
+
+    /// `tab`, `табуляция`, `4`, `Пробелы: 2`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        if matches!(name.as_str(), "tab" | "tabs" | "табуляция") {
+            return Some(Indent::Tabs);
+        }
+        let width: u8 = name.trim_start_matches("пробелы:").trim().parse().ok()?;
+        (1..=8).contains(&width).then_some(Indent::Spaces(width))
+    }
+
+    /// Сколько столбцов занимает уровень отступа.
+    pub fn width(self) -> usize {
+        match self {
+            Indent::Tabs => TAB_WIDTH,
+            Indent::Spaces(width) => width as usize,
+        }
+    }
+
+    /// Один уровень отступа.
+    pub fn unit(self) -> String {
+        match self {
+            Indent::Tabs => "\t".to_string(),
+            Indent::Spaces(width) => " ".repeat(width as usize),
+        }
+    }
+
+    /// Что вставить по Tab в столбце `column`: пробелы доводят до
+    /// следующего уровня, а не добавляют уровень целиком.
+    pub fn fill(self, column: usize) -> String {
+        match self {
+            Indent::Tabs => "\t".to_string(),
+            Indent::Spaces(_) => " ".repeat(self.width() - column % self.width()),
+        }
+    }
+
+    /// Ведущие пробелы и табуляции `leading`, записанные этим отступом.
+    /// Табуляция в `leading` считается шириной `width`.
+    pub fn rewrite(self, leading: &str, width: usize) -> String {
+        let columns = leading.chars().fold(0, |column, c| match c {
+            '\t' => column + width - column % width,
+            _ => column + 1,
+        });
+        match self {
+            Indent::Tabs => "\t".repeat(columns / width) + &" ".repeat(columns % width),
+            Indent::Spaces(_) => " ".repeat(columns),
+        }
+    }
+
+    /// Отступ, которым написан `text`, если строки с отступом вообще есть.
+    ///
+    /// Ширину пробельного отступа берём по самой частой разнице отступов
+    /// соседних строк: так выравнивание продолжений строк не мешает.
+    pub fn detect(text: &Rope) -> Option<Self> {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut steps = [0usize; 9];
+        let mut previous = 0;
+        for line in text.lines().take(DETECT_LINES) {
+            let mut chars = line.chars().peekable();
+            if chars.peek() == Some(&'\t') {
+                tabs += 1;
+                continue;
+            }
+            let width = chars.take_while(|&c| c == ' ').count();
+            if line.chars().all(char::is_whitespace) {
+                continue;
+            }
+            if width > 0 {
+                spaces += 1;
+            }
+            let step = width.abs_diff(previous);
+            if (1..steps.len()).contains(&step) {
+                steps[step] += 1;
+            }
+            previous = width;
+        }
+
+        if tabs == 0 && spaces == 0 {
+            return None;
+        }
+        if tabs > spaces {
+            return Some(Indent::Tabs);
+        }
+        // При равенстве — меньшая ширина: 2 и 4 часто встречаются вместе.
+        let (width, _) = steps
+            .iter()
+            .enumerate()
+            .rev()
+            .max_by_key(|&(_, count)| *count)?;
+        Some(Indent::Spaces(width.max(1) as u8))
+    }
+}
+
+/// Перевод строки в позиции `pos` с отступом текущей строки. После
+/// открывающей скобки, а в Python и YAML — после двоеточия, на уровень больше.
+pub fn line_break(text: &Rope, pos: usize, indent: Indent, language: Language) -> String {
+    let line = text.char_to_line(pos);
+    let before: String = text.slice(text.line_to_char(line)..pos).chars().collect();
+    let leading = &before[..before.len() - before.trim_start().len()];
+    let mut result = format!("\n{leading}");
+    let opens_block = match before.trim_end().chars().last() {
+        Some('{' | '(' | '[') => true,
+        Some(':') => matches!(language, Language::Python | Language::Yaml),
+        _ => false,
+    };
+    if opens_block {
+        result.push_str(&indent.unit());
+    }
+    result
+}
+
+/// Первый уровень ведущего отступа строки `line`, который снимает
+/// Shift+Tab: табуляция или до `width` пробелов.
+pub fn outdent_len(line: &str, indent: Indent) -> usize {
+    if line.starts_with('\t') {
+        return 1;
+    }
+    line.chars()
+        .take(indent.width())
+        .take_while(|&c| c == ' ')
+        .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_indentation() {
+        let detect = |text: &str| Indent::detect(&Rope::from_str(text));
+        assert_eq!(detect("a\n  b\n    c\n  d\n"), Some(Indent::Spaces(2)));
+        assert_eq!(
+            detect("fn f() {\n    if x {\n        y(1,\n          2);\n    }\n}\n"),
+            Some(Indent::Spaces(4))
+        );
+        assert_eq!(detect("a\n\tb\n\t\tc\n"), Some(Indent::Tabs));
+        assert_eq!(detect("без отступов\n"), None);
+
+        assert_eq!(Indent::from_name("Пробелы: 2"), Some(Indent::Spaces(2)));
+        assert_eq!(Indent::from_name("tab"), Some(Indent::Tabs));
+        assert_eq!(Indent::from_name("0"), None);
+    }
+
+    #[test]
+    fn indents_new_lines_and_rewrites_indentation() {
+        let text = Rope::from_str("fn f() {\n    if x {\n        y\n");
+        let at_end_of = |line: usize| text.line_to_char(line + 1) - 1;
+        let spaces = Indent::Spaces(4);
+        assert_eq!(
+            line_break(&text, at_end_of(0), spaces, Language::Rust),
+            "\n    "
+        );
+        assert_eq!(
+            line_break(&text, at_end_of(2), spaces, Language::Rust),
+            "\n        "
+        );
+        let python = Rope::from_str("\tif x:\n");
+        assert_eq!(
+            line_break(&python, 6, Indent::Tabs, Language::Python),
+            "\n\t\t"
+        );
+
+        assert_eq!(spaces.fill(6), "  ");
+        assert_eq!(Indent::Tabs.rewrite("      ", 4), "\t  ");
+        assert_eq!(Indent::Spaces(2).rewrite("\t ", 2), "   ");
+        assert_eq!(outdent_len("      x", spaces), 4);
+        assert_eq!(outdent_len("\t\tx", spaces), 1);
+    }
+}
@@ -0,0 +1,224 @@
+//! Раскладка горячих клавиш: сочетания по умолчанию из [`Action`] плюс
+//! переопределения из файла `keymap.toml` в каталоге данных редактора:
+//!
+//! ```toml
+//! "file.save" = "Ctrl+S"
+//! "edit.redo" = ["Ctrl+Shift+Z", "Ctrl+Y"]
+//! "file.print" = []  # отключить сочетание
+//! ```
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+use eframe::egui::{self, Key, KeyboardShortcut, Modifiers};
+use serde::Deserialize;
+
+use crate::actions::Action;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Chords {
+    One(String),
+    Many(Vec<String>),
+}
+
+pub struct Keymap {
+    /// В порядке [`Action::ALL`].
+    bindings: Vec<(Action, Vec<KeyboardShortcut>)>,
+    /// Ошибки в файле раскладки и конфликты сочетаний — их показываем пользователю.
+    pub problems: Vec<String>,
+}
+
+impl Keymap {
+    pub fn defaults() -> Self {
+        Self {
+            bindings: Action::ALL
+                .iter()
+                .map(|&action| (action, action.default_shortcuts()))
+                .collect(),
+            problems: Vec::new(),
+        }
+    }
+
+    /// Раскладка по умолчанию с переопределениями из файла, если он есть.
+    pub fn load() -> Self {
+        let mut keymap = Self::defaults();
+        if let Some(path) = keymap_path() {
+            match fs::read_to_string(&path) {
+                Ok(source) => keymap.apply_overrides(&source),
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {}
+                Err(err) => keymap
+                    .problems
+                    .push(format!("Не удалось прочитать {}: {err}", path.display())),
+            }
+        }
+        keymap.check_conflicts();
+        keymap
+    }
+
+    fn apply_overrides(&mut self, source: &str) {
+        let table: HashMap<String, Chords> = match toml::from_str(source) {
+            Ok(table) => table,
+            Err(err) => {
+                self.problems
+                    .push(format!("Ошибка в файле раскладки: {err}"));
+                return;
+            }
+        };
+
+        for (id, chords) in table {
+            let Some(action) = Action::ALL.iter().copied().find(|a| a.id() == id) else {
+                self.problems.push(format!("Неизвестная команда «{id}»"));
+                continue;
+            };
+            let chords = match chords {
+                Chords::One(chord) => vec![chord],
+                Chords::Many(chords) => chords,
+            };
+
+            let mut shortcuts = Vec::new();
+            for chord in chords.iter().filter(|c| !c.trim().is_empty()) {
+                match parse_chord(chord) {
+                    Ok(shortcut) => shortcuts.push(shortcut),
+                    Err(err) => self.problems.push(format!("{id}: {err}")),
+                }
+            }
+            if let Some((_, bound)) = self.bindings.iter_mut().find(|(a, _)| *a == action) {
+                *bound = shortcuts;
+            }
+        }
+    }
+
+    /// Одно сочетание на две команды — сработала бы только одна из них.
+    fn check_conflicts(&mut self) {
+        let mut owners: Vec<(KeyboardShortcut, Action)> = Vec::new();
+        for (action, shortcuts) in &self.bindings {
+            for shortcut in shortcuts {
+                match owners.iter().find(|(s, _)| s == shortcut) {
+                    Some((_, owner)) if owner != action => self.problems.push(format!(
+                        "{} назначено и на «{}», и на «{}»",
+                        format_chord(shortcut),
+                        owner.label(),
+                        action.label()
+                    )),
+                    Some(_) => {}
+                    None => owners.push((*shortcut, *action)),
+                }
+            }
+        }
+    }
+
+    /// Сочетание, которое показываем в меню.
+    pub fn shortcut(&self, action: Action) -> Option<KeyboardShortcut> {
+        self.bindings
+            .iter()
+            .find(|(a, _)| *a == action)
+            .and_then(|(_, shortcuts)| shortcuts.first().copied())
+    }
+
+    /// Забирает из ввода нажатые сочетания, чтобы их не обработал
+    /// текстовый виджет, и возвращает соответствующие команды.
+    pub fn consume_pressed(&self, ctx: &egui::Context) -> Vec<Action> {
+        let mut all: Vec<(KeyboardShortcut, Action)> = self
+            .bindings
+            .iter()
+            .flat_map(|(action, shortcuts)| shortcuts.iter().map(|s| (*s, *action)))
+            .collect();
+        // egui сравнивает модификаторы "логически": Ctrl+Z сработал бы и на
+        // Ctrl+Shift+Z. Поэтому сначала проверяем сочетания с большим числом клавиш.
+        all.sort_by_key(|(s, _)| std::cmp::Reverse(modifier_count(s.modifiers)));
+
+        ctx.input_mut(|i| {
+            all.iter()
+                .filter(|(shortcut, _)| i.consume_shortcut(shortcut))
+                .map(|(_, action)| *action)
+                .collect()
+        })
+    }
+}
+
+fn keymap_path() -> Option<PathBuf> {
+    eframe::storage_dir(crate::APP_NAME).map(|dir| dir.join("keymap.toml"))
+}
+
+fn modifier_count(modifiers: Modifiers) -> usize {
+    [modifiers.alt, modifiers.command, modifiers.shift]
+        .iter()
+        .filter(|&&m| m)
+        .count()
+}
+
+/// Разбирает сочетание вида `Ctrl+Shift+S`, `Alt+Up`, `F3`.
+pub fn parse_chord(chord: &str) -> Result<KeyboardShortcut, String> {
+    let chord = chord.trim();
+    // "Ctrl++" — клавиша плюс.
+    let (mods, key) = match chord.strip_suffix("++") {
+        Some(mods) => (mods, "+"),
+        None => chord.rsplit_once('+').unwrap_or(("", chord)),
+    };
+
+    let mut modifiers = Modifiers::NONE;
+    for part in mods.split('+').map(str::trim).filter(|p| !p.is_empty()) {
+        match part.to_lowercase().as_str() {
+            "ctrl" | "control" | "cmd" | "command" => {
+                modifiers = modifiers.plus(Modifiers::COMMAND)
+            }
+            "shift" => modifiers = modifiers.plus(Modifiers::SHIFT),
+            "alt" | "option" => modifiers = modifiers.plus(Modifiers::ALT),
+            _ => return Err(format!("неизвестный модификатор «{part}» в «{chord}»")),
+        }
+    }
+
+    let key = key.trim();
+    let key = Key::from_name(key)
+        .or_else(|| Key::from_name(&key.to_uppercase()))
+        .ok_or_else(|| format!("неизвестная клавиша «{key}» в «{chord}»"))?;
+    Ok(KeyboardShortcut::new(modifiers, key))
+}
+
+/// Сочетание в том виде, в каком его пишут в файле раскладки.
+pub fn format_chord(shortcut: &KeyboardShortcut) -> String {
+    let mut parts = Vec::new();
+    if shortcut.modifiers.command {
+        parts.push("Ctrl");
+    }
+    if shortcut.modifiers.alt {
+        parts.push("Alt");
+    }
+    if shortcut.modifiers.shift {
+        parts.push("Shift");
+    }
+    parts.push(shortcut.logical_key.name());
+    parts.join("+")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_chords() {
+        let save = parse_chord("ctrl+shift+s").unwrap();
+        assert_eq!(
+            save,
+            KeyboardShortcut::new(Modifiers::COMMAND.plus(Modifiers::SHIFT), Key::S)
+        );
+        assert_eq!(parse_chord("F3").unwrap().logical_key, Key::F3);
+        assert_eq!(parse_chord("Ctrl++").unwrap().logical_key, Key::Plus);
+        assert!(parse_chord("Hyper+S").is_err());
+        assert_eq!(format_chord(&save), "Ctrl+Shift+S");
+    }
+
+    #[test]
+    fn reports_conflicting_overrides() {
+        let mut keymap = Keymap::defaults();
+        keymap.apply_overrides("\"file.print\" = \"Ctrl+S\"\n\"file.nope\" = \"F1\"");
+        keymap.check_conflicts();
+        assert_eq!(keymap.problems.len(), 2);
+        assert_eq!(
+            keymap.shortcut(Action::Print),
+            keymap.shortcut(Action::Save)
+        );
+    }
+}
@@ -0,0 +1,121 @@
+//! Команды над целыми строками: дублировать, удалить, переместить,
+//! объединить, отсортировать и т. п. Здесь — только преобразование строк;
+//! как выбираются строки и как правка попадает в историю, решает
+//! [`Document::line_command`](crate::document::Document::line_command).
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LineCommand {
+    Duplicate,
+    Delete,
+    MoveUp,
+    MoveDown,
+    Join,
+    Sort(SortOrder),
+    RemoveDuplicates,
+    Reverse,
+    TrimTrailingWhitespace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortOrder {
+    Ascending,
+    Descending,
+    /// По числу в начале строки; строки без числа — первыми.
+    Numeric,
+    CaseInsensitive,
+}
+
+/// Новые строки вместо `lines` для команд, которые не двигают границы
+/// блока: сортировка, повторы, обращение, пробелы в конце, объединение.
+pub fn transform(command: LineCommand, lines: &[&str]) -> Vec<String> {
+    let mut lines: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    match command {
+        LineCommand::Sort(SortOrder::Ascending) => lines.sort(),
+        LineCommand::Sort(SortOrder::Descending) => lines.sort_by(|a, b| b.cmp(a)),
+        LineCommand::Sort(SortOrder::CaseInsensitive) => {
+            lines.sort_by_key(|line| line.to_lowercase())
+        }
+        LineCommand::Sort(SortOrder::Numeric) => {
+            lines.sort_by(|a, b| match (leading_number(a), leading_number(b)) {
+                (Some(a), Some(b)) => a.total_cmp(&b),
+                (a, b) => a.is_some().cmp(&b.is_some()),
+            })
+        }
+        LineCommand::RemoveDuplicates => {
+            let mut seen = std::collections::HashSet::new();
+            lines.retain(|line| seen.insert(line.clone()));
+        }
+        LineCommand::Reverse => lines.reverse(),
+        LineCommand::TrimTrailingWhitespace => {
+            for line in &mut lines {
+                line.truncate(line.trim_end().len());
+            }
+        }
+        LineCommand::Join => {
+            let mut joined = String::new();
+            for (i, line) in lines.iter().enumerate() {
+                let piece = if i == 0 { line.trim_end() } else { line.trim() };
+                if !joined.is_empty() && !piece.is_empty() {
+                    joined.push(' ');
+                }
+                joined.push_str(piece);
+            }
+            lines = vec![joined];
+        }
+        LineCommand::Duplicate
+        | LineCommand::Delete
+        | LineCommand::MoveUp
+        | LineCommand::MoveDown => {}
+    }
+    lines
+}
+
+/// Число в начале строки, после пробелов: `42`, `-3.5`.
+fn leading_number(line: &str) -> Option<f64> {
+    let line = line.trim_start();
+    let mut end = 0;
+    let mut seen_dot = false;
+    for (i, c) in line.char_indices() {
+        let sign = i == 0 && (c == '-' || c == '+');
+        let dot = c == '.' && !seen_dot;
+        if !(c.is_ascii_digit() || sign || dot) {
+            break;
+        }
+        seen_dot |= dot;
+        end = i + 1;
+    }
+    line[..end].parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms_lines() {
+        let lines = ["10 б", "9 а", "Б", "а", "", "9 а"];
+        let sorted = |order| transform(LineCommand::Sort(order), &lines);
+        assert_eq!(
+            sorted(SortOrder::Ascending),
+            ["", "10 б", "9 а", "9 а", "Б", "а"].map(String::from)
+        );
+        assert_eq!(
+            sorted(SortOrder::Numeric),
+            ["Б", "а", "", "9 а", "9 а", "10 б"].map(String::from)
+        );
+        assert_eq!(
+            sorted(SortOrder::CaseInsensitive)[4..],
+            ["а", "Б"].map(String::from)
+        );
+        assert_eq!(transform(LineCommand::RemoveDuplicates, &lines).len(), 5);
+
+        assert_eq!(
+            transform(LineCommand::Join, &["fn f() {  ", "    x", "}"]),
+            ["fn f() { x }"]
+        );
+        assert_eq!(
+            transform(LineCommand::TrimTrailingWhitespace, &["a \t", " b"]),
+            ["a", " b"]
+        );
+    }
+}
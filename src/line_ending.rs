@@ -0,0 +1,124 @@
+//! Окончания строк. Внутри редактора строки всегда разделены `\n`, а при
+//! сохранении переводятся в окончания файла.
+
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    CrLf,
+    Cr,
+}
+
+impl LineEnding {
+    pub const ALL: [LineEnding; 3] = [LineEnding::Lf, LineEnding::CrLf, LineEnding::Cr];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::CrLf => "CRLF",
+            LineEnding::Cr => "CR",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "\n",
+            LineEnding::CrLf => "\r\n",
+            LineEnding::Cr => "\r",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|ending| ending.label().eq_ignore_ascii_case(name.trim()))
+    }
+}
+
+/// Окончания строк, найденные в тексте.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Detected {
+    /// Самое частое; при равенстве — LF, потом CRLF.
+    pub dominant: LineEnding,
+    /// В тексте встречаются разные окончания.
+    pub mixed: bool,
+}
+
+pub fn detect(text: &str) -> Detected {
+    let (mut lf, mut crlf, mut cr) = (0usize, 0usize, 0usize);
+    let mut bytes = text.bytes().peekable();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\r' if bytes.peek() == Some(&b'\n') => {
+                bytes.next();
+                crlf += 1;
+            }
+            b'\r' => cr += 1,
+            b'\n' => lf += 1,
+            _ => {}
+        }
+    }
+
+    let dominant = if crlf > lf && crlf >= cr {
+        LineEnding::CrLf
+    } else if cr > lf && cr > crlf {
+        LineEnding::Cr
+    } else {
+        LineEnding::Lf
+    };
+    let kinds = [lf, crlf, cr].iter().filter(|&&n| n > 0).count();
+    Detected {
+        dominant,
+        mixed: kinds > 1,
+    }
+}
+
+/// Приводит все окончания строк к `\n`.
+pub fn normalize(text: &str) -> Cow<'_, str> {
+    if !text.contains('\r') {
+        return Cow::Borrowed(text);
+    }
+    Cow::Owned(text.replace("\r\n", "\n").replace('\r', "\n"))
+}
+
+/// Переводит `\n` в окончания `ending` для записи в файл.
+pub fn apply(text: &str, ending: LineEnding) -> Cow<'_, str> {
+    match ending {
+        LineEnding::Lf => Cow::Borrowed(text),
+        _ => Cow::Owned(text.replace('\n', ending.as_str())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_dominant_and_mixed_endings() {
+        let crlf = detect("a\r\nb\r\nc\n");
+        assert_eq!(crlf.dominant, LineEnding::CrLf);
+        assert!(crlf.mixed);
+        assert_eq!(
+            detect("a\rb\r"),
+            Detected {
+                dominant: LineEnding::Cr,
+                mixed: false
+            }
+        );
+        assert_eq!(detect("одна строка").dominant, LineEnding::Lf);
+    }
+
+    #[test]
+    fn round_trips_through_lf() {
+        for ending in LineEnding::ALL {
+            let on_disk = format!("раз{0}два{0}{0}три{0}", ending.as_str());
+            let normalized = normalize(&on_disk);
+            assert_eq!(normalized, "раз\nдва\n\nтри\n");
+            assert_eq!(apply(&normalized, ending), on_disk);
+        }
+    }
+}
@@ -1,16 +1,40 @@
+mod actions;
 mod app;
 mod document;
+mod editorconfig;
+mod encoding;
+mod file_io;
+mod folder_search;
+mod gutter;
+mod hash;
+mod history;
+mod history_store;
+mod indent;
+mod keymap;
+mod line_commands;
+mod line_ending;
+mod merge;
+mod multi_cursor;
+mod notifications;
+mod palette;
+mod search;
+mod session;
+mod swap;
+mod symbols;
+mod syntax;
+mod text_buffer;
 
 use app::TextEditorApp;
 
+/// Имя приложения; по нему eframe выбирает каталог для сохранения данных.
+pub const APP_NAME: &str = "Rust Text Editor";
+
 fn main() -> eframe::Result<()> {
     let native_options = eframe::NativeOptions::default();
 
     eframe::run_native(
-        "Rust Text Editor",
+        APP_NAME,
         native_options,
-        Box::new(|cc| {
-            Ok(Box::new(TextEditorApp::new(cc)) as Box<dyn eframe::App>)
-        }),
+        Box::new(|cc| Ok(Box::new(TextEditorApp::new(cc)) as Box<dyn eframe::App>)),
     )
 }
@@ -0,0 +1,154 @@
+//! Трёхстороннее построчное слияние: общий предок и две версии с правками.
+//!
+//! Правки, которые не задевают одни и те же строки, объединяются. Там, где
+//! обе стороны поменяли одно место по-разному, остаётся конфликт с
+//! маркерами `<<<<<<<` / `=======` / `>>>>>>>`, как в git.
+
+use std::ops::Range;
+
+use similar::{Algorithm, DiffOp, capture_diff_slices};
+
+pub struct Merged {
+    pub text: String,
+    pub conflicts: usize,
+}
+
+/// Изменённый кусок: строки `base` предка заменены строками `lines` версии.
+struct Hunk {
+    base: Range<usize>,
+    lines: Range<usize>,
+}
+
+fn hunks(base: &[&str], side: &[&str]) -> Vec<Hunk> {
+    capture_diff_slices(Algorithm::Myers, base, side)
+        .into_iter()
+        .filter(|op| !matches!(op, DiffOp::Equal { .. }))
+        .map(|op| {
+            let (_, base, lines) = op.as_tag_tuple();
+            Hunk { base, lines }
+        })
+        .collect()
+}
+
+/// Текст версии на участке `range` предка.
+fn side_text(base: &[&str], side: &[&str], hunks: &[Hunk], range: Range<usize>) -> String {
+    let mut text = String::new();
+    let mut pos = range.start;
+    for hunk in hunks {
+        text.extend(base[pos..hunk.base.start].iter().copied());
+        text.extend(side[hunk.lines.clone()].iter().copied());
+        pos = hunk.base.end;
+    }
+    text.extend(base[pos..range.end].iter().copied());
+    text
+}
+
+fn push_line_block(out: &mut String, text: &str) {
+    out.push_str(text);
+    if !text.is_empty() && !text.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+pub fn merge3(
+    base: &str,
+    mine: &str,
+    theirs: &str,
+    mine_label: &str,
+    theirs_label: &str,
+) -> Merged {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let mine: Vec<&str> = mine.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let ours = hunks(&base, &mine);
+    let other = hunks(&base, &theirs);
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let mut pos = 0;
+    let (mut i, mut j) = (0, 0);
+
+    while i < ours.len() || j < other.len() {
+        // Группа — цепочка правок обеих сторон, которые перекрываются или соприкасаются.
+        let start = match (ours.get(i), other.get(j)) {
+            (Some(a), Some(b)) => a.base.start.min(b.base.start),
+            (Some(a), None) => a.base.start,
+            (None, Some(b)) => b.base.start,
+            (None, None) => unreachable!(),
+        };
+        let (first_ours, first_other) = (i, j);
+        let mut end = start;
+        loop {
+            let mut grew = false;
+            while let Some(hunk) = ours.get(i)
+                && hunk.base.start <= end
+            {
+                end = end.max(hunk.base.end);
+                i += 1;
+                grew = true;
+            }
+            while let Some(hunk) = other.get(j)
+                && hunk.base.start <= end
+            {
+                end = end.max(hunk.base.end);
+                j += 1;
+                grew = true;
+            }
+            if !grew {
+                break;
+            }
+        }
+
+        out.extend(base[pos..start].iter().copied());
+        let mine_text = side_text(&base, &mine, &ours[first_ours..i], start..end);
+        let theirs_text = side_text(&base, &theirs, &other[first_other..j], start..end);
+        if first_other == j || mine_text == theirs_text {
+            out.push_str(&mine_text);
+        } else if first_ours == i {
+            out.push_str(&theirs_text);
+        } else {
+            conflicts += 1;
+            out.push_str(&format!("<<<<<<< {mine_label}\n"));
+            push_line_block(&mut out, &mine_text);
+            out.push_str("=======\n");
+            push_line_block(&mut out, &theirs_text);
+            out.push_str(&format!(">>>>>>> {theirs_label}\n"));
+        }
+        pos = end;
+    }
+    out.extend(base[pos..].iter().copied());
+
+    Merged {
+        text: out,
+        conflicts,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge(base: &str, mine: &str, theirs: &str) -> Merged {
+        merge3(base, mine, theirs, "мои", "на диске")
+    }
+
+    #[test]
+    fn merges_separate_edits() {
+        let merged = merge("a\nb\nc\nd\n", "A\nb\nc\nd\n", "a\nb\nc\nD\n");
+        assert_eq!(merged.conflicts, 0);
+        assert_eq!(merged.text, "A\nb\nc\nD\n");
+
+        let same = merge("a\nb\n", "a\nx\n", "a\nx\n");
+        assert_eq!((same.text.as_str(), same.conflicts), ("a\nx\n", 0));
+    }
+
+    #[test]
+    fn marks_conflicts() {
+        let merged = merge("a\nb\nc\n", "a\nmine\nc\n", "a\ntheirs\nc");
+        assert_eq!(merged.conflicts, 1);
+        assert_eq!(
+            merged.text,
+            "a\n<<<<<<< мои\nmine\nc\n=======\ntheirs\nc\n>>>>>>> на диске\n"
+        );
+    }
+}
@@ -0,0 +1,338 @@
+//! Несколько курсоров: ввод, удаление, вставка и перемещение сразу во всех.
+//! Пока курсор один, всем этим занимается сам `TextEdit`; как только
+//! появляются дополнительные, эти события забираем у него и применяем к
+//! курсорам документа.
+
+use std::ops::Range;
+
+use eframe::egui::text::CCursor;
+use eframe::egui::{self, Event, Key, Pos2, Rect, Shape};
+use ropey::Rope;
+
+use crate::document::{Document, Selection};
+use crate::line_ending;
+
+/// Забирает у виджета события ввода и применяет их ко всем курсорам.
+pub fn handle_input(ctx: &egui::Context, doc: &mut Document) {
+    let events: Vec<Event> = ctx.input_mut(|i| {
+        let (ours, rest) = std::mem::take(&mut i.events).into_iter().partition(is_ours);
+        i.events = rest;
+        ours
+    });
+    for event in events {
+        apply(ctx, doc, event);
+    }
+}
+
+fn is_ours(event: &Event) -> bool {
+    match event {
+        Event::Text(_) | Event::Paste(_) | Event::Copy | Event::Cut => true,
+        Event::Key {
+            key,
+            pressed: true,
+            modifiers,
+            ..
+        } => {
+            let plain = modifiers.is_none() || *modifiers == egui::Modifiers::SHIFT;
+            // С Ctrl — по словам и к началу или концу текста.
+            let word = modifiers.command && !modifiers.alt;
+            match key {
+                Key::Backspace
+                | Key::Delete
+                | Key::ArrowLeft
+                | Key::ArrowRight
+                | Key::Home
+                | Key::End => plain || word,
+                Key::Enter | Key::Tab | Key::Escape | Key::ArrowUp | Key::ArrowDown => plain,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn apply(ctx: &egui::Context, doc: &mut Document, event: Event) {
+    let insert = |doc: &mut Document, text: &str| {
+        doc.edit_cursors(|_, _, selection| (selection.range(), text.to_string()));
+    };
+    match event {
+        Event::Text(text) => insert(doc, &text),
+        Event::Paste(text) => {
+            // Строк столько же, сколько курсоров, — каждому своя строка.
+            let text = line_ending::normalize(&text);
+            let lines: Vec<&str> = text.lines().collect();
+            let count = doc.extra_cursors().len() + 1;
+            doc.edit_cursors(|_, i, selection| {
+                let piece = if lines.len() == count {
+                    lines[i]
+                } else {
+                    &text
+                };
+                (selection.range(), piece.to_string())
+            });
+        }
+        Event::Copy | Event::Cut => {
+            let (all, _) = doc.all_cursors();
+            let pieces: Vec<String> = all
+                .iter()
+                .map(|c| doc.text().slice(c.range()).to_string())
+                .collect();
+            ctx.copy_text(pieces.join("\n"));
+            if event == Event::Cut {
+                insert(doc, "");
+            }
+        }
+        Event::Key { key, modifiers, .. } => match key {
+            Key::Backspace => doc.edit_cursors(|text, _, selection| {
+                let range = selection.range();
+                let range = match range.is_empty() {
+                    true if modifiers.command => {
+                        word_boundary(text, range.start, false)..range.start
+                    }
+                    true => range.start.saturating_sub(1)..range.start,
+                    false => range,
+                };
+                (range, String::new())
+            }),
+            Key::Delete => doc.edit_cursors(|text, _, selection| {
+                let range = selection.range();
+                let range = match range.is_empty() {
+                    true if modifiers.command => {
+                        range.start..word_boundary(text, range.start, true)
+                    }
+                    true => range.start..(range.start + 1).min(text.len_chars()),
+                    false => range,
+                };
+                (range, String::new())
+            }),
+            Key::Enter => doc.newline(),
+            Key::Tab => doc.tab(modifiers.shift),
+            Key::Escape => doc.clear_cursors(),
+            _ => doc.move_cursors(|text, selection| {
+                step(text, selection, key, modifiers.shift, modifiers.command)
+            }),
+        },
+        _ => {}
+    }
+}
+
+/// Курсор после нажатия стрелки, Home или End. С Shift выделение растёт,
+/// с Ctrl (`word`) стрелки идут по словам, а Home и End — к краям текста.
+fn step(text: &Rope, selection: Selection, key: Key, extend: bool, word: bool) -> Selection {
+    let range = selection.range();
+    let line = text.char_to_line(selection.head);
+    let line_start = text.line_to_char(line);
+    let line_len = |line: usize| text.line(line).chars().take_while(|&c| c != '\n').count();
+    let vertical = |target: usize| {
+        let column = selection.head - line_start;
+        text.line_to_char(target) + column.min(line_len(target))
+    };
+    let head = match key {
+        Key::ArrowLeft if word => word_boundary(text, selection.head, false),
+        Key::ArrowRight if word => word_boundary(text, selection.head, true),
+        Key::Home if word => 0,
+        Key::End if word => text.len_chars(),
+        Key::ArrowLeft if !extend && !range.is_empty() => range.start,
+        Key::ArrowRight if !extend && !range.is_empty() => range.end,
+        Key::ArrowLeft => selection.head.saturating_sub(1),
+        Key::ArrowRight => (selection.head + 1).min(text.len_chars()),
+        Key::Home => line_start,
+        Key::End => line_start + line_len(line),
+        Key::ArrowUp if line == 0 => 0,
+        Key::ArrowUp => vertical(line - 1),
+        Key::ArrowDown if line + 1 >= text.len_lines() => text.len_chars(),
+        Key::ArrowDown => vertical(line + 1),
+        _ => selection.head,
+    };
+    Selection {
+        anchor: if extend { selection.anchor } else { head },
+        head,
+    }
+}
+
+/// Граница слова от `pos` вправо или влево: сперва пропускаем пробелы и
+/// знаки, потом само слово.
+fn word_boundary(text: &Rope, pos: usize, forward: bool) -> usize {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    let count = |chars: &mut dyn Iterator<Item = char>| {
+        let mut chars = chars.peekable();
+        let mut n = 0;
+        while chars.next_if(|&c| !is_word(c)).is_some() {
+            n += 1;
+        }
+        while chars.next_if(|&c| is_word(c)).is_some() {
+            n += 1;
+        }
+        n
+    };
+    if forward {
+        pos + count(&mut text.chars_at(pos))
+    } else {
+        pos - count(&mut text.chars_at(pos).reversed())
+    }
+}
+
+/// Прямоугольное выделение (Alt+перетаскивание) от точки `from` до `to`:
+/// строка документа и x в раскладке. На каждую строку между ними —
+/// своё выделение; основным становится то, что на строке `to`.
+pub fn block_selection(
+    galley: &egui::Galley,
+    lines: &[(usize, Rect)],
+    view_start: usize,
+    from: (usize, f32),
+    to: (usize, f32),
+) -> Option<(Selection, Vec<Selection>)> {
+    let first = from.0.min(to.0);
+    let last = from.0.max(to.0);
+    let mut primary = None;
+    let mut extra = Vec::new();
+    for (line, rect) in lines
+        .iter()
+        .filter(|(line, _)| (first..=last).contains(line))
+    {
+        // Перенесённая строка выделяется по первому ряду.
+        let y = rect.min.y + 1.0;
+        let at = |x: f32| view_start + galley.cursor_from_pos(egui::vec2(x, y)).index;
+        let selection = Selection {
+            anchor: at(from.1),
+            head: at(to.1),
+        };
+        if *line == to.0 {
+            primary = Some(selection);
+        } else {
+            extra.push(selection);
+        }
+    }
+    Some((primary?, extra))
+}
+
+/// Фон выделений дополнительных курсоров; раскладка нарисована в `origin`
+/// и начинается с символа `view.start` документа.
+pub fn selection_shapes(
+    ui: &egui::Ui,
+    galley: &egui::Galley,
+    origin: Pos2,
+    view: &Range<usize>,
+    cursors: &[Selection],
+) -> Vec<Shape> {
+    let fill = ui.visuals().selection.bg_fill;
+    let mut shapes = Vec::new();
+    for cursor in cursors {
+        let range = cursor.range();
+        if range.is_empty() || range.end <= view.start || range.start >= view.end {
+            continue;
+        }
+        let start = CCursor::new(range.start.max(view.start) - view.start);
+        let end = CCursor::new(range.end.min(view.end) - view.start);
+        let (start_row, end_row) = (
+            galley.layout_from_cursor(start).row,
+            galley.layout_from_cursor(end).row,
+        );
+        for row in start_row..=end_row {
+            let rect = galley.rows[row].rect();
+            let left = if row == start_row {
+                galley.pos_from_cursor(start).left()
+            } else {
+                rect.left()
+            };
+            let right = if row == end_row {
+                galley.pos_from_cursor(end).left()
+            } else {
+                rect.right()
+            };
+            let rect = Rect::from_x_y_ranges(left..=right, rect.y_range());
+            shapes.push(Shape::rect_filled(
+                rect.translate(origin.to_vec2()),
+                0.0,
+                fill,
+            ));
+        }
+    }
+    shapes
+}
+
+/// Черта на месте каждого дополнительного курсора.
+pub fn paint_cursors(
+    ui: &egui::Ui,
+    galley: &egui::Galley,
+    origin: Pos2,
+    view: &Range<usize>,
+    cursors: &[Selection],
+) {
+    let stroke = ui.visuals().text_cursor.stroke;
+    for cursor in cursors
+        .iter()
+        .filter(|c| view.contains(&c.head) || view.end == c.head)
+    {
+        let rect = galley
+            .pos_from_cursor(CCursor::new(cursor.head - view.start))
+            .translate(origin.to_vec2());
+        ui.painter()
+            .line_segment([rect.center_top(), rect.center_bottom()], stroke);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(pos: usize) -> Selection {
+        Selection {
+            anchor: pos,
+            head: pos,
+        }
+    }
+
+    #[test]
+    fn applies_keys_at_every_cursor() {
+        let ctx = egui::Context::default();
+        let mut doc = Document::untitled_with_text(1, "один\nдва\n");
+        doc.set_cursors(cursor(4), vec![cursor(8)]);
+
+        apply(&ctx, &mut doc, Event::Paste("1\r\n2".to_string()));
+        assert_eq!(doc.text().to_string(), "один1\nдва2\n");
+
+        let key = |key| Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers: egui::Modifiers::SHIFT,
+        };
+        apply(&ctx, &mut doc, key(Key::Home));
+        apply(&ctx, &mut doc, Event::Text("> ".to_string()));
+        assert_eq!(doc.text().to_string(), "> \n> \n");
+
+        apply(&ctx, &mut doc, key(Key::Escape));
+        assert!(doc.extra_cursors().is_empty());
+    }
+
+    #[test]
+    fn ctrl_keys_and_typing_work_at_every_cursor() {
+        let ctx = egui::Context::default();
+        let mut doc = Document::untitled_with_text(1, "let кот = 1;\nlet пёс = 2;\n");
+        doc.set_cursors(cursor(0), vec![cursor(13)]);
+        let ctrl = |key| Event::Key {
+            key,
+            physical_key: None,
+            pressed: true,
+            repeat: false,
+            modifiers: egui::Modifiers::CTRL | egui::Modifiers::COMMAND,
+        };
+        assert!(is_ours(&ctrl(Key::Backspace)));
+
+        apply(&ctx, &mut doc, ctrl(Key::ArrowRight));
+        apply(&ctx, &mut doc, ctrl(Key::ArrowRight));
+        apply(&ctx, &mut doc, ctrl(Key::Backspace));
+        assert_eq!(doc.text().to_string(), "let  = 1;\nlet  = 2;\n");
+
+        for ch in ["з", "в", "е", "р", "ь"] {
+            apply(&ctx, &mut doc, Event::Text(ch.to_string()));
+        }
+        assert_eq!(doc.text().to_string(), "let зверь = 1;\nlet зверь = 2;\n");
+
+        // Правки подряд во всех курсорах отменяются одним шагом.
+        doc.undo();
+        assert_eq!(doc.text().to_string(), "let кот = 1;\nlet пёс = 2;\n");
+    }
+}
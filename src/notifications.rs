@@ -0,0 +1,249 @@
+//! Уведомления: всплывающие сообщения в углу окна и журнал всех сообщений
+//! за сессию. Ошибки ввода-вывода показываем здесь, а не в stderr, которого
+//! пользователь графического редактора не видит.
+
+use std::io;
+use std::time::{Duration, Instant, SystemTime};
+
+use eframe::egui::{self, Color32};
+
+use crate::actions::Action;
+
+/// Сколько висит всплывающее сообщение. Ошибки висят, пока их не закроют.
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+/// Сколько сообщений держим в журнале.
+const LOG_LIMIT: usize = 500;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    fn icon(self) -> &'static str {
+        match self {
+            Severity::Info => "ℹ",
+            Severity::Warning => "⚠",
+            Severity::Error => "⛔",
+        }
+    }
+
+    fn color(self, visuals: &egui::Visuals) -> Color32 {
+        match self {
+            Severity::Info => visuals.text_color(),
+            Severity::Warning => visuals.warn_fg_color,
+            Severity::Error => visuals.error_fg_color,
+        }
+    }
+}
+
+/// Что можно сделать прямо из уведомления: команда для документа.
+#[derive(Clone, Copy)]
+pub struct FollowUp {
+    pub label: &'static str,
+    pub action: Action,
+    pub doc_id: Option<usize>,
+}
+
+struct Notification {
+    severity: Severity,
+    message: String,
+    time: SystemTime,
+    shown_at: Instant,
+    follow_up: Option<FollowUp>,
+    /// Всплывающее сообщение закрыто; в журнале оно остаётся.
+    dismissed: bool,
+}
+
+#[derive(Default)]
+pub struct Notifications {
+    log: Vec<Notification>,
+    pub show_log: bool,
+}
+
+impl Notifications {
+    pub fn info(&mut self, message: impl Into<String>) {
+        self.push(Severity::Info, message.into(), None);
+    }
+
+    pub fn warning(&mut self, message: impl Into<String>) {
+        self.push(Severity::Warning, message.into(), None);
+    }
+
+    pub fn error(&mut self, message: impl Into<String>) {
+        self.push(Severity::Error, message.into(), None);
+    }
+
+    /// Ошибка записи файла. Если писать туда нельзя, предлагаем сохранить
+    /// документ в другое место.
+    pub fn write_error(&mut self, message: String, err: &io::Error, doc_id: usize) {
+        let follow_up = matches!(
+            err.kind(),
+            io::ErrorKind::PermissionDenied
+                | io::ErrorKind::ReadOnlyFilesystem
+                | io::ErrorKind::StorageFull
+                | io::ErrorKind::NotFound
+        )
+        .then_some(FollowUp {
+            label: "Сохранить в другое место...",
+            action: Action::SaveAs,
+            doc_id: Some(doc_id),
+        });
+        self.push(Severity::Error, format!("{message}: {err}"), follow_up);
+    }
+
+    fn push(&mut self, severity: Severity, message: String, follow_up: Option<FollowUp>) {
+        // Одна и та же ошибка каждую секунду (например, автосохранения)
+        // не должна заваливать экран: обновляем уже показанную.
+        if let Some(last) = self.log.last_mut()
+            && !last.dismissed
+            && last.severity == severity
+            && last.message == message
+        {
+            last.time = SystemTime::now();
+            last.shown_at = Instant::now();
+            return;
+        }
+
+        self.log.push(Notification {
+            severity,
+            message,
+            time: SystemTime::now(),
+            shown_at: Instant::now(),
+            follow_up,
+            dismissed: false,
+        });
+        if self.log.len() > LOG_LIMIT {
+            self.log.remove(0);
+        }
+    }
+
+    fn visible(notification: &Notification) -> bool {
+        !notification.dismissed
+            && (notification.severity == Severity::Error
+                || notification.shown_at.elapsed() < TOAST_DURATION)
+    }
+
+    /// Всплывающие сообщения в правом нижнем углу. Возвращает выбранное
+    /// пользователем действие.
+    pub fn show_toasts(&mut self, ctx: &egui::Context) -> Option<FollowUp> {
+        let mut chosen = None;
+        if !self.log.iter().any(Self::visible) {
+            return None;
+        }
+
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_BOTTOM, [-12.0, -36.0])
+            .order(egui::Order::Foreground)
+            .show(ctx, |ui| {
+                ui.set_max_width(360.0);
+                for notification in self.log.iter_mut().filter(|n| Self::visible(n)) {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| {
+                        ui.horizontal(|ui| {
+                            let color = notification.severity.color(ui.visuals());
+                            ui.colored_label(color, notification.severity.icon());
+                            ui.label(&notification.message);
+                            if ui.small_button("×").clicked() {
+                                notification.dismissed = true;
+                            }
+                        });
+                        if let Some(follow_up) = notification.follow_up
+                            && ui.button(follow_up.label).clicked()
+                        {
+                            notification.dismissed = true;
+                            chosen = Some(follow_up);
+                        }
+                    });
+                }
+            });
+
+        // Чтобы сообщения исчезали сами, даже если ничего не происходит.
+        ctx.request_repaint_after(Duration::from_millis(500));
+        chosen
+    }
+
+    /// Нижняя панель со всеми сообщениями сессии.
+    pub fn log_panel(&mut self, ctx: &egui::Context) -> Option<FollowUp> {
+        let mut chosen = None;
+        egui::TopBottomPanel::bottom("notification_log")
+            .resizable(true)
+            .default_height(160.0)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.strong("Журнал сообщений");
+                    if ui.small_button("Очистить").clicked() {
+                        self.log.clear();
+                    }
+                    if ui.small_button("×").clicked() {
+                        self.show_log = false;
+                    }
+                });
+                ui.separator();
+                egui::ScrollArea::vertical()
+                    .auto_shrink(false)
+                    .stick_to_bottom(true)
+                    .show(ui, |ui| {
+                        for notification in &mut self.log {
+                            ui.horizontal(|ui| {
+                                let color = notification.severity.color(ui.visuals());
+                                ui.weak(time_ago(notification.time));
+                                ui.colored_label(color, notification.severity.icon());
+                                ui.label(&notification.message);
+                                if let Some(follow_up) = notification.follow_up
+                                    && ui.small_button(follow_up.label).clicked()
+                                {
+                                    notification.dismissed = true;
+                                    chosen = Some(follow_up);
+                                }
+                            });
+                        }
+                    });
+            });
+        chosen
+    }
+
+    /// Есть ли непрочитанные ошибки — для значка в строке состояния.
+    pub fn unread_errors(&self) -> usize {
+        self.log
+            .iter()
+            .filter(|n| n.severity == Severity::Error && !n.dismissed)
+            .count()
+    }
+}
+
+/// "5 мин назад" — время для журнала сообщений и панели дерева отмены.
+pub fn time_ago(time: SystemTime) -> String {
+    let secs = time.elapsed().map(|d| d.as_secs()).unwrap_or(0);
+    match secs {
+        0..60 => format!("{secs} с назад"),
+        60..3600 => format!("{} мин назад", secs / 60),
+        3600..86400 => format!("{} ч назад", secs / 3600),
+        _ => format!("{} дн назад", secs / 86400),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn offers_save_as_when_file_is_not_writable() {
+        let mut notifications = Notifications::default();
+        let denied = io::Error::from(io::ErrorKind::PermissionDenied);
+        notifications.write_error("Не удалось сохранить".to_string(), &denied, 7);
+        notifications.write_error("Не удалось сохранить".to_string(), &denied, 7);
+
+        assert_eq!(notifications.unread_errors(), 1);
+        let follow_up = notifications.log[0].follow_up.expect("действие");
+        assert_eq!(
+            (follow_up.action, follow_up.doc_id),
+            (Action::SaveAs, Some(7))
+        );
+
+        let invalid = io::Error::from(io::ErrorKind::InvalidData);
+        notifications.write_error("Не удалось сохранить".to_string(), &invalid, 7);
+        assert!(notifications.log[1].follow_up.is_none());
+    }
+}
@@ -0,0 +1,224 @@
+//! Палитра команд (Ctrl+Shift+P): нечёткий поиск по всем командам редактора.
+//!
+//! Команды с аргументом вызываются как `Имя команды: аргумент`,
+//! например «Перейти к строке: 120» или «Размер шрифта: 18».
+
+use std::collections::HashMap;
+
+use eframe::egui;
+
+use crate::actions::Action;
+use crate::keymap::Keymap;
+
+/// Сколько строк результата показываем.
+const MAX_RESULTS: usize = 12;
+
+/// Что выбрали в палитре.
+pub struct Invocation {
+    pub action: Action,
+    pub argument: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Palette {
+    open: bool,
+    query: String,
+    selected: usize,
+    /// Ошибка последнего запуска (например, неверный аргумент).
+    error: Option<String>,
+    focus_query: bool,
+    /// Номер последнего использования каждой команды.
+    last_used: HashMap<Action, u64>,
+    uses: u64,
+}
+
+impl Palette {
+    /// Открывает палитру с уже введённым текстом.
+    pub fn open(&mut self, query: &str) {
+        self.open = true;
+        self.query = query.to_string();
+        self.selected = 0;
+        self.error = None;
+        self.focus_query = true;
+    }
+
+    pub fn close(&mut self) {
+        self.open = false;
+        self.error = None;
+    }
+
+    /// Команда отработала с ошибкой — палитра остаётся открытой с сообщением.
+    pub fn show_error(&mut self, error: String) {
+        self.open = true;
+        self.error = Some(error);
+        self.focus_query = true;
+    }
+
+    pub fn record_use(&mut self, action: Action) {
+        self.uses += 1;
+        self.last_used.insert(action, self.uses);
+    }
+
+    /// Команды, подходящие под запрос, лучшие первыми.
+    fn matches(&self, name_query: &str) -> Vec<Action> {
+        let mut scored: Vec<(i64, usize, Action)> = Action::ALL
+            .iter()
+            .enumerate()
+            .filter(|(_, action)| **action != Action::CommandPalette)
+            .filter_map(|(order, &action)| {
+                let score = fuzzy_score(name_query, action.name())
+                    .max(fuzzy_score(name_query, action.id()))?;
+                // Недавно использованные команды поднимаем выше.
+                let recency = self
+                    .last_used
+                    .get(&action)
+                    .map_or(0, |&used| 100 / (1 + (self.uses - used) as i64));
+                Some((score + recency, order, action))
+            })
+            .collect();
+        scored.sort_by_key(|&(score, order, _)| (std::cmp::Reverse(score), order));
+        scored.into_iter().map(|(_, _, action)| action).collect()
+    }
+
+    pub fn show(&mut self, ctx: &egui::Context, keymap: &Keymap) -> Option<Invocation> {
+        if !self.open {
+            return None;
+        }
+
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        if escape {
+            self.close();
+            return None;
+        }
+
+        let (name_query, argument) = match self.query.split_once(':') {
+            Some((name, arg)) => (name.trim().to_string(), Some(arg.trim().to_string())),
+            None => (self.query.trim().to_string(), None),
+        };
+        let results = self.matches(&name_query);
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if down {
+            self.selected += 1;
+        }
+        self.selected = self
+            .selected
+            .min(results.len().min(MAX_RESULTS).saturating_sub(1));
+
+        let mut chosen = enter.then(|| results.get(self.selected).copied()).flatten();
+
+        egui::Window::new("Палитра команд")
+            .title_bar(false)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .fixed_size([420.0, 0.0])
+            .show(ctx, |ui| {
+                let edit = ui.add(
+                    egui::TextEdit::singleline(&mut self.query)
+                        .hint_text("Команда (аргумент — после двоеточия)")
+                        .desired_width(f32::INFINITY),
+                );
+                if std::mem::take(&mut self.focus_query) {
+                    edit.request_focus();
+                }
+                if edit.changed() {
+                    self.selected = 0;
+                    self.error = None;
+                }
+
+                if let Some(error) = &self.error {
+                    ui.colored_label(ui.visuals().error_fg_color, error);
+                }
+                ui.separator();
+
+                for (i, &action) in results.iter().take(MAX_RESULTS).enumerate() {
+                    ui.horizontal(|ui| {
+                        let mut label = action.name().to_string();
+                        if let Some(hint) = action.argument_hint() {
+                            label.push_str(&format!(": <{hint}>"));
+                        }
+                        if ui.selectable_label(i == self.selected, label).clicked() {
+                            chosen = Some(action);
+                        }
+                        if let Some(shortcut) = keymap.shortcut(action) {
+                            ui.with_layout(
+                                egui::Layout::right_to_left(egui::Align::Center),
+                                |ui| {
+                                    ui.weak(ctx.format_shortcut(&shortcut));
+                                },
+                            );
+                        }
+                    });
+                }
+                if results.is_empty() {
+                    ui.weak("Нет подходящих команд");
+                }
+            });
+
+        let action = chosen?;
+        let argument = argument.filter(|arg| !arg.is_empty());
+        if action.argument_hint().is_some() && argument.is_none() {
+            // Команде нужен аргумент — подставляем имя и ждём ввода.
+            self.open(&format!("{}: ", action.name()));
+            return None;
+        }
+        self.close();
+        Some(Invocation { action, argument })
+    }
+}
+
+/// Нечёткое совпадение: все символы запроса встречаются в `candidate` по
+/// порядку. Больше очков за идущие подряд символы и начала слов.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+
+    let candidate: Vec<char> = candidate.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut previous: Option<usize> = None;
+
+    for q in query.chars().flat_map(char::to_lowercase) {
+        if q.is_whitespace() {
+            continue;
+        }
+        let found = pos + candidate[pos..].iter().position(|&c| c == q)?;
+        let word_start = found == 0 || !candidate[found - 1].is_alphanumeric();
+        score += 1;
+        if word_start {
+            score += 8;
+        }
+        if previous.is_some_and(|p| p + 1 == found) {
+            score += 5;
+        } else if let Some(p) = previous {
+            score -= (found - p).min(10) as i64;
+        }
+        previous = Some(found);
+        pos = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_prefers_word_starts() {
+        assert!(fuzzy_score("пкс", "Перейти к строке").is_some());
+        assert!(fuzzy_score("xyz", "Перейти к строке").is_none());
+        let starts = fuzzy_score("сох", "Сохранить").unwrap();
+        let scattered = fuzzy_score("сох", "Размер шрифта: высокое окно х").unwrap_or(i64::MIN);
+        assert!(starts > scattered);
+    }
+}
@@ -0,0 +1,294 @@
+//! Поиск и замена: обычная подстрока или регулярное выражение, с учётом
+//! регистра и поиском слова целиком.
+//!
+//! Обычный поиск тоже идёт через `regex` — экранированный образец. Так у
+//! обоих режимов одни и те же параметры и одна реализация замены.
+
+use std::ops::Range;
+
+use regex::{Captures, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+/// Сколько замен показываем в предпросмотре.
+pub const PREVIEW_LIMIT: usize = 1000;
+/// Сколько совпадений подсвечиваем и обходим по F3.
+pub const MATCH_LIMIT: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub ignore_case: bool,
+    pub whole_word: bool,
+}
+
+/// Где искать и заменять.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SearchScope {
+    #[default]
+    Document,
+    Selection,
+    AllDocuments,
+}
+
+impl SearchScope {
+    pub const ALL: [SearchScope; 3] = [
+        SearchScope::Document,
+        SearchScope::Selection,
+        SearchScope::AllDocuments,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            SearchScope::Document => "В документе",
+            SearchScope::Selection => "В выделении",
+            SearchScope::AllDocuments => "Во всех вкладках",
+        }
+    }
+}
+
+/// Одна замена для предпросмотра.
+pub struct Change {
+    /// Номер строки, с 1.
+    pub line: usize,
+    pub found: String,
+    pub replaced: String,
+}
+
+/// Строка с найденным для списка результатов: без отступа и не длиннее
+/// пары сотен символов вокруг совпадения. Возвращает строку и байты
+/// совпадения в ней.
+pub fn preview_line(line: &str, found: Range<usize>) -> (String, Range<usize>) {
+    const BEFORE: usize = 40;
+    const TOTAL: usize = 160;
+    let line = line.trim_end_matches(['\n', '\r']);
+    let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+    let first = indent
+        .min(found.start)
+        .max(found.start.saturating_sub(BEFORE));
+    let chars: Vec<char> = line.chars().skip(first).take(TOTAL).collect();
+    let byte = |ch: usize| {
+        chars[..ch.min(chars.len())]
+            .iter()
+            .map(|c| c.len_utf8())
+            .sum::<usize>()
+    };
+    let found = byte(found.start - first)..byte(found.end - first);
+    (chars.into_iter().collect(), found)
+}
+
+/// Собранный образец. Копия дешёвая: `Regex` внутри общий.
+#[derive(Clone)]
+pub struct Query {
+    regex: Regex,
+    /// Подставлять ли в замену группы `$1`, `${name}`. В обычном режиме `$`
+    /// в замене — просто символ.
+    expand: bool,
+}
+
+impl Query {
+    pub fn new(pattern: &str, options: SearchOptions) -> Result<Self, String> {
+        if pattern.is_empty() {
+            return Err("Введите, что искать".to_string());
+        }
+        let mut source = if options.regex {
+            pattern.to_string()
+        } else {
+            regex::escape(pattern)
+        };
+        if options.whole_word {
+            // У знака препинания `\b` требует букву рядом, и «foo(» в «foo(x)»
+            // не нашлось бы. Поэтому у простого образца границу ставим только
+            // с той стороны, где он начинается или кончается буквой. Края
+            // регулярного выражения заранее не известны — там просто не
+            // должно быть букв вплотную.
+            let word = |c: Option<char>| {
+                options.regex || c.is_some_and(|c| c.is_alphanumeric() || c == '_')
+            };
+            let start = if word(pattern.chars().next()) {
+                r"\b{start-half}"
+            } else {
+                ""
+            };
+            let end = if word(pattern.chars().next_back()) {
+                r"\b{end-half}"
+            } else {
+                ""
+            };
+            source = format!("{start}(?:{source}){end}");
+        }
+        let regex = RegexBuilder::new(&source)
+            .case_insensitive(options.ignore_case)
+            .multi_line(true)
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(Self {
+            regex,
+            expand: options.regex,
+        })
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.regex.find_iter(text).count()
+    }
+
+    /// Совпадения в позициях символов, не больше [`MATCH_LIMIT`]. Пустые
+    /// совпадения (`^`, `a*`) не подсвечиваются и пропускаются.
+    pub fn find_chars(&self, text: &str) -> Vec<Range<usize>> {
+        let mut chars = 0;
+        let mut pos = 0;
+        self.regex
+            .find_iter(text)
+            .filter(|m| !m.is_empty())
+            .take(MATCH_LIMIT)
+            .map(|m| {
+                chars += text[pos..m.start()].chars().count();
+                let start = chars;
+                chars += m.as_str().chars().count();
+                pos = m.end();
+                start..chars
+            })
+            .collect()
+    }
+
+    /// Замена для совпадения в байтах `range`, если оно там по-прежнему есть.
+    pub fn replacement_for(
+        &self,
+        text: &str,
+        range: Range<usize>,
+        template: &str,
+    ) -> Option<String> {
+        let caps = self.regex.captures_at(text, range.start)?;
+        (caps.get(0)?.range() == range).then(|| self.replacement(&caps, template))
+    }
+
+    fn replacement(&self, caps: &Captures, template: &str) -> String {
+        if !self.expand {
+            return template.to_string();
+        }
+        let mut out = String::new();
+        caps.expand(template, &mut out);
+        out
+    }
+
+    /// Заменяет все совпадения. Возвращает новый текст и число замен.
+    pub fn replace_all(&self, text: &str, template: &str) -> (String, usize) {
+        let mut count = 0;
+        let replaced = self.regex.replace_all(text, |caps: &Captures| {
+            count += 1;
+            self.replacement(caps, template)
+        });
+        (replaced.into_owned(), count)
+    }
+
+    /// Первые [`PREVIEW_LIMIT`] замен, которые сделает `replace_all`.
+    pub fn preview(&self, text: &str, template: &str) -> Vec<Change> {
+        let mut line = 1;
+        let mut pos = 0;
+        self.regex
+            .captures_iter(text)
+            .take(PREVIEW_LIMIT)
+            .map(|caps| {
+                let found = caps.get(0).expect("совпадение целиком");
+                line += text[pos..found.start()].matches('\n').count();
+                pos = found.start();
+                Change {
+                    line,
+                    found: found.as_str().to_string(),
+                    replaced: self.replacement(&caps, template),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(pattern: &str, options: SearchOptions) -> Query {
+        Query::new(pattern, options).unwrap()
+    }
+
+    #[test]
+    fn substitutes_capture_groups() {
+        let regex = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let dates = query(r"(?<d>\d\d)\.(\d\d)\.(\d{4})", regex);
+        let (text, count) = dates.replace_all("с 01.02.2024 по 03.04.2025", "$3-$2-${d}");
+        assert_eq!((text.as_str(), count), ("с 2024-02-01 по 2025-04-03", 2));
+
+        // В обычном режиме `$1` — просто текст, а точка — просто точка.
+        let literal = query("1.", SearchOptions::default());
+        assert_eq!(literal.replace_all("1. 12", "$1").0, "$1 12");
+    }
+
+    #[test]
+    fn respects_case_and_whole_words() {
+        let options = SearchOptions {
+            ignore_case: true,
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        let text = "Кот, котёл и КОТ";
+        assert_eq!(query("кот", options).count(text), 2);
+        assert_eq!(query("кот", SearchOptions::default()).count(text), 1);
+
+        let changes = query("кот", options).preview("а\nкот\nб кот", "пёс");
+        let lines: Vec<usize> = changes.iter().map(|change| change.line).collect();
+        assert_eq!(lines, [2, 3]);
+        assert_eq!(changes[0].replaced, "пёс");
+
+        // Край образца — знак препинания: с этой стороны граница не нужна.
+        let whole = SearchOptions {
+            whole_word: true,
+            ..SearchOptions::default()
+        };
+        assert_eq!(
+            query("foo(", whole).find_chars("foo(x) barfoo(y) foo(z)"),
+            [0..4, 17..21]
+        );
+        assert_eq!(query("-x", whole).find_chars("a-x -xy -x"), [1..3, 8..10]);
+        assert_eq!(query("a.", whole).find_chars("a. ba. a.b"), [0..2, 7..9]);
+    }
+
+    #[test]
+    fn finds_matches_in_chars() {
+        let regex = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        let words = query(r"\w+", regex);
+        let text = "ёж и уж";
+        assert_eq!(words.find_chars(text), [0..2, 3..4, 5..7]);
+        assert_eq!(words.replacement_for(text, 4..5, "x"), None);
+        assert_eq!(
+            words.replacement_for(text, 5..7, "[$0]").as_deref(),
+            Some("[и]")
+        );
+    }
+
+    #[test]
+    fn trims_preview_lines() {
+        let (line, found) = preview_line("    let кот = 1;\n", 8..11);
+        assert_eq!(line, "let кот = 1;");
+        assert_eq!(&line[found], "кот");
+
+        let long = format!("{}кот", "а".repeat(100));
+        let (line, found) = preview_line(&long, 100..103);
+        assert_eq!(line.chars().count(), 43);
+        assert_eq!(&line[found], "кот");
+    }
+
+    #[test]
+    fn reports_invalid_patterns() {
+        let regex = SearchOptions {
+            regex: true,
+            ..SearchOptions::default()
+        };
+        assert!(Query::new("(незакрытая", regex).is_err());
+        assert!(Query::new("(незакрытая", SearchOptions::default()).is_ok());
+        assert!(Query::new("", SearchOptions::default()).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Сессия редактора: открытые вкладки и настройки, которые переживают
+//! перезапуск. Хранится через persistence-хранилище eframe.
+
+use std::path::PathBuf;
+
+use eframe::egui::{Color32, Vec2};
+use serde::{Deserialize, Serialize};
+
+use crate::document::Selection;
+use crate::encoding::TextEncoding;
+use crate::file_io::BackupMode;
+use crate::gutter::LineNumbers;
+use crate::search::{SearchOptions, SearchScope};
+use crate::syntax::Language;
+
+/// Вкладка прошлой сессии.
+#[derive(Serialize, Deserialize)]
+pub struct Tab {
+    /// `None` — безымянный документ, его текст лежит в `text`.
+    pub path: Option<PathBuf>,
+    pub text: Option<String>,
+    #[serde(default)]
+    pub encoding: Option<TextEncoding>,
+    /// Подсветка, выбранная вручную; `None` — по расширению файла.
+    #[serde(default)]
+    pub language: Option<Language>,
+    pub selection: Selection,
+    pub scroll: Vec2,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Session {
+    pub tabs: Vec<Tab>,
+    pub active_tab: usize,
+    pub font_size: f32,
+    pub text_color: Color32,
+    pub autosave_secs: u64,
+    pub undo_budget_mb: u32,
+    #[serde(default)]
+    pub backup_mode: BackupMode,
+    #[serde(default)]
+    pub line_numbers: LineNumbers,
+    pub find_text: String,
+    pub replace_text: String,
+    #[serde(default)]
+    pub search_options: SearchOptions,
+    #[serde(default)]
+    pub search_scope: SearchScope,
+}
+
+pub fn load(storage: Option<&dyn eframe::Storage>) -> Option<Session> {
+    eframe::get_value(storage?, eframe::APP_KEY)
+}
+
+pub fn save(storage: &mut dyn eframe::Storage, session: &Session) {
+    eframe::set_value(storage, eframe::APP_KEY, session);
+}
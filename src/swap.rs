@@ -0,0 +1,220 @@
+//! Файлы подкачки: текст каждого изменённого документа периодически пишем
+//! в каталог данных редактора. Если редактор упал, при следующем запуске
+//! эти файлы предлагается восстановить.
+//!
+//! Каждый запуск держит блокировку ОС на своём файле `<запуск>.lock`.
+//! Пока редактор работает, блокировку не взять; после его падения ОС её
+//! снимает. Так не мешают ни повторно выданный pid, ни второе окно.
+
+use std::fs::{self, File, TryLockError};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::document::Document;
+use crate::file_io::{self, BackupMode};
+
+const FORMAT_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct SwapFile {
+    version: u32,
+    /// Запуск редактора, который писал файл. У файлов старого вида его нет.
+    #[serde(default)]
+    instance: String,
+    pub path: Option<PathBuf>,
+    pub title: String,
+    pub saved_at: SystemTime,
+    pub text: String,
+}
+
+/// Файл подкачки, оставшийся от прошлого запуска.
+pub struct Leftover {
+    pub file: PathBuf,
+    pub swap: SwapFile,
+}
+
+fn swap_dir() -> Option<PathBuf> {
+    eframe::storage_dir(crate::APP_NAME).map(|dir| dir.join("swap"))
+}
+
+/// Этот запуск: pid и время старта. Блокировку держим до выхода.
+struct Instance {
+    id: String,
+    _lock: Option<File>,
+}
+
+fn instance() -> &'static str {
+    static INSTANCE: OnceLock<Instance> = OnceLock::new();
+    &INSTANCE
+        .get_or_init(|| {
+            let started = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_nanos();
+            let id = format!("{}-{started}", process::id());
+            let lock = swap_dir().and_then(|dir| hold_lock(&dir, &id).ok());
+            Instance { id, _lock: lock }
+        })
+        .id
+}
+
+fn lock_file(dir: &Path, instance: &str) -> PathBuf {
+    dir.join(format!("{instance}.lock"))
+}
+
+/// Создаёт файл блокировки запуска и берёт на нём блокировку ОС.
+fn hold_lock(dir: &Path, instance: &str) -> io::Result<File> {
+    fs::create_dir_all(dir)?;
+    let file = File::create(lock_file(dir, instance))?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Работает ли ещё запуск `instance`: его блокировку держит ОС.
+fn instance_alive(dir: &Path, instance: &str) -> bool {
+    match File::open(lock_file(dir, instance)) {
+        Ok(file) => matches!(file.try_lock(), Err(TryLockError::WouldBlock)),
+        Err(_) => false,
+    }
+}
+
+/// Номера документов у каждого запуска свои, поэтому в имени есть запуск.
+fn swap_file(doc_id: usize) -> Option<PathBuf> {
+    swap_dir().map(|dir| dir.join(format!("{}-{doc_id}.json", instance())))
+}
+
+/// Пишет текст документа в его файл подкачки.
+pub fn write(doc: &Document) -> io::Result<()> {
+    let Some(file) = swap_file(doc.id) else {
+        return Ok(());
+    };
+    let swap = SwapFile {
+        version: FORMAT_VERSION,
+        instance: instance().to_string(),
+        path: doc.path.clone(),
+        title: doc.title.clone(),
+        saved_at: SystemTime::now(),
+        text: doc.text().to_string(),
+    };
+    if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    file_io::write_atomic(&file, BackupMode::None, |out| {
+        serde_json::to_writer(out, &swap).map_err(io::Error::from)
+    })
+}
+
+/// Удаляет файл подкачки документа — после сохранения или закрытия.
+pub fn remove(doc_id: usize) -> io::Result<()> {
+    match swap_file(doc_id).map(fs::remove_file) {
+        Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+pub fn discard(file: &Path) -> io::Result<()> {
+    fs::remove_file(file)
+}
+
+/// Файлы подкачки запусков, которые уже не работают, старые первыми.
+/// Нечитаемые файлы удаляем сразу — восстановить из них всё равно нечего.
+pub fn leftovers() -> Vec<Leftover> {
+    match swap_dir() {
+        Some(dir) => leftovers_in(&dir, instance()),
+        None => Vec::new(),
+    }
+}
+
+fn leftovers_in(dir: &Path, own: &str) -> Vec<Leftover> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let files: Vec<PathBuf> = entries.flatten().map(|entry| entry.path()).collect();
+    let has_ext = |file: &Path, ext: &str| file.extension().is_some_and(|e| e == ext);
+
+    let mut leftovers = Vec::new();
+    for file in files.iter().filter(|file| has_ext(file, "json")) {
+        let parsed = fs::read(file)
+            .ok()
+            .and_then(|bytes| serde_json::from_slice::<SwapFile>(&bytes).ok())
+            .filter(|swap| swap.version == FORMAT_VERSION);
+        match parsed {
+            Some(swap) if swap.instance == own || instance_alive(dir, &swap.instance) => {}
+            Some(swap) => leftovers.push(Leftover {
+                file: file.clone(),
+                swap,
+            }),
+            None => {
+                let _ = fs::remove_file(file);
+            }
+        }
+    }
+
+    // Блокировки упавших запусков, от которых не осталось подкачки.
+    for lock in files.iter().filter(|file| has_ext(file, "lock")) {
+        let Some(instance) = lock.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+        let orphaned = !leftovers.iter().any(|l| l.swap.instance == instance);
+        if instance != own && orphaned && !instance_alive(dir, instance) {
+            let _ = fs::remove_file(lock);
+        }
+    }
+
+    leftovers.sort_by_key(|leftover| leftover.swap.saved_at);
+    leftovers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_swap(dir: &Path, instance: &str, text: &str) -> PathBuf {
+        let file = dir.join(format!("{instance}-1.json"));
+        let swap = SwapFile {
+            version: FORMAT_VERSION,
+            instance: instance.to_string(),
+            path: None,
+            title: "Безымянный 1".to_string(),
+            saved_at: SystemTime::now(),
+            text: text.to_string(),
+        };
+        fs::write(&file, serde_json::to_vec(&swap).unwrap()).unwrap();
+        file
+    }
+
+    #[test]
+    fn leftovers_are_swaps_of_exited_instances() {
+        let dir = std::env::temp_dir().join(format!("rte-swap-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+
+        // Упавший запуск: файл блокировки есть, но никто её не держит.
+        File::create(lock_file(&dir, "1-crashed")).unwrap();
+        let crashed = write_swap(&dir, "1-crashed", "спасти");
+        // Работающий запуск (второе окно) — его блокировка занята.
+        let _running = hold_lock(&dir, "2-running").unwrap();
+        write_swap(&dir, "2-running", "не трогать");
+        write_swap(&dir, "3-own", "свой");
+        fs::write(dir.join("4-broken-1.json"), "{не json").unwrap();
+
+        let leftovers = leftovers_in(&dir, "3-own");
+        assert_eq!(leftovers.len(), 1);
+        assert_eq!(leftovers[0].file, crashed);
+        assert_eq!(leftovers[0].swap.text, "спасти");
+        assert!(!dir.join("4-broken-1.json").exists());
+
+        // Подкачку восстановили — блокировка упавшего запуска больше не нужна.
+        discard(&crashed).unwrap();
+        assert!(leftovers_in(&dir, "3-own").is_empty());
+        assert!(!lock_file(&dir, "1-crashed").exists());
+        assert!(lock_file(&dir, "2-running").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+//! Символы документа для перехода (Ctrl+Shift+O): функции, типы,
+//! заголовки, секции конфигов. Ищутся простыми шаблонами по строкам —
+//! без полного разбора, зато для любого размера файла и любого языка.
+
+use eframe::egui;
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+
+use crate::palette::fuzzy_score;
+use crate::syntax::Language;
+
+/// Сколько символов показываем в списке.
+const MAX_RESULTS: usize = 200;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolKind {
+    Function,
+    Type,
+    Module,
+    Impl,
+    Constant,
+    Heading,
+    Key,
+}
+
+impl SymbolKind {
+    fn label(self) -> &'static str {
+        match self {
+            SymbolKind::Function => "fn",
+            SymbolKind::Type => "type",
+            SymbolKind::Module => "mod",
+            SymbolKind::Impl => "impl",
+            SymbolKind::Constant => "const",
+            SymbolKind::Heading => "#",
+            SymbolKind::Key => "key",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Symbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    /// Строка и столбец имени, с нуля, в символах.
+    pub line: usize,
+    pub column: usize,
+}
+
+/// Шаблоны языка: первая непустая группа — имя символа.
+fn patterns(language: Language) -> Vec<(String, SymbolKind)> {
+    // Rust: видимость перед объявлением.
+    let item = |rest: &str| format!(r"^[ \t]*(?:pub(?:\([^)]*\))?[ \t]+)?{rest}");
+    match language {
+        Language::Rust => vec![
+            (
+                item(r#"(?:(?:const|async|unsafe)[ \t]+)*(?:extern[ \t]+"[^"]*"[ \t]+)?fn[ \t]+(\w+)"#),
+                SymbolKind::Function,
+            ),
+            (item(r"(?:unsafe[ \t]+)?(?:struct|enum|union|trait|type)[ \t]+(\w+)"), SymbolKind::Type),
+            (item(r"mod[ \t]+(\w+)"), SymbolKind::Module),
+            (item(r"(?:const|static)[ \t]+(?:mut[ \t]+)?([A-Z_][A-Z0-9_]*)[ \t]*:"), SymbolKind::Constant),
+            (r"^[ \t]*(?:unsafe[ \t]+)?(impl\b[^{;]*?)[ \t]*(?:\{|where|$)".to_string(), SymbolKind::Impl),
+            (r"^[ \t]*macro_rules![ \t]+(\w+)".to_string(), SymbolKind::Function),
+        ],
+        Language::Python => vec![
+            (r"^[ \t]*(?:async[ \t]+)?def[ \t]+(\w+)".to_string(), SymbolKind::Function),
+            (r"^[ \t]*class[ \t]+(\w+)".to_string(), SymbolKind::Type),
+        ],
+        Language::Toml => vec![(r"^[ \t]*\[\[?[ \t]*([^\]]+?)[ \t]*\]".to_string(), SymbolKind::Heading)],
+        Language::Yaml => vec![(r"^([^\s#'\x22-][^:#]*?)[ \t]*:(?:[ \t]|$)".to_string(), SymbolKind::Key)],
+        // Ключи верхнего уровня в отформатированном JSON.
+        Language::Json => vec![(r#"^(?: {2}|\t)"([^"\\]+)"[ \t]*:"#.to_string(), SymbolKind::Key)],
+        Language::Sql => vec![
+            (
+                r#"^[ \t]*create[ \t]+(?:or[ \t]+replace[ \t]+)?(?:temp(?:orary)?[ \t]+)?(?:table|view|index|type)[ \t]+(?:if[ \t]+not[ \t]+exists[ \t]+)?([\w."]+)"#
+                    .to_string(),
+                SymbolKind::Type,
+            ),
+            (
+                r#"^[ \t]*create[ \t]+(?:or[ \t]+replace[ \t]+)?(?:function|procedure|trigger)[ \t]+([\w."]+)"#
+                    .to_string(),
+                SymbolKind::Function,
+            ),
+        ],
+        Language::Shell => vec![
+            (r"^[ \t]*function[ \t]+([\w-]+)".to_string(), SymbolKind::Function),
+            (r"^[ \t]*([\w-]+)[ \t]*\(\)".to_string(), SymbolKind::Function),
+        ],
+        Language::Markdown | Language::PlainText => Vec::new(),
+    }
+}
+
+/// Символы документа в порядке строк.
+pub fn symbols(language: Language, text: &Rope) -> Vec<Symbol> {
+    if language == Language::Markdown {
+        return headings(text);
+    }
+    let source = text.to_string();
+    let mut found = Vec::new();
+    for (pattern, kind) in patterns(language) {
+        let regex: Regex = RegexBuilder::new(&pattern)
+            .multi_line(true)
+            .case_insensitive(language == Language::Sql)
+            .build()
+            .expect("шаблон символов");
+        for caps in regex.captures_iter(&source) {
+            let Some(name) = caps.iter().skip(1).flatten().next() else {
+                continue;
+            };
+            let start = text.byte_to_char(name.start());
+            let line = text.char_to_line(start);
+            found.push(Symbol {
+                name: name
+                    .as_str()
+                    .split_whitespace()
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                kind,
+                line,
+                column: start - text.line_to_char(line),
+            });
+        }
+    }
+    found.sort_by_key(|symbol| (symbol.line, symbol.column));
+    found
+}
+
+/// Заголовки Markdown, кроме строк внутри блоков кода.
+fn headings(text: &Rope) -> Vec<Symbol> {
+    let mut found = Vec::new();
+    let mut in_code = false;
+    for (n, line) in text.lines().enumerate() {
+        let line = line.to_string();
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_code = !in_code;
+            continue;
+        }
+        let level = line.chars().take_while(|&c| c == '#').count();
+        if in_code || !(1..=6).contains(&level) || !line[level..].starts_with(' ') {
+            continue;
+        }
+        let title = line[level..].trim().trim_end_matches('#').trim_end();
+        found.push(Symbol {
+            name: format!("{}{title}", "  ".repeat(level - 1)),
+            kind: SymbolKind::Heading,
+            line: n,
+            column: level + 1,
+        });
+    }
+    found
+}
+
+/// Окно выбора символа: нечёткий поиск по именам.
+#[derive(Default)]
+pub struct SymbolPicker {
+    open: bool,
+    query: String,
+    selected: usize,
+    symbols: Vec<Symbol>,
+    focus_query: bool,
+}
+
+impl SymbolPicker {
+    pub fn open(&mut self, symbols: Vec<Symbol>) {
+        *self = Self {
+            open: true,
+            symbols,
+            focus_query: true,
+            ..Self::default()
+        };
+    }
+
+    /// Номера подходящих символов, лучшие первыми.
+    fn matches(&self) -> Vec<usize> {
+        let query = self.query.trim();
+        let mut scored: Vec<(i64, usize)> = self
+            .symbols
+            .iter()
+            .enumerate()
+            .filter_map(|(i, symbol)| Some((fuzzy_score(query, symbol.name.trim())?, i)))
+            .collect();
+        // Сортировка устойчивая: при равных очках — порядок в файле.
+        scored.sort_by_key(|&(score, _)| std::cmp::Reverse(score));
+        scored.into_iter().map(|(_, i)| i).collect()
+    }
+
+    /// Показывает окно. Возвращает выбранный символ.
+    pub fn show(&mut self, ctx: &egui::Context) -> Option<Symbol> {
+        if !self.open {
+            return None;
+        }
+
+        let (up, down, enter, escape) = ctx.input_mut(|i| {
+            (
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowUp),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::ArrowDown),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Enter),
+                i.consume_key(egui::Modifiers::NONE, egui::Key::Escape),
+            )
+        });
+        if escape {
+            self.open = false;
+            return None;
+        }
+
+        let results = self.matches();
+        let shown = results.len().min(MAX_RESULTS);
+        if up {
+            self.selected = self.selected.saturating_sub(1);
+        }
+        if down {
+            self.selected += 1;
+        }
+        self.selected = self.selected.min(shown.saturating_sub(1));
+        let mut chosen = enter.then(|| results.get(self.selected).copied()).flatten();
+
+        let mut query = self.query.clone();
+        egui::Window::new("Перейти к символу")
+            .title_bar(false)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(egui::Align2::CENTER_TOP, [0.0, 40.0])
+            .fixed_size([420.0, 0.0])
+            .show(ctx, |ui| {
+                let edit = ui.add(
+                    egui::TextEdit::singleline(&mut query)
+                        .hint_text("Имя функции, типа, заголовка")
+                        .desired_width(f32::INFINITY),
+                );
+                if std::mem::take(&mut self.focus_query) {
+                    edit.request_focus();
+                }
+                ui.separator();
+
+                egui::ScrollArea::vertical()
+                    .max_height(320.0)
+                    .show(ui, |ui| {
+                        for (i, &index) in results.iter().take(MAX_RESULTS).enumerate() {
+                            let symbol = &self.symbols[index];
+                            ui.horizontal(|ui| {
+                                ui.weak(format!("{:>5}", symbol.kind.label()));
+                                let label = ui.selectable_label(i == self.selected, &symbol.name);
+                                if i == self.selected && (up || down) {
+                                    label.scroll_to_me(None);
+                                }
+                                if label.clicked() {
+                                    chosen = Some(index);
+                                }
+                                ui.with_layout(
+                                    egui::Layout::right_to_left(egui::Align::Center),
+                                    |ui| {
+                                        ui.weak(format!("{}", symbol.line + 1));
+                                    },
+                                );
+                            });
+                        }
+                    });
+                if results.is_empty() {
+                    ui.weak(if self.symbols.is_empty() {
+                        "В документе нет символов"
+                    } else {
+                        "Ничего не найдено"
+                    });
+                }
+            });
+
+        let chosen = chosen.map(|index| self.symbols[index].clone());
+        if query != self.query {
+            self.query = query;
+            self.selected = 0;
+        }
+        if chosen.is_some() {
+            self.open = false;
+        }
+        chosen
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(language: Language, text: &str) -> Vec<(String, SymbolKind, usize)> {
+        symbols(language, &Rope::from_str(text))
+            .into_iter()
+            .map(|symbol| (symbol.name, symbol.kind, symbol.line))
+            .collect()
+    }
+
+    #[test]
+    fn finds_symbols_by_language() {
+        let rust = "pub struct Ёж;\nimpl<T> Show for Ёж {\n    pub(crate) async fn show(&self) {}\n}\nconst MAX: usize = 1;\n";
+        assert_eq!(
+            names(Language::Rust, rust),
+            [
+                ("Ёж".to_string(), SymbolKind::Type, 0),
+                ("impl<T> Show for Ёж".to_string(), SymbolKind::Impl, 1),
+                ("show".to_string(), SymbolKind::Function, 2),
+                ("MAX".to_string(), SymbolKind::Constant, 4),
+            ]
+        );
+
+        let markdown = "# Начало\n```sh\n# не заголовок\n```\n## Подробнее ##\n";
+        let headings: Vec<String> = names(Language::Markdown, markdown)
+            .into_iter()
+            .map(|(name, _, _)| name)
+            .collect();
+        assert_eq!(headings, ["Начало", "  Подробнее"]);
+
+        let python = "class A:\n    def b(self): pass\n";
+        assert_eq!(names(Language::Python, python).len(), 2);
+        assert_eq!(
+            names(Language::Sql, "CREATE TABLE users (id int);")[0].0,
+            "users"
+        );
+        assert!(names(Language::PlainText, "fn main() {}").is_empty());
+    }
+}
@@ -0,0 +1,1181 @@
+//! Подсветка синтаксиса. Лексер простой, построчный: на границе строк
+//! помнит только, внутри чего строка начинается (комментария, строки,
+//! блока кода). Эти состояния кэшируются по строкам документа, поэтому
+//! после правки пересчитываются строки от места правки до экрана, а не весь
+//! файл. Раскраска строк тоже кэшируется (см. [`SpanCache`]).
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::Path;
+
+use eframe::egui::Color32;
+use ropey::Rope;
+use serde::{Deserialize, Serialize};
+
+use crate::hash::fnv64;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Language {
+    #[default]
+    PlainText,
+    Rust,
+    Python,
+    Json,
+    Toml,
+    Yaml,
+    Markdown,
+    Sql,
+    Shell,
+}
+
+impl Language {
+    pub const ALL: [Language; 9] = [
+        Language::PlainText,
+        Language::Rust,
+        Language::Python,
+        Language::Json,
+        Language::Toml,
+        Language::Yaml,
+        Language::Markdown,
+        Language::Sql,
+        Language::Shell,
+    ];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Language::PlainText => "Обычный текст",
+            Language::Rust => "Rust",
+            Language::Python => "Python",
+            Language::Json => "JSON",
+            Language::Toml => "TOML",
+            Language::Yaml => "YAML",
+            Language::Markdown => "Markdown",
+            Language::Sql => "SQL",
+            Language::Shell => "Shell",
+        }
+    }
+
+    /// Язык по расширению, а для известных файлов без расширения — по имени.
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        match extension.as_str() {
+            "rs" => Language::Rust,
+            "py" | "pyw" | "pyi" => Language::Python,
+            "json" => Language::Json,
+            "toml" => Language::Toml,
+            "yaml" | "yml" => Language::Yaml,
+            "md" | "markdown" => Language::Markdown,
+            "sql" => Language::Sql,
+            "sh" | "bash" | "zsh" => Language::Shell,
+            _ => match path.file_name().and_then(|name| name.to_str()) {
+                Some("Cargo.lock") => Language::Toml,
+                Some(".bashrc" | ".bash_profile" | ".profile" | ".zshrc") => Language::Shell,
+                _ => Language::PlainText,
+            },
+        }
+    }
+
+    /// Язык по названию или расширению: `rust`, `py`, `yml`, `текст`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_lowercase();
+        if matches!(name.as_str(), "text" | "txt" | "plain" | "текст") {
+            return Some(Language::PlainText);
+        }
+        Self::ALL
+            .into_iter()
+            .find(|language| language.label().to_lowercase() == name)
+            .or_else(|| {
+                Some(Self::from_path(Path::new(&format!("file.{name}"))))
+                    .filter(|&language| language != Language::PlainText)
+            })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TokenKind {
+    Keyword,
+    Type,
+    Function,
+    String,
+    Number,
+    Comment,
+    /// `true`, `null`, `None` и т. п.
+    Literal,
+    /// Ключи в JSON, TOML, YAML.
+    Key,
+    Heading,
+    /// Атрибуты, декораторы, маркеры списков, ограждения блоков кода.
+    Meta,
+    Variable,
+    Emphasis,
+}
+
+impl TokenKind {
+    /// Цвета тем One Dark и One Light.
+    pub fn color(self, dark_mode: bool) -> Color32 {
+        let (dark, light) = match self {
+            TokenKind::Keyword | TokenKind::Emphasis => ([0xC6, 0x78, 0xDD], [0xA6, 0x26, 0xA4]),
+            TokenKind::Type => ([0xE5, 0xC0, 0x7B], [0xC1, 0x84, 0x01]),
+            TokenKind::Function => ([0x61, 0xAF, 0xEF], [0x40, 0x78, 0xF2]),
+            TokenKind::String => ([0x98, 0xC3, 0x79], [0x50, 0xA1, 0x4F]),
+            TokenKind::Number | TokenKind::Literal => ([0xD1, 0x9A, 0x66], [0x98, 0x68, 0x01]),
+            TokenKind::Comment => ([0x7F, 0x84, 0x8E], [0xA0, 0xA1, 0xA7]),
+            TokenKind::Key | TokenKind::Variable | TokenKind::Heading => {
+                ([0xE0, 0x6C, 0x75], [0xE4, 0x56, 0x49])
+            }
+            TokenKind::Meta => ([0x56, 0xB6, 0xC2], [0x01, 0x84, 0xBC]),
+        };
+        let [r, g, b] = if dark_mode { dark } else { light };
+        Color32::from_rgb(r, g, b)
+    }
+}
+
+/// Внутри чего начинается строка.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum State {
+    #[default]
+    Normal,
+    /// Блочный комментарий и глубина вложенности (в Rust они вкладываются).
+    BlockComment(u8),
+    /// Строка в кавычках `quote`, не закрытая на прошлой строке.
+    String(char),
+    /// Python `"""` / `'''` и многострочные строки TOML.
+    TripleString(char),
+    /// Сырая строка Rust `r#"..."#` с таким числом решёток.
+    RawString(u8),
+    /// Блок кода Markdown между ```.
+    CodeBlock,
+}
+
+/// Раскрашенный кусок текста, в символах.
+pub type Span = (Range<usize>, TokenKind);
+
+/// Раскраска строк с прошлых кадров. Строка лексится заново, только если
+/// изменился её текст или состояние, в котором она начинается: ниже правки
+/// состояние сразу сходится с прежним, и остальное берётся из кэша.
+#[derive(Default)]
+pub struct SpanCache {
+    language: Language,
+    /// По состоянию в начале строки и хэшу её текста.
+    lines: HashMap<(State, u64), CachedLine>,
+}
+
+struct CachedLine {
+    /// В символах от начала строки.
+    spans: Vec<Span>,
+    end: State,
+}
+
+impl SpanCache {
+    /// Раскрашивает подряд идущие строки документа; первая начинается в
+    /// состоянии `state`. Диапазоны — в символах от начала `text`.
+    /// В кэше остаются только строки этого текста.
+    pub fn highlight(&mut self, language: Language, text: &str, mut state: State) -> Vec<Span> {
+        let mut spans = Vec::new();
+        if language != self.language {
+            self.language = language;
+            self.lines.clear();
+        }
+        if language == Language::PlainText {
+            return spans;
+        }
+        let mut used: HashMap<(State, u64), CachedLine> = HashMap::new();
+        let mut offset = 0;
+        let mut chars = Vec::new();
+        for line in text.split_inclusive('\n') {
+            let key = (state, fnv64(line.as_bytes()));
+            let cached = used.entry(key).or_insert_with(|| {
+                self.lines.remove(&key).unwrap_or_else(|| {
+                    chars.clear();
+                    chars.extend(line.chars().filter(|&c| c != '\n'));
+                    let mut spans = Vec::new();
+                    let end = lex_line(language, &chars, state, &mut spans);
+                    CachedLine { spans, end }
+                })
+            });
+            spans.extend(
+                cached
+                    .spans
+                    .iter()
+                    .map(|(range, kind)| (range.start + offset..range.end + offset, *kind)),
+            );
+            state = cached.end;
+            offset += line.chars().count();
+        }
+        self.lines = used;
+        spans
+    }
+}
+
+/// Состояния лексера в начале строк документа. Считаются лениво, до
+/// строки, которую попросили, и сбрасываются от места правки.
+#[derive(Default)]
+pub struct Highlighter {
+    language: Language,
+    states: Vec<State>,
+}
+
+impl Highlighter {
+    pub fn new(language: Language) -> Self {
+        Self {
+            language,
+            states: Vec::new(),
+        }
+    }
+
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    pub fn set_language(&mut self, language: Language) {
+        self.language = language;
+        self.states.clear();
+    }
+
+    /// Строка `line` изменилась: состояния после неё больше не верны.
+    pub fn invalidate_from(&mut self, line: usize) {
+        self.states.truncate(line + 1);
+    }
+
+    /// Состояние в начале строки `line`.
+    pub fn state_at(&mut self, text: &Rope, line: usize) -> State {
+        if self.language == Language::PlainText {
+            return State::Normal;
+        }
+        let line = line.min(text.len_lines() - 1);
+        if self.states.is_empty() {
+            self.states.push(State::Normal);
+        }
+        let mut chars = Vec::new();
+        let mut spans = Vec::new();
+        while self.states.len() <= line {
+            let i = self.states.len() - 1;
+            chars.clear();
+            chars.extend(text.line(i).chars().filter(|&c| c != '\n'));
+            spans.clear();
+            let next = lex_line(self.language, &chars, self.states[i], &mut spans);
+            self.states.push(next);
+        }
+        self.states[line]
+    }
+}
+
+fn lex_line(language: Language, chars: &[char], state: State, spans: &mut Vec<Span>) -> State {
+    let mut lexer = Lexer {
+        language,
+        chars,
+        spans,
+        i: 0,
+        state,
+    };
+    match language {
+        Language::PlainText => {}
+        Language::Rust => lexer.rust(),
+        Language::Python => lexer.python(),
+        Language::Json => lexer.json(),
+        Language::Toml => lexer.toml(),
+        Language::Yaml => lexer.yaml(),
+        Language::Markdown => lexer.markdown(),
+        Language::Sql => lexer.sql(),
+        Language::Shell => lexer.shell(),
+    }
+    lexer.state
+}
+
+const RUST_KEYWORDS: &[&str] = &[
+    "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+    "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub", "ref",
+    "return", "self", "Self", "static", "struct", "super", "trait", "type", "unsafe", "use",
+    "where", "while", "yield",
+];
+const RUST_TYPES: &[&str] = &[
+    "bool", "char", "str", "u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64",
+    "i128", "isize", "f32", "f64",
+];
+const PYTHON_KEYWORDS: &[&str] = &[
+    "and", "as", "assert", "async", "await", "break", "class", "continue", "def", "del", "elif",
+    "else", "except", "finally", "for", "from", "global", "if", "import", "in", "is", "lambda",
+    "match", "case", "nonlocal", "not", "or", "pass", "raise", "return", "try", "while", "with",
+    "yield",
+];
+const SQL_KEYWORDS: &[&str] = &[
+    "ADD",
+    "ALL",
+    "ALTER",
+    "AND",
+    "AS",
+    "ASC",
+    "BEGIN",
+    "BETWEEN",
+    "BY",
+    "CASE",
+    "COMMIT",
+    "CONSTRAINT",
+    "CREATE",
+    "DEFAULT",
+    "DELETE",
+    "DESC",
+    "DISTINCT",
+    "DROP",
+    "ELSE",
+    "END",
+    "EXISTS",
+    "FOREIGN",
+    "FROM",
+    "FULL",
+    "GROUP",
+    "HAVING",
+    "IF",
+    "IN",
+    "INDEX",
+    "INNER",
+    "INSERT",
+    "INTO",
+    "IS",
+    "JOIN",
+    "KEY",
+    "LEFT",
+    "LIKE",
+    "LIMIT",
+    "NOT",
+    "OFFSET",
+    "ON",
+    "OR",
+    "ORDER",
+    "OUTER",
+    "PRIMARY",
+    "REFERENCES",
+    "RETURNING",
+    "RIGHT",
+    "ROLLBACK",
+    "SELECT",
+    "SET",
+    "TABLE",
+    "THEN",
+    "TRANSACTION",
+    "UNION",
+    "UNIQUE",
+    "UPDATE",
+    "VALUES",
+    "VIEW",
+    "WHEN",
+    "WHERE",
+    "WITH",
+];
+const SQL_TYPES: &[&str] = &[
+    "BIGINT",
+    "BLOB",
+    "BOOLEAN",
+    "CHAR",
+    "DATE",
+    "DECIMAL",
+    "DOUBLE",
+    "FLOAT",
+    "INT",
+    "INTEGER",
+    "NUMERIC",
+    "REAL",
+    "SERIAL",
+    "SMALLINT",
+    "TEXT",
+    "TIME",
+    "TIMESTAMP",
+    "VARCHAR",
+];
+const SHELL_KEYWORDS: &[&str] = &[
+    "case", "do", "done", "elif", "else", "esac", "export", "fi", "for", "function", "if", "in",
+    "local", "readonly", "return", "select", "then", "until", "while",
+];
+
+struct Lexer<'a> {
+    language: Language,
+    chars: &'a [char],
+    spans: &'a mut Vec<Span>,
+    i: usize,
+    state: State,
+}
+
+impl Lexer<'_> {
+    fn len(&self) -> usize {
+        self.chars.len()
+    }
+
+    fn at(&self, i: usize) -> Option<char> {
+        self.chars.get(i).copied()
+    }
+
+    fn starts_with(&self, i: usize, pattern: &str) -> bool {
+        (i..)
+            .zip(pattern.chars())
+            .all(|(j, c)| self.at(j) == Some(c))
+    }
+
+    fn push(&mut self, range: Range<usize>, kind: TokenKind) {
+        if !range.is_empty() {
+            self.spans.push((range, kind));
+        }
+    }
+
+    /// Всё от `i` до конца строки — одним цветом.
+    fn rest(&mut self, kind: TokenKind) {
+        self.push(self.i..self.len(), kind);
+        self.i = self.len();
+    }
+
+    /// Строка до сих пор состоит из одних пробелов.
+    fn at_line_start(&self) -> bool {
+        self.chars[..self.i].iter().all(|c| c.is_whitespace())
+    }
+
+    fn word_end(&self, mut i: usize) -> usize {
+        while self.at(i).is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            i += 1;
+        }
+        i
+    }
+
+    fn word(&self, end: usize) -> String {
+        self.chars[self.i..end].iter().collect()
+    }
+
+    fn number(&mut self) {
+        let mut end = self.i + 1;
+        while let Some(c) = self.at(end) {
+            let fraction = c == '.' && self.at(end + 1).is_some_and(|c| c.is_ascii_digit());
+            if !(c.is_alphanumeric() || c == '_' || fraction) {
+                break;
+            }
+            end += 1;
+        }
+        self.push(self.i..end, TokenKind::Number);
+        self.i = end;
+    }
+
+    fn next_non_space(&self, mut i: usize) -> Option<char> {
+        while self.at(i).is_some_and(char::is_whitespace) {
+            i += 1;
+        }
+        self.at(i)
+    }
+
+    /// Экранирует ли `\` кавычку в строке.
+    fn escapes(&self, quote: char) -> bool {
+        match self.language {
+            Language::Sql => false,
+            Language::Shell | Language::Toml | Language::Yaml => quote == '"',
+            _ => true,
+        }
+    }
+
+    /// Начинает строку или комментарий, открытый на `len` символах с `i`.
+    fn open(&mut self, len: usize, state: State) {
+        let start = self.i;
+        self.i += len;
+        self.state = state;
+        self.resume_from(start);
+    }
+
+    /// Дочитывает то, что открыто на прошлых строках.
+    fn resume(&mut self) {
+        self.resume_from(self.i);
+    }
+
+    /// Дочитывает открытое с `start` до закрытия или конца строки.
+    fn resume_from(&mut self, start: usize) {
+        let n = self.len();
+        match self.state {
+            State::Normal | State::CodeBlock => return,
+            State::BlockComment(mut depth) => {
+                let nested = self.language == Language::Rust;
+                while self.i < n && depth > 0 {
+                    if self.starts_with(self.i, "*/") {
+                        depth -= 1;
+                        self.i += 2;
+                    } else if nested && self.starts_with(self.i, "/*") {
+                        depth += 1;
+                        self.i += 2;
+                    } else {
+                        self.i += 1;
+                    }
+                }
+                self.state = if depth == 0 {
+                    State::Normal
+                } else {
+                    State::BlockComment(depth)
+                };
+                self.push(start..self.i, TokenKind::Comment);
+                return;
+            }
+            State::String(quote) => {
+                let escapes = self.escapes(quote);
+                while self.i < n {
+                    let c = self.chars[self.i];
+                    self.i += if escapes && c == '\\' { 2 } else { 1 };
+                    if c == quote {
+                        self.state = State::Normal;
+                        break;
+                    }
+                }
+            }
+            State::TripleString(quote) => {
+                let closing: String = [quote; 3].iter().collect();
+                while self.i < n {
+                    if self.starts_with(self.i, &closing) {
+                        self.i += 3;
+                        self.state = State::Normal;
+                        break;
+                    }
+                    self.i += if self.chars[self.i] == '\\' { 2 } else { 1 };
+                }
+            }
+            State::RawString(hashes) => {
+                let closing = format!("\"{}", "#".repeat(hashes as usize));
+                while self.i < n {
+                    if self.starts_with(self.i, &closing) {
+                        self.i += closing.len();
+                        self.state = State::Normal;
+                        break;
+                    }
+                    self.i += 1;
+                }
+            }
+        }
+        self.i = self.i.min(n);
+        self.push(start..self.i, TokenKind::String);
+    }
+
+    /// Строка в кавычках, которая обязана закончиться на этой строке.
+    fn inline_string(&mut self, quote: char) -> usize {
+        let start = self.i;
+        let escapes = self.escapes(quote);
+        let mut end = start + 1;
+        while let Some(c) = self.at(end) {
+            end += if escapes && c == '\\' { 2 } else { 1 };
+            if c == quote {
+                break;
+            }
+        }
+        end.min(self.len())
+    }
+
+    fn rust(&mut self) {
+        self.resume();
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            if self.starts_with(self.i, "//") {
+                self.rest(TokenKind::Comment);
+            } else if self.starts_with(self.i, "/*") {
+                self.open(2, State::BlockComment(1));
+            } else if c == '"' {
+                self.open(1, State::String('"'));
+            } else if let Some((len, hashes)) = self.raw_string_start() {
+                self.open(len, State::RawString(hashes));
+            } else if c == 'b' && self.at(self.i + 1) == Some('"') {
+                self.open(2, State::String('"'));
+            } else if c == '\'' {
+                self.rust_quote();
+            } else if c == '#'
+                && (self.at(self.i + 1) == Some('[') || self.starts_with(self.i + 1, "!["))
+            {
+                let end = (self.i..self.len())
+                    .find(|&j| self.chars[j] == ']')
+                    .map_or(self.len(), |j| j + 1);
+                self.push(self.i..end, TokenKind::Meta);
+                self.i = end;
+            } else if c.is_ascii_digit() {
+                self.number();
+            } else if c.is_alphabetic() || c == '_' {
+                let end = self.word_end(self.i);
+                let word = self.word(end);
+                let kind = if RUST_KEYWORDS.contains(&word.as_str()) {
+                    Some(TokenKind::Keyword)
+                } else if word == "true" || word == "false" {
+                    Some(TokenKind::Literal)
+                } else if RUST_TYPES.contains(&word.as_str()) || c.is_uppercase() {
+                    Some(TokenKind::Type)
+                } else if matches!(self.at(end), Some('(' | '!')) {
+                    Some(TokenKind::Function)
+                } else {
+                    None
+                };
+                // Макрос — вместе с `!`.
+                let end = if self.at(end) == Some('!') && kind == Some(TokenKind::Function) {
+                    end + 1
+                } else {
+                    end
+                };
+                if let Some(kind) = kind {
+                    self.push(self.i..end, kind);
+                }
+                self.i = end;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    /// `r"`, `r#"`, `br##"` — длина начала и число решёток.
+    fn raw_string_start(&self) -> Option<(usize, u8)> {
+        let mut j = self.i;
+        if self.at(j) == Some('b') {
+            j += 1;
+        }
+        if self.at(j) != Some('r') {
+            return None;
+        }
+        // `r` не должна быть концом слова: `bar"` — не сырая строка.
+        if self.i > 0
+            && self
+                .at(self.i - 1)
+                .is_some_and(|c| c.is_alphanumeric() || c == '_')
+        {
+            return None;
+        }
+        j += 1;
+        let mut hashes = 0;
+        while self.at(j) == Some('#') {
+            hashes += 1;
+            j += 1;
+        }
+        (self.at(j) == Some('"')).then_some((j + 1 - self.i, hashes))
+    }
+
+    /// Символьный литерал `'a'`, `'\n'` или время жизни `'a`.
+    fn rust_quote(&mut self) {
+        let start = self.i;
+        let end = if self.at(start + 1) == Some('\\') {
+            (start + 2..self.len())
+                .find(|&j| self.chars[j] == '\'')
+                .map(|j| j + 1)
+        } else if self.at(start + 2) == Some('\'') {
+            Some(start + 3)
+        } else {
+            None
+        };
+        match end {
+            Some(end) => {
+                self.push(start..end, TokenKind::String);
+                self.i = end;
+            }
+            None => {
+                let end = self.word_end(start + 1);
+                self.push(start..end, TokenKind::Type);
+                self.i = end.max(start + 1);
+            }
+        }
+    }
+
+    fn python(&mut self) {
+        self.resume();
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            if c == '#' {
+                self.rest(TokenKind::Comment);
+            } else if c == '"' || c == '\'' {
+                self.python_string(0);
+            } else if c == '@' && self.at_line_start() {
+                let mut end = self.i + 1;
+                while self
+                    .at(end)
+                    .is_some_and(|c| c.is_alphanumeric() || c == '_' || c == '.')
+                {
+                    end += 1;
+                }
+                self.push(self.i..end, TokenKind::Meta);
+                self.i = end;
+            } else if c.is_ascii_digit() {
+                self.number();
+            } else if c.is_alphabetic() || c == '_' {
+                let end = self.word_end(self.i);
+                let word = self.word(end);
+                let prefix = word.len() <= 2 && word.chars().all(|c| "rbufRBUF".contains(c));
+                if prefix && matches!(self.at(end), Some('"' | '\'')) {
+                    self.python_string(end - self.i);
+                    continue;
+                }
+                let kind = if PYTHON_KEYWORDS.contains(&word.as_str()) {
+                    Some(TokenKind::Keyword)
+                } else if matches!(word.as_str(), "True" | "False" | "None") {
+                    Some(TokenKind::Literal)
+                } else if word == "self" || word == "cls" {
+                    Some(TokenKind::Variable)
+                } else if self.at(end) == Some('(') {
+                    Some(TokenKind::Function)
+                } else if c.is_uppercase() {
+                    Some(TokenKind::Type)
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    self.push(self.i..end, kind);
+                }
+                self.i = end;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    /// Строка Python с префиксом длины `prefix` (`r`, `f`, `rb`...).
+    fn python_string(&mut self, prefix: usize) {
+        let quote = self.chars[self.i + prefix];
+        let triple: String = [quote; 3].iter().collect();
+        if self.starts_with(self.i + prefix, &triple) {
+            self.open(prefix + 3, State::TripleString(quote));
+        } else {
+            self.i += prefix;
+            let end = self.inline_string(quote);
+            self.push(self.i - prefix..end, TokenKind::String);
+            self.i = end;
+        }
+    }
+
+    fn json(&mut self) {
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            if self.starts_with(self.i, "//") {
+                self.rest(TokenKind::Comment);
+            } else if c == '"' {
+                let end = self.inline_string('"');
+                let kind = if self.next_non_space(end) == Some(':') {
+                    TokenKind::Key
+                } else {
+                    TokenKind::String
+                };
+                self.push(self.i..end, kind);
+                self.i = end;
+            } else if c.is_ascii_digit()
+                || (c == '-' && self.at(self.i + 1).is_some_and(|c| c.is_ascii_digit()))
+            {
+                self.number();
+            } else if c.is_alphabetic() {
+                let end = self.word_end(self.i);
+                if matches!(self.word(end).as_str(), "true" | "false" | "null") {
+                    self.push(self.i..end, TokenKind::Literal);
+                }
+                self.i = end;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    fn toml(&mut self) {
+        self.resume();
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            if c == '#' {
+                self.rest(TokenKind::Comment);
+            } else if c == '[' && self.at_line_start() {
+                let end = (self.i..self.len())
+                    .rfind(|&j| self.chars[j] == ']')
+                    .map_or(self.len(), |j| j + 1);
+                self.push(self.i..end, TokenKind::Heading);
+                self.i = end;
+            } else if self.at_line_start()
+                && let Some(eq) = self.toml_key_end()
+            {
+                self.push(self.i..eq, TokenKind::Key);
+                self.i = eq;
+            } else if c == '"' || c == '\'' {
+                let triple: String = [c; 3].iter().collect();
+                if self.starts_with(self.i, &triple) {
+                    self.open(3, State::TripleString(c));
+                } else {
+                    let end = self.inline_string(c);
+                    self.push(self.i..end, TokenKind::String);
+                    self.i = end;
+                }
+            } else if c.is_ascii_digit()
+                || (matches!(c, '+' | '-')
+                    && self.at(self.i + 1).is_some_and(|c| c.is_ascii_digit()))
+            {
+                // Числа, даты и время: 1_000, 1979-05-27T07:32:00Z.
+                let mut end = self.i + 1;
+                while self
+                    .at(end)
+                    .is_some_and(|c| c.is_alphanumeric() || "_.:-+".contains(c))
+                {
+                    end += 1;
+                }
+                self.push(self.i..end, TokenKind::Number);
+                self.i = end;
+            } else if c.is_alphabetic() {
+                let end = self.word_end(self.i);
+                if matches!(self.word(end).as_str(), "true" | "false" | "inf" | "nan") {
+                    self.push(self.i..end, TokenKind::Literal);
+                }
+                self.i = end;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    /// Конец ключа `a.b-c = ...` (без пробелов перед `=`).
+    fn toml_key_end(&self) -> Option<usize> {
+        let eq = (self.i..self.len()).find(|&j| self.chars[j] == '=')?;
+        let key = &self.chars[self.i..eq];
+        let bare = key
+            .iter()
+            .all(|&c| c.is_alphanumeric() || "_-. \"'".contains(c));
+        let end = self.i + key.iter().rposition(|c| !c.is_whitespace())? + 1;
+        bare.then_some(end)
+    }
+
+    fn yaml(&mut self) {
+        if self.starts_with(0, "---") || self.starts_with(0, "...") {
+            self.rest(TokenKind::Meta);
+            return;
+        }
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            let after_space = self.i == 0 || self.chars[self.i - 1].is_whitespace();
+            if c == '#' && after_space {
+                self.rest(TokenKind::Comment);
+            } else if c == '-'
+                && self.at_line_start()
+                && self.at(self.i + 1).is_none_or(char::is_whitespace)
+            {
+                self.push(self.i..self.i + 1, TokenKind::Meta);
+                self.i += 1;
+            } else if !c.is_whitespace()
+                && self.line_start_after_dash()
+                && let Some(end) = self.yaml_key_end()
+            {
+                self.push(self.i..end, TokenKind::Key);
+                self.i = end;
+            } else if c == '"' || c == '\'' {
+                let end = self.inline_string(c);
+                self.push(self.i..end, TokenKind::String);
+                self.i = end;
+            } else if matches!(c, '&' | '*')
+                && self.at(self.i + 1).is_some_and(char::is_alphanumeric)
+            {
+                let end = self.word_end(self.i + 1);
+                self.push(self.i..end, TokenKind::Variable);
+                self.i = end;
+            } else if c == '!' {
+                let end = (self.i..self.len())
+                    .find(|&j| self.chars[j].is_whitespace())
+                    .unwrap_or(self.len());
+                self.push(self.i..end, TokenKind::Meta);
+                self.i = end;
+            } else if c.is_ascii_digit() && after_space {
+                self.number();
+            } else if c.is_alphabetic() || c == '~' {
+                let end = self.word_end(self.i).max(self.i + 1);
+                let word = self.word(end).to_lowercase();
+                let scalar_end = self
+                    .next_non_space(end)
+                    .is_none_or(|c| c == '#' || c == ',');
+                if scalar_end
+                    && matches!(
+                        word.as_str(),
+                        "true" | "false" | "null" | "yes" | "no" | "on" | "off" | "~"
+                    )
+                {
+                    self.push(self.i..end, TokenKind::Literal);
+                }
+                self.i = end;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    /// До `i` только отступ и маркеры списка `- `.
+    fn line_start_after_dash(&self) -> bool {
+        self.chars[..self.i]
+            .iter()
+            .all(|&c| c.is_whitespace() || c == '-')
+    }
+
+    /// Конец ключа `key:` — двоеточие с пробелом или в конце строки.
+    fn yaml_key_end(&self) -> Option<usize> {
+        let mut j = self.i;
+        while j < self.len() {
+            match self.chars[j] {
+                ':' if self.at(j + 1).is_none_or(char::is_whitespace) => return Some(j),
+                '#' | '"' | '\'' | '{' | '[' if j == self.i => return None,
+                _ => j += 1,
+            }
+        }
+        None
+    }
+
+    fn markdown(&mut self) {
+        let trimmed: String = self
+            .chars
+            .iter()
+            .collect::<String>()
+            .trim_start()
+            .to_string();
+        let fence = trimmed.starts_with("```") || trimmed.starts_with("~~~");
+        if self.state == State::CodeBlock {
+            if fence {
+                self.state = State::Normal;
+                self.rest(TokenKind::Meta);
+            } else {
+                self.rest(TokenKind::String);
+            }
+            return;
+        }
+        if fence {
+            self.state = State::CodeBlock;
+            self.rest(TokenKind::Meta);
+            return;
+        }
+        let hashes = trimmed.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&hashes) && trimmed[hashes..].starts_with(' ') {
+            self.rest(TokenKind::Heading);
+            return;
+        }
+        if trimmed.starts_with('>') {
+            self.rest(TokenKind::Comment);
+            return;
+        }
+
+        // Маркер списка: "- ", "* ", "+ ", "1. ".
+        self.i = self.chars.iter().take_while(|c| c.is_whitespace()).count();
+        let digits = self.chars[self.i..]
+            .iter()
+            .take_while(|c| c.is_ascii_digit())
+            .count();
+        let marker = match self.at(self.i) {
+            Some('-' | '*' | '+') => 1,
+            _ if digits > 0 && matches!(self.at(self.i + digits), Some('.' | ')')) => digits + 1,
+            _ => 0,
+        };
+        if marker > 0 && self.at(self.i + marker) == Some(' ') {
+            self.push(self.i..self.i + marker, TokenKind::Meta);
+            self.i += marker;
+        }
+
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            let closing = |lexer: &Self, from: usize, pattern: &str| {
+                (from..lexer.len()).find(|&j| lexer.starts_with(j, pattern))
+            };
+            if c == '`' {
+                let end = closing(self, self.i + 1, "`").map_or(self.len(), |j| j + 1);
+                self.push(self.i..end, TokenKind::String);
+                self.i = end;
+            } else if (self.starts_with(self.i, "**") || self.starts_with(self.i, "__"))
+                && let Some(j) = closing(
+                    self,
+                    self.i + 2,
+                    &self.chars[self.i..self.i + 2].iter().collect::<String>(),
+                )
+            {
+                self.push(self.i..j + 2, TokenKind::Emphasis);
+                self.i = j + 2;
+            } else if matches!(c, '*' | '_')
+                && self.at(self.i + 1).is_some_and(|c| !c.is_whitespace())
+                && let Some(j) = closing(self, self.i + 1, &c.to_string())
+            {
+                self.push(self.i..j + 1, TokenKind::Emphasis);
+                self.i = j + 1;
+            } else if c == '['
+                && let Some(close) = closing(self, self.i + 1, "](")
+                && let Some(end) = closing(self, close + 2, ")")
+            {
+                self.push(self.i..close + 1, TokenKind::Function);
+                self.push(close + 1..end + 1, TokenKind::String);
+                self.i = end + 1;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    fn sql(&mut self) {
+        self.resume();
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            if self.starts_with(self.i, "--") {
+                self.rest(TokenKind::Comment);
+            } else if self.starts_with(self.i, "/*") {
+                self.open(2, State::BlockComment(1));
+            } else if c == '\'' {
+                self.open(1, State::String('\''));
+            } else if c == '"' || c == '`' {
+                let end = self.inline_string(c);
+                self.push(self.i..end, TokenKind::Key);
+                self.i = end;
+            } else if c.is_ascii_digit() {
+                self.number();
+            } else if c.is_alphabetic() || c == '_' {
+                let end = self.word_end(self.i);
+                let word = self.word(end).to_uppercase();
+                let kind = if SQL_KEYWORDS.contains(&word.as_str()) {
+                    Some(TokenKind::Keyword)
+                } else if SQL_TYPES.contains(&word.as_str()) {
+                    Some(TokenKind::Type)
+                } else if matches!(word.as_str(), "NULL" | "TRUE" | "FALSE") {
+                    Some(TokenKind::Literal)
+                } else if self.at(end) == Some('(') {
+                    Some(TokenKind::Function)
+                } else {
+                    None
+                };
+                if let Some(kind) = kind {
+                    self.push(self.i..end, kind);
+                }
+                self.i = end;
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+
+    fn shell(&mut self) {
+        self.resume();
+        while self.i < self.len() {
+            let c = self.chars[self.i];
+            let after_space = self.i == 0 || self.chars[self.i - 1].is_whitespace();
+            if c == '#' && after_space {
+                self.rest(TokenKind::Comment);
+            } else if c == '"' || c == '\'' {
+                self.open(1, State::String(c));
+            } else if c == '\\' {
+                self.i += 2;
+            } else if c == '$' {
+                let end = match self.at(self.i + 1) {
+                    Some('{') => (self.i..self.len())
+                        .find(|&j| self.chars[j] == '}')
+                        .map_or(self.len(), |j| j + 1),
+                    Some(c) if c.is_alphabetic() || c == '_' => self.word_end(self.i + 1),
+                    Some(c) if c.is_ascii_digit() || "?@#*$!-".contains(c) => self.i + 2,
+                    _ => self.i + 1,
+                };
+                self.push(self.i..end, TokenKind::Variable);
+                self.i = end;
+            } else if (c.is_alphabetic() || c == '_') && after_space {
+                let end = self.word_end(self.i);
+                let word = self.word(end);
+                if SHELL_KEYWORDS.contains(&word.as_str()) {
+                    self.push(self.i..end, TokenKind::Keyword);
+                } else if self.starts_with(end, "()") {
+                    self.push(self.i..end, TokenKind::Function);
+                }
+                self.i = end;
+            } else if c.is_ascii_digit() && after_space {
+                self.number();
+            } else {
+                self.i += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(language: Language, text: &str) -> Vec<(String, TokenKind)> {
+        let chars: Vec<char> = text.chars().collect();
+        SpanCache::default()
+            .highlight(language, text, State::Normal)
+            .into_iter()
+            .map(|(range, kind)| (chars[range].iter().collect(), kind))
+            .collect()
+    }
+
+    fn has(spans: &[(String, TokenKind)], text: &str, kind: TokenKind) -> bool {
+        spans.iter().any(|(t, k)| t == text && *k == kind)
+    }
+
+    #[test]
+    fn highlights_rust() {
+        let spans = kinds(
+            Language::Rust,
+            "#[derive(Debug)]\npub fn main() {\n    let s = r#\"сырая\"#; // комм\n    println!(\"{}\", 'a');\n}\n",
+        );
+        assert!(has(&spans, "#[derive(Debug)]", TokenKind::Meta));
+        assert!(has(&spans, "fn", TokenKind::Keyword));
+        assert!(has(&spans, "main", TokenKind::Function));
+        assert!(has(&spans, "r#\"сырая\"#", TokenKind::String));
+        assert!(has(&spans, "// комм", TokenKind::Comment));
+        assert!(has(&spans, "println!", TokenKind::Function));
+        assert!(has(&spans, "'a'", TokenKind::String));
+    }
+
+    #[test]
+    fn carries_state_across_lines() {
+        let python = kinds(Language::Python, "x = \"\"\"начало\nконец\"\"\" # да\n");
+        assert!(has(&python, "\"\"\"начало", TokenKind::String));
+        assert!(has(&python, "конец\"\"\"", TokenKind::String));
+        assert!(has(&python, "# да", TokenKind::Comment));
+
+        let markdown = kinds(
+            Language::Markdown,
+            "# Заголовок\n```\nfn x\n```\n**жирный**",
+        );
+        assert!(has(&markdown, "# Заголовок", TokenKind::Heading));
+        assert!(has(&markdown, "fn x", TokenKind::String));
+        assert!(has(&markdown, "**жирный**", TokenKind::Emphasis));
+    }
+
+    #[test]
+    fn highlights_config_formats() {
+        let json = kinds(
+            Language::Json,
+            r#"{"ключ": "значение", "n": -1.5, "ok": true}"#,
+        );
+        assert!(has(&json, "\"ключ\"", TokenKind::Key));
+        assert!(has(&json, "\"значение\"", TokenKind::String));
+        assert!(has(&json, "-1.5", TokenKind::Number));
+
+        let toml = kinds(Language::Toml, "[package]\nname = \"редактор\" # имя\n");
+        assert!(has(&toml, "[package]", TokenKind::Heading));
+        assert!(has(&toml, "name", TokenKind::Key));
+
+        let yaml = kinds(Language::Yaml, "- name: тест\n  enabled: yes\n");
+        assert!(has(&yaml, "name", TokenKind::Key));
+        assert!(has(&yaml, "yes", TokenKind::Literal));
+
+        let sql = kinds(Language::Sql, "select count(*) from t -- всё");
+        assert!(has(&sql, "select", TokenKind::Keyword));
+        assert!(has(&sql, "count", TokenKind::Function));
+
+        let shell = kinds(
+            Language::Shell,
+            "if [ -n \"$HOME\" ]; then echo ${PATH}; fi",
+        );
+        assert!(has(&shell, "if", TokenKind::Keyword));
+        assert!(has(&shell, "${PATH}", TokenKind::Variable));
+    }
+
+    #[test]
+    fn cached_spans_follow_edits() {
+        let mut cache = SpanCache::default();
+        let mut check = |text: &str| {
+            let cached = cache.highlight(Language::Rust, text, State::Normal);
+            let fresh = SpanCache::default().highlight(Language::Rust, text, State::Normal);
+            assert_eq!(cached, fresh);
+        };
+        check("fn a() {}\nlet b = 1;\n}\n}\n");
+        check("// fn a() {}\nlet b = 1;\n}\n}\n");
+        // Открытый комментарий меняет состояние строк ниже, хоть их текст тот же.
+        check("/* fn a() {}\nlet b = 1;\n}\n}\n");
+        check("fn a() {}\nlet b = 1;\n}\n}\n");
+    }
+
+    #[test]
+    fn recomputes_states_after_edit() {
+        let mut text = Rope::from_str("a\nb\nc\n");
+        let mut highlighter = Highlighter::new(Language::Rust);
+        assert_eq!(highlighter.state_at(&text, 2), State::Normal);
+
+        text.insert(0, "/* ");
+        highlighter.invalidate_from(0);
+        assert_eq!(highlighter.state_at(&text, 2), State::BlockComment(1));
+        assert_eq!(Language::from_path(Path::new("Cargo.toml")), Language::Toml);
+        assert_eq!(Language::from_name("yml"), Some(Language::Yaml));
+    }
+}
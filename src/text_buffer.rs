@@ -0,0 +1,67 @@
+use std::any::TypeId;
+use std::cell::Cell;
+use std::ops::Range;
+
+use eframe::egui;
+
+use crate::document::Document;
+use crate::line_ending;
+
+/// Адаптер [`Document`] к [`egui::TextBuffer`].
+///
+/// Виджет видит только окно просмотра документа (см. [`Document::prepare_view`]),
+/// а все правки уходят прямо в rope, без копии всего текста на каждом кадре.
+/// Индексы символов виджета отсчитываются от начала окна.
+pub struct DocumentBuffer<'a> {
+    doc: &'a mut Document,
+    /// Ревизия документа после последней правки — её читает layouter,
+    /// пока буфер занят виджетом.
+    revision: &'a Cell<u64>,
+}
+
+impl<'a> DocumentBuffer<'a> {
+    pub fn new(doc: &'a mut Document, revision: &'a Cell<u64>) -> Self {
+        revision.set(doc.revision());
+        Self { doc, revision }
+    }
+
+    fn to_document(&self, char_range: Range<usize>) -> Range<usize> {
+        let start = self.doc.view_range().start;
+        start + char_range.start..start + char_range.end
+    }
+}
+
+impl egui::TextBuffer for DocumentBuffer<'_> {
+    fn is_mutable(&self) -> bool {
+        true
+    }
+
+    fn as_str(&self) -> &str {
+        self.doc.view_text()
+    }
+
+    fn insert_text(&mut self, text: &str, char_index: usize) -> usize {
+        // Вставленный из буфера обмена текст может прийти с `\r\n`.
+        let text = line_ending::normalize(text);
+        let range = self.to_document(char_index..char_index);
+        self.doc.insert(range.start, &text);
+        self.revision.set(self.doc.revision());
+        text.chars().count()
+    }
+
+    fn delete_char_range(&mut self, char_range: Range<usize>) {
+        let range = self.to_document(char_range);
+        self.doc.remove(range);
+        self.revision.set(self.doc.revision());
+    }
+
+    fn replace_with(&mut self, text: &str) {
+        let range = self.doc.view_range();
+        self.doc.replace(range, &line_ending::normalize(text));
+        self.revision.set(self.doc.revision());
+    }
+
+    fn type_id(&self) -> TypeId {
+        TypeId::of::<DocumentBuffer<'static>>()
+    }
+}